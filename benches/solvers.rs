@@ -0,0 +1,134 @@
+// Accuracy and time of the force solvers, run with `cargo bench`.
+// The particle counts can be given as arguments: `cargo bench -- 10000 1000000`.
+// Errors are RMS force errors relative to direct summation, over a sample of
+// the particles for large N.
+
+use particle_sim::forces::compute_gravity;
+use particle_sim::initial_conditions::{
+    generate_random_particles, generate_random_particles_around_attractor,
+};
+use particle_sim::quadtree::MultipoleOrder;
+use particle_sim::simulation::{
+    BarnesHutConfig, BarnesHutParallel, FastMultipole, FmmConfig, LinearBarnesHut, ParticleMesh,
+    PmConfig, TreePm, TreePmConfig,
+};
+use particle_sim::{ForceSolver, Particle, Softening};
+use std::time::{Duration, Instant};
+
+const SAMPLE_SIZE: usize = 1000;
+
+fn exact_forces(particles: &[Particle], sample: &[usize]) -> Vec<[f64; 2]> {
+    sample
+        .iter()
+        .map(|&i| {
+            particles.iter().fold([0.0, 0.0], |acc, other| {
+                let f = compute_gravity(&particles[i], other, &Softening::default());
+                [acc[0] + f[0], acc[1] + f[1]]
+            })
+        })
+        .collect()
+}
+
+fn relative_rms_error(forces: &[[f64; 2]], exact: &[[f64; 2]], sample: &[usize]) -> f64 {
+    let mut error = 0.0;
+    let mut norm = 0.0;
+    for (&i, e) in sample.iter().zip(exact.iter()) {
+        error += (forces[i][0] - e[0]).powi(2) + (forces[i][1] - e[1]).powi(2);
+        norm += e[0] * e[0] + e[1] * e[1];
+    }
+    (error / norm).sqrt()
+}
+
+// Best of a few runs, after a warm-up
+fn time(solver: &mut dyn ForceSolver, particles: &[Particle], forces: &mut [[f64; 2]]) -> Duration {
+    solver.compute_forces(particles, forces);
+    (0..3)
+        .map(|_| {
+            let start = Instant::now();
+            solver.compute_forces(particles, forces);
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn solvers() -> Vec<(String, Box<dyn ForceSolver>)> {
+    let bh = |theta: f64, order: MultipoleOrder| {
+        BarnesHutConfig::new(theta)
+            .unwrap()
+            .with_multipole_order(order)
+    };
+    let fmm = |order: usize, theta: f64| FmmConfig::new(order, theta).unwrap();
+    vec![
+        (
+            "Barnes-Hut theta 0.5".to_string(),
+            Box::new(BarnesHutParallel::new(bh(0.5, MultipoleOrder::Monopole))),
+        ),
+        (
+            "Linear Barnes-Hut theta 0.5".to_string(),
+            Box::new(LinearBarnesHut::new(bh(0.5, MultipoleOrder::Monopole))),
+        ),
+        (
+            "Linear Barnes-Hut theta 0.7 quadrupole".to_string(),
+            Box::new(LinearBarnesHut::new(bh(0.7, MultipoleOrder::Quadrupole))),
+        ),
+        (
+            "FMM p 4 theta 0.5".to_string(),
+            Box::new(FastMultipole::new(fmm(4, 0.5))),
+        ),
+        (
+            "FMM p 8 theta 0.5".to_string(),
+            Box::new(FastMultipole::new(fmm(8, 0.5))),
+        ),
+        (
+            "PM 512 CIC isolated".to_string(),
+            Box::new(ParticleMesh::new(PmConfig::isolated(512).unwrap())),
+        ),
+        (
+            "TreePM 256 theta 0.5".to_string(),
+            Box::new(TreePm::new(
+                TreePmConfig::new(PmConfig::isolated(256).unwrap(), 0.5).unwrap(),
+            )),
+        ),
+    ]
+}
+
+fn run(name: &str, particles: &[Particle]) {
+    let step = (particles.len() / SAMPLE_SIZE).max(1);
+    let sample: Vec<usize> = (0..particles.len()).step_by(step).collect();
+    let exact = exact_forces(particles, &sample);
+
+    println!("{} particles, {}", particles.len(), name);
+    println!("{:<40} {:>12} {:>12}", "solver", "time (ms)", "error");
+    let mut forces = vec![[0.0, 0.0]; particles.len()];
+    for (label, mut solver) in solvers() {
+        let elapsed = time(solver.as_mut(), particles, &mut forces);
+        let error = relative_rms_error(&forces, &exact, &sample);
+        println!(
+            "{:<40} {:>12.1} {:>12.2e}",
+            label,
+            elapsed.as_secs_f64() * 1e3,
+            error
+        );
+    }
+    println!();
+}
+
+fn main() {
+    // `cargo bench` passes --bench, which is not a particle count
+    let mut counts: Vec<usize> = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse().ok())
+        .collect();
+    if counts.is_empty() {
+        counts = vec![10_000, 100_000];
+    }
+
+    for &n in counts.iter() {
+        run("uniform", &generate_random_particles(n));
+        run(
+            "around an attractor",
+            &generate_random_particles_around_attractor(n),
+        );
+    }
+}
@@ -4,6 +4,7 @@ pub struct SimState {
     pub positions: Vec<[f32; 2]>,
     pub start_time: Instant,
     pub sim_time: f64,
+    pub dt: f64,
     pub sim_speed: f64,
     pub steps_taken: usize,
 }
@@ -14,6 +15,7 @@ impl Clone for SimState {
             positions: self.positions.clone(),
             start_time: self.start_time,
             sim_time: self.sim_time,
+            dt: self.dt,
             sim_speed: self.sim_speed,
             steps_taken: self.steps_taken,
         }
@@ -26,6 +28,7 @@ impl SimState {
             positions: vec![[0.0, 0.0]; particle_count],
             start_time: Instant::now(),
             sim_time: 0.0,
+            dt: 0.0,
             sim_speed: 0.0,
             steps_taken: 0,
         }
@@ -44,10 +44,11 @@ impl ggez::event::EventHandler for SimulationVisualizer {
 
         // Display simulation stats
         let display_text = format!(
-            "Real Time: {:.1} s\nSim Time: {:.1} s\nSim Speed: {:.2}\nSteps: {}\nFPS: {:.1}",
+            "Real Time: {:.1} s\nSim Time: {:.1} s\nSim Speed: {:.2}\nTime Step: {:.2e}\nSteps: {}\nFPS: {:.1}",
             self.my_state.start_time.elapsed().as_secs_f64(),
             self.my_state.sim_time,
             self.my_state.sim_speed,
+            self.my_state.dt,
             self.my_state.steps_taken,
             ggez::timer::fps(ctx),
         );
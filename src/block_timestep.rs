@@ -0,0 +1,139 @@
+use crate::error::ConfigError;
+use crate::integrator::{ForceEvaluator, Integrator};
+use crate::particle::Particle;
+use rayon::prelude::*;
+
+// Hierarchical (block) time steps: particle i advances with dt / 2^rung[i], the
+// rung being picked from dt_i = eta * sqrt(softening / |a_i|). Each call to
+// `step` covers the full `dt`, split into 2^max_rung ticks. The loop jumps from
+// one tick where some particle's step ends to the next, drifting every particle
+// in between so positions stay synchronised, and only particles reaching the
+// end of their own step get their force recomputed, in a kick-drift-kick
+// fashion. All particles are synchronised again after `dt`.
+#[derive(Debug, Clone, Copy)]
+pub struct BlockTimesteps {
+    max_rung: u32,
+    eta: f64,
+    softening: f64,
+}
+
+impl BlockTimesteps {
+    pub const MAX_RUNG: u32 = 20;
+
+    pub fn new(max_rung: u32, eta: f64, softening: f64) -> Result<Self, ConfigError> {
+        if max_rung > Self::MAX_RUNG || !(eta > 0.0 && softening > 0.0) {
+            return Err(ConfigError::InvalidBlockTimesteps(max_rung, eta, softening));
+        }
+        Ok(BlockTimesteps {
+            max_rung,
+            eta,
+            softening,
+        })
+    }
+
+    pub fn max_rung(&self) -> u32 {
+        self.max_rung
+    }
+
+    pub fn eta(&self) -> f64 {
+        self.eta
+    }
+
+    pub fn softening(&self) -> f64 {
+        self.softening
+    }
+
+    pub fn rung(&self, particle: &Particle, force: &[f64; 2], dt: f64) -> u32 {
+        let acc = (force[0] * force[0] + force[1] * force[1]).sqrt() / particle.mass;
+        if acc == 0.0 {
+            return 0;
+        }
+        let dt_particle = self.eta * (self.softening / acc).sqrt();
+        let rung = (dt / dt_particle).log2().ceil();
+        if rung <= 0.0 {
+            0
+        } else {
+            (rung as u32).min(self.max_rung)
+        }
+    }
+
+    pub fn assign_rungs(&self, particles: &[Particle], forces: &[[f64; 2]], dt: f64) -> Vec<u32> {
+        particles
+            .par_iter()
+            .zip(forces.par_iter())
+            .map(|(p, force)| self.rung(p, force, dt))
+            .collect()
+    }
+
+    // Number of particles on each rung, for diagnostics
+    pub fn rung_counts(&self, rungs: &[u32]) -> Vec<usize> {
+        let mut counts = vec![0; self.max_rung as usize + 1];
+        for &rung in rungs {
+            counts[rung as usize] += 1;
+        }
+        counts
+    }
+}
+
+fn half_kick(
+    particles: &mut [Particle],
+    forces: &[[f64; 2]],
+    indices: &[usize],
+    rungs: &[u32],
+    dt: f64,
+) {
+    for &i in indices {
+        let p = &mut particles[i];
+        let half_dt = 0.5 * dt / (1u64 << rungs[i]) as f64;
+        p.velocity[0] += forces[i][0] * half_dt / p.mass;
+        p.velocity[1] += forces[i][1] * half_dt / p.mass;
+    }
+}
+
+impl Integrator for BlockTimesteps {
+    fn step(&self, particles: &mut [Particle], forces: &mut ForceEvaluator, dt: f64) {
+        let n_ticks: u64 = 1 << self.max_rung;
+        let tick_dt = dt / n_ticks as f64;
+        // Length of a step on a given rung, in ticks
+        let ticks_of = |rung: u32| n_ticks >> rung;
+
+        let mut rungs = {
+            let f = forces.forces(particles);
+            self.assign_rungs(particles, f, dt)
+        };
+
+        let mut tick = 0;
+        while tick < n_ticks {
+            let starting: Vec<usize> = (0..particles.len())
+                .filter(|&i| tick % ticks_of(rungs[i]) == 0)
+                .collect();
+            half_kick(particles, forces.last_forces(), &starting, &rungs, dt);
+
+            // Next tick where the step of some particle ends
+            let next = rungs
+                .iter()
+                .map(|&rung| (tick / ticks_of(rung) + 1) * ticks_of(rung))
+                .min()
+                .unwrap_or(n_ticks);
+            forces.drift(particles, (next - tick) as f64 * tick_dt);
+
+            let ending: Vec<usize> = (0..particles.len())
+                .filter(|&i| next % ticks_of(rungs[i]) == 0)
+                .collect();
+            forces.update_active(particles, &ending);
+            half_kick(particles, forces.last_forces(), &ending, &rungs, dt);
+
+            // Particles may move to a finer rung at the end of any of their steps,
+            // and to a coarser one only where it keeps them synchronised
+            let f = forces.last_forces();
+            for &i in ending.iter() {
+                let mut rung = self.rung(&particles[i], &f[i], dt);
+                while rung < rungs[i] && next % ticks_of(rung) != 0 {
+                    rung += 1;
+                }
+                rungs[i] = rung;
+            }
+            tick = next;
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use crate::error::ConfigError;
+use crate::forces::minimum_image;
+use crate::particle::Particle;
+use std::f64::consts::PI;
+
+// Interaction between given particles, referred to by their index in the
+// simulation, added to the forces of the solver
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bond {
+    // Spring of energy k (r - r0)^2 / 2, with a dashpot pulling along the bond
+    // against the relative velocity, with a force gamma (dv.n) n. Stiff springs
+    // stand in for rigid rods, at the cost of a short time step.
+    Harmonic {
+        i: usize,
+        j: usize,
+        stiffness: f64,
+        rest_length: f64,
+        damping: f64,
+    },
+    // Finitely extensible nonlinear elastic bond, of energy
+    // -k R^2 / 2 ln(1 - (r / R)^2), which diverges as r reaches R. Time steps
+    // must be short enough for no bond to be stretched past R within a step:
+    // beyond (r / R)^2 = FENE_MAX_STRETCH the energy goes on as a parabola,
+    // keeping forces finite, and the bond is counted as overstretched.
+    Fene {
+        i: usize,
+        j: usize,
+        stiffness: f64,
+        max_length: f64,
+    },
+    // Bending of the angle at `vertex` between the directions to `i` and `j`,
+    // of energy k (theta - theta0)^2 / 2, theta0 = pi keeping them aligned
+    Angle {
+        i: usize,
+        vertex: usize,
+        j: usize,
+        stiffness: f64,
+        rest_angle: f64,
+    },
+}
+
+pub const FENE_MAX_STRETCH: f64 = 0.99;
+
+// Radial force over r, positive when pulling the ends together, and energy of a
+// FENE bond, along with whether it is overstretched
+fn fene(stiffness: f64, max_length: f64, d: [f64; 2]) -> (f64, f64, bool) {
+    let r_sq = d[0] * d[0] + d[1] * d[1];
+    let stretch = r_sq / (max_length * max_length);
+    if stretch <= FENE_MAX_STRETCH {
+        let energy = -0.5 * stiffness * max_length * max_length * (1.0 - stretch).ln();
+        return (stiffness / (1.0 - stretch), energy, false);
+    }
+    // Second order expansion in r about the limit
+    let limit = max_length * FENE_MAX_STRETCH.sqrt();
+    let slack = 1.0 - FENE_MAX_STRETCH;
+    let energy = -0.5 * stiffness * max_length * max_length * slack.ln();
+    let slope = stiffness * limit / slack;
+    let curvature = stiffness * (1.0 + FENE_MAX_STRETCH) / (slack * slack);
+    let r = r_sq.sqrt();
+    let excess = r - limit;
+    (
+        (slope + curvature * excess) / r,
+        energy + slope * excess + 0.5 * curvature * excess * excess,
+        true,
+    )
+}
+
+impl Bond {
+    fn indices(&self) -> Vec<usize> {
+        match *self {
+            Bond::Harmonic { i, j, .. } | Bond::Fene { i, j, .. } => vec![i, j],
+            Bond::Angle { i, vertex, j, .. } => vec![i, vertex, j],
+        }
+    }
+
+    // Checks the parameters, and that the bond joins distinct particles among
+    // the first `n`
+    pub fn validate(&self, n: usize) -> Result<(), ConfigError> {
+        let indices = self.indices();
+        let distinct = (0..indices.len()).all(|a| !indices[a + 1..].contains(&indices[a]));
+        let valid = match *self {
+            Bond::Harmonic {
+                stiffness,
+                rest_length,
+                damping,
+                ..
+            } => [stiffness, rest_length, damping]
+                .iter()
+                .all(|x| x.is_finite() && *x >= 0.0),
+            Bond::Fene {
+                stiffness,
+                max_length,
+                ..
+            } => {
+                stiffness.is_finite()
+                    && stiffness >= 0.0
+                    && max_length.is_finite()
+                    && max_length > 0.0
+            }
+            Bond::Angle {
+                stiffness,
+                rest_angle,
+                ..
+            } => stiffness.is_finite() && stiffness >= 0.0 && (0.0..=PI).contains(&rest_angle),
+        };
+        if !(valid && distinct && indices.iter().all(|&i| i < n)) {
+            return Err(ConfigError::InvalidBond(*self));
+        }
+        Ok(())
+    }
+
+    // Adds the forces of the bond on its particles, returning whether it is
+    // overstretched. With a period, the bond joins the nearest images.
+    pub fn add_forces(
+        &self,
+        particles: &[Particle],
+        period: Option<[f64; 2]>,
+        forces: &mut [[f64; 2]],
+    ) -> bool {
+        let separation = |from: usize, to: usize| {
+            let (a, b) = (particles[from].position, particles[to].position);
+            minimum_image([b[0] - a[0], b[1] - a[1]], period)
+        };
+        let mut add = |index: usize, f: [f64; 2], sign: f64| {
+            forces[index][0] += sign * f[0];
+            forces[index][1] += sign * f[1];
+        };
+
+        match *self {
+            Bond::Harmonic {
+                i,
+                j,
+                stiffness,
+                rest_length,
+                damping,
+            } => {
+                let d = separation(i, j);
+                let r = (d[0] * d[0] + d[1] * d[1]).sqrt();
+                if r == 0.0 {
+                    return false;
+                }
+                let (vi, vj) = (particles[i].velocity, particles[j].velocity);
+                let dv = [vj[0] - vi[0], vj[1] - vi[1]];
+                let rate = (dv[0] * d[0] + dv[1] * d[1]) / r;
+                let magnitude = (stiffness * (r - rest_length) + damping * rate) / r;
+                let f = [magnitude * d[0], magnitude * d[1]];
+                add(i, f, 1.0);
+                add(j, f, -1.0);
+            }
+            Bond::Fene {
+                i,
+                j,
+                stiffness,
+                max_length,
+            } => {
+                let d = separation(i, j);
+                let (magnitude, _, overstretched) = fene(stiffness, max_length, d);
+                let f = [magnitude * d[0], magnitude * d[1]];
+                add(i, f, 1.0);
+                add(j, f, -1.0);
+                return overstretched;
+            }
+            Bond::Angle {
+                i,
+                vertex,
+                j,
+                stiffness,
+                rest_angle,
+            } => {
+                // Signed angle phi from a to b, theta = |phi|, whose gradients
+                // stay finite when the bonds are aligned
+                let (a, b) = (separation(vertex, i), separation(vertex, j));
+                let (a_sq, b_sq) = (a[0] * a[0] + a[1] * a[1], b[0] * b[0] + b[1] * b[1]);
+                if a_sq == 0.0 || b_sq == 0.0 {
+                    return false;
+                }
+                let phi = (a[0] * b[1] - a[1] * b[0]).atan2(a[0] * b[0] + a[1] * b[1]);
+                let torque = -stiffness * (phi.abs() - rest_angle) * phi.signum();
+                let fi = [torque * a[1] / a_sq, -torque * a[0] / a_sq];
+                let fj = [-torque * b[1] / b_sq, torque * b[0] / b_sq];
+                add(i, fi, 1.0);
+                add(j, fj, 1.0);
+                add(vertex, [fi[0] + fj[0], fi[1] + fj[1]], -1.0);
+            }
+        }
+        false
+    }
+
+    // Potential energy of the bond, leaving out what the damping dissipates
+    pub fn energy(&self, particles: &[Particle], period: Option<[f64; 2]>) -> f64 {
+        let separation = |from: usize, to: usize| {
+            let (a, b) = (particles[from].position, particles[to].position);
+            minimum_image([b[0] - a[0], b[1] - a[1]], period)
+        };
+        match *self {
+            Bond::Harmonic {
+                i,
+                j,
+                stiffness,
+                rest_length,
+                ..
+            } => {
+                let d = separation(i, j);
+                let r = (d[0] * d[0] + d[1] * d[1]).sqrt();
+                0.5 * stiffness * (r - rest_length).powi(2)
+            }
+            Bond::Fene {
+                i,
+                j,
+                stiffness,
+                max_length,
+            } => fene(stiffness, max_length, separation(i, j)).1,
+            Bond::Angle {
+                i,
+                vertex,
+                j,
+                stiffness,
+                rest_angle,
+            } => {
+                let (a, b) = (separation(vertex, i), separation(vertex, j));
+                let theta = (a[0] * b[1] - a[1] * b[0])
+                    .atan2(a[0] * b[0] + a[1] * b[1])
+                    .abs();
+                0.5 * stiffness * (theta - rest_angle).powi(2)
+            }
+        }
+    }
+}
+
+// Adds the forces of the bonds, returning how many are overstretched
+pub fn add_bond_forces(
+    bonds: &[Bond],
+    particles: &[Particle],
+    period: Option<[f64; 2]>,
+    forces: &mut [[f64; 2]],
+) -> usize {
+    bonds
+        .iter()
+        .filter(|bond| bond.add_forces(particles, period, forces))
+        .count()
+}
+
+pub fn bond_energy(bonds: &[Bond], particles: &[Particle], period: Option<[f64; 2]>) -> f64 {
+    bonds
+        .iter()
+        .map(|bond| bond.energy(particles, period))
+        .sum()
+}
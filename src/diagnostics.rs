@@ -0,0 +1,138 @@
+use crate::ewald::period;
+use crate::forces::minimum_image;
+use crate::pair_force::{Gravity, PairForce};
+use crate::particle::Particle;
+use crate::softening::Softening;
+use rayon::prelude::*;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Diagnostics {
+    pub kinetic_energy: f64,
+    pub potential_energy: f64,
+    pub total_energy: f64,
+    pub momentum: [f64; 2],
+    pub angular_momentum: f64,
+    pub center_of_mass: [f64; 2],
+    pub total_mass: f64,
+}
+
+impl Diagnostics {
+    pub fn compute(particles: &[Particle]) -> Self {
+        Diagnostics::compute_with_softening(particles, &Softening::default())
+    }
+
+    // The potential energy should use the softening of the solver for the
+    // total energy to be conserved
+    pub fn compute_with_softening(particles: &[Particle], softening: &Softening) -> Self {
+        Diagnostics::compute_with_force(particles, &Gravity::with_softening(*softening))
+    }
+
+    // Diagnostics with the potential energy of any pair force
+    pub fn compute_with_force(particles: &[Particle], force: &impl PairForce) -> Self {
+        Diagnostics::with_potential_energy(particles, potential_energy(particles, force))
+    }
+
+    // Diagnostics of a periodic box in which pairs only interact through their
+    // nearest image, as with the short-range forces of NeighbourList
+    pub fn compute_in_periodic_domain(
+        particles: &[Particle],
+        force: &impl PairForce,
+        domain: [f64; 4],
+    ) -> Self {
+        let potential_energy = periodic_potential_energy(particles, force, domain);
+        Diagnostics::with_potential_energy(particles, potential_energy)
+    }
+
+    // Diagnostics with a potential energy computed elsewhere, such as by
+    // Simulation::diagnostics
+    pub fn with_potential_energy(particles: &[Particle], potential_energy: f64) -> Self {
+        let kinetic_energy = kinetic_energy(particles);
+        let total_mass: f64 = particles.iter().map(|p| p.mass).sum();
+
+        let mut momentum = [0.0, 0.0];
+        let mut angular_momentum = 0.0;
+        let mut center_of_mass = [0.0, 0.0];
+        for p in particles {
+            momentum[0] += p.mass * p.velocity[0];
+            momentum[1] += p.mass * p.velocity[1];
+            angular_momentum +=
+                p.mass * (p.position[0] * p.velocity[1] - p.position[1] * p.velocity[0]);
+            center_of_mass[0] += p.mass * p.position[0];
+            center_of_mass[1] += p.mass * p.position[1];
+        }
+        if total_mass != 0.0 {
+            center_of_mass[0] /= total_mass;
+            center_of_mass[1] /= total_mass;
+        }
+
+        Diagnostics {
+            kinetic_energy,
+            potential_energy,
+            total_energy: kinetic_energy + potential_energy,
+            momentum,
+            angular_momentum,
+            center_of_mass,
+            total_mass,
+        }
+    }
+
+    // Relative drift of the total energy with respect to a reference state, or
+    // absolute drift when the reference energy vanishes
+    pub fn energy_error(&self, reference: &Diagnostics) -> f64 {
+        let drift = (self.total_energy - reference.total_energy).abs();
+        if reference.total_energy == 0.0 {
+            return drift;
+        }
+        drift / reference.total_energy.abs()
+    }
+}
+
+pub fn kinetic_energy(particles: &[Particle]) -> f64 {
+    particles
+        .iter()
+        .map(|p| 0.5 * p.mass * (p.velocity[0] * p.velocity[0] + p.velocity[1] * p.velocity[1]))
+        .sum()
+}
+
+// Exact pairwise potential energy, O(N^2). Partial sums are added in index
+// order so the result does not depend on thread scheduling.
+pub fn potential_energy(particles: &[Particle], force: &impl PairForce) -> f64 {
+    pair_potential_energy(particles, force, None)
+}
+
+// Potential energy of the nearest images of all pairs, O(N^2)
+pub fn periodic_potential_energy(
+    particles: &[Particle],
+    force: &impl PairForce,
+    domain: [f64; 4],
+) -> f64 {
+    pair_potential_energy(particles, force, Some(period(domain)))
+}
+
+pub(crate) fn pair_potential_energy(
+    particles: &[Particle],
+    force: &impl PairForce,
+    period: Option<[f64; 2]>,
+) -> f64 {
+    let partial_sums: Vec<f64> = (0..particles.len())
+        .into_par_iter()
+        .map(|i| {
+            let p = &particles[i];
+            let mut energy = 0.0;
+            for other in particles[i + 1..].iter() {
+                let mut image = *other;
+                let d = minimum_image(
+                    [
+                        other.position[0] - p.position[0],
+                        other.position[1] - p.position[1],
+                    ],
+                    period,
+                );
+                image.position = [p.position[0] + d[0], p.position[1] + d[1]];
+                energy += force.potential_energy(p, &image);
+            }
+            energy
+        })
+        .collect();
+    partial_sums.iter().sum()
+}
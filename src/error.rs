@@ -0,0 +1,179 @@
+use crate::bonds::Bond;
+use crate::quadtree::{OpeningCriterion, TreeLimits};
+use crate::softening::Softening;
+use crate::timestep::TimestepCriterion;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    InvalidTimestep(f64),
+    InvalidTheta(f64),
+    InvalidCompositionWeights(f64),
+    InvalidTolerance(f64, f64),
+    InvalidTimestepBounds(f64, f64),
+    InvalidTimestepCriterion(TimestepCriterion),
+    InvalidBlockTimesteps(u32, f64, f64),
+    JerkNotSupported,
+    InvalidCentralBody(usize, usize),
+    GravityRequired,
+    InvalidAccuracyParameter(f64),
+    InvalidDomain([f64; 4]),
+    InvalidOpeningCriterion(OpeningCriterion),
+    InvalidTreeLimits(TreeLimits),
+    InvalidFmmConfig(usize, f64),
+    InvalidGridSize(usize),
+    InvalidForceSplit(f64, f64),
+    InvalidSoftening(Softening),
+    InvalidPairForce(String),
+    InvalidNeighbourList(f64, f64),
+    InvalidBond(Bond),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidTimestep(dt) => {
+                write!(f, "time step must be finite and positive, got {}", dt)
+            }
+            ConfigError::InvalidTheta(theta) => {
+                write!(
+                    f,
+                    "Barnes-Hut theta must be finite and non-negative, got {}",
+                    theta
+                )
+            }
+            ConfigError::InvalidCompositionWeights(sum) => {
+                write!(
+                    f,
+                    "composition weights must be finite and sum to one, got a sum of {}",
+                    sum
+                )
+            }
+            ConfigError::InvalidTolerance(atol, rtol) => {
+                write!(
+                    f,
+                    "tolerances must be non-negative and not both zero, got atol = {} and rtol = {}",
+                    atol, rtol
+                )
+            }
+            ConfigError::InvalidTimestepBounds(dt_min, dt_max) => {
+                write!(
+                    f,
+                    "time step bounds must satisfy 0 < dt_min <= dt_max, got [{}, {}]",
+                    dt_min, dt_max
+                )
+            }
+            ConfigError::InvalidTimestepCriterion(criterion) => {
+                write!(f, "invalid time step criterion parameters: {:?}", criterion)
+            }
+            ConfigError::InvalidBlockTimesteps(max_rung, eta, softening) => {
+                write!(
+                    f,
+                    "block time steps need max_rung <= 20 and positive eta and softening, got {}, {} and {}",
+                    max_rung, eta, softening
+                )
+            }
+            ConfigError::JerkNotSupported => {
+                write!(
+                    f,
+                    "the integrator needs jerks, which the solver does not compute"
+                )
+            }
+            ConfigError::InvalidCentralBody(central, n) => {
+                write!(
+                    f,
+                    "central body {} is not among the {} particles",
+                    central, n
+                )
+            }
+            ConfigError::GravityRequired => {
+                write!(
+                    f,
+                    "the integrator needs a solver computing Newtonian gravity between masses in an isolated domain"
+                )
+            }
+            ConfigError::InvalidAccuracyParameter(eta) => {
+                write!(
+                    f,
+                    "accuracy parameter must be finite and positive, got {}",
+                    eta
+                )
+            }
+            ConfigError::InvalidDomain(domain) => {
+                write!(
+                    f,
+                    "domain must be a finite, non-empty box, got {:?}",
+                    domain
+                )
+            }
+            ConfigError::InvalidOpeningCriterion(criterion) => {
+                write!(f, "invalid opening criterion parameters: {:?}", criterion)
+            }
+            ConfigError::InvalidTreeLimits(limits) => {
+                write!(
+                    f,
+                    "tree limits need a positive leaf capacity and max_depth <= {}, got {:?}",
+                    TreeLimits::MAX_DEPTH,
+                    limits
+                )
+            }
+            ConfigError::InvalidFmmConfig(order, theta) => {
+                write!(
+                    f,
+                    "FMM needs 1 <= order <= 30 and 0 < theta < 1, got {} and {}",
+                    order, theta
+                )
+            }
+            ConfigError::InvalidGridSize(size) => {
+                write!(f, "mesh is too small, got {} cells per side", size)
+            }
+            ConfigError::InvalidForceSplit(split, cutoff) => {
+                write!(
+                    f,
+                    "force split needs a positive scale and cutoff, reaching less than half a periodic box, got {} and {}",
+                    split, cutoff
+                )
+            }
+            ConfigError::InvalidSoftening(softening) => {
+                write!(
+                    f,
+                    "softening lengths and neighbour counts must be finite and positive, got {:?}",
+                    softening
+                )
+            }
+            ConfigError::InvalidPairForce(force) => {
+                write!(f, "invalid pair force parameters: {}", force)
+            }
+            ConfigError::InvalidNeighbourList(cutoff, skin) => {
+                write!(
+                    f,
+                    "neighbour lists need a force with a finite cutoff and a non-negative skin, reaching less than half a periodic box, got {} and {}",
+                    cutoff, skin
+                )
+            }
+            ConfigError::InvalidBond(bond) => {
+                write!(
+                    f,
+                    "bonds need finite, non-negative parameters and distinct particles of the simulation, got {:?}",
+                    bond
+                )
+            }
+        }
+    }
+}
+
+// Finite box with x_min < x_max and y_min < y_max
+pub(crate) fn validate_domain(domain: [f64; 4]) -> Result<(), ConfigError> {
+    let [x_min, y_min, x_max, y_max] = domain;
+    if !(x_min < x_max && y_min < y_max && domain.iter().all(|x| x.is_finite())) {
+        return Err(ConfigError::InvalidDomain(domain));
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_limits(limits: TreeLimits) -> Result<(), ConfigError> {
+    if limits.leaf_capacity == 0 || limits.max_depth > TreeLimits::MAX_DEPTH {
+        return Err(ConfigError::InvalidTreeLimits(limits));
+    }
+    Ok(())
+}
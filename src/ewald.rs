@@ -0,0 +1,164 @@
+use crate::forces::{compute_gravity_at, minimum_image, ForceSplit, GRAVIT_CONST};
+use crate::particle::Particle;
+use crate::softening::Softening;
+use rayon::prelude::*;
+use std::f64::consts::PI;
+
+// Cells of the Ewald table along each half side of the box
+pub const EWALD_TABLE_SIZE: usize = 64;
+
+// Side lengths of a periodic domain [x_min, y_min, x_max, y_max]
+pub fn period(domain: [f64; 4]) -> [f64; 2] {
+    [domain[2] - domain[0], domain[3] - domain[1]]
+}
+
+// Position brought back into a periodic domain
+pub fn wrap_position(position: [f64; 2], domain: [f64; 4]) -> [f64; 2] {
+    let [x_min, y_min, x_max, y_max] = domain;
+    [
+        x_min + (position[0] - x_min).rem_euclid(x_max - x_min),
+        y_min + (position[1] - y_min).rem_euclid(y_max - y_min),
+    ]
+}
+
+// Difference between the force from a particle and all of its periodic images,
+// in a uniform background cancelling their mean density, and the force from its
+// nearest image alone. Tabulated over a quarter of the box, the rest following
+// from the symmetries of the lattice, and interpolated bilinearly.
+#[derive(Debug, Clone)]
+pub struct EwaldTable {
+    period: [f64; 2],
+    size: usize,
+    // (size + 1)^2 corrections, by rows of increasing y
+    corrections: Vec<[f64; 2]>,
+}
+
+impl EwaldTable {
+    pub fn new(period: [f64; 2], size: usize) -> Self {
+        let step = [0.5 * period[0] / size as f64, 0.5 * period[1] / size as f64];
+        let corrections = (0..(size + 1) * (size + 1))
+            .into_par_iter()
+            .map(|cell| {
+                let (i, j) = (cell % (size + 1), cell / (size + 1));
+                ewald_correction([i as f64 * step[0], j as f64 * step[1]], period)
+            })
+            .collect();
+        EwaldTable {
+            period,
+            size,
+            corrections,
+        }
+    }
+
+    pub fn period(&self) -> [f64; 2] {
+        self.period
+    }
+
+    // Correction to the force between unit masses at separation d = x2 - x1,
+    // d going to the nearest image
+    pub fn correction(&self, d: [f64; 2]) -> [f64; 2] {
+        let n = self.size;
+        let index = |x: f64, side: f64| {
+            let u = x.abs() / (0.5 * side) * n as f64;
+            let i = (u.floor() as usize).min(n - 1);
+            (i, (u - i as f64).min(1.0))
+        };
+        let (i, fx) = index(d[0], self.period[0]);
+        let (j, fy) = index(d[1], self.period[1]);
+        let at = |i: usize, j: usize| self.corrections[j * (n + 1) + i];
+        let (c00, c10, c01, c11) = (at(i, j), at(i + 1, j), at(i, j + 1), at(i + 1, j + 1));
+        let mut c = [0.0; 2];
+        for k in 0..2 {
+            c[k] = (1.0 - fy) * ((1.0 - fx) * c00[k] + fx * c10[k])
+                + fy * ((1.0 - fx) * c01[k] + fx * c11[k]);
+        }
+        // Each component is odd along its own axis and even along the other
+        [c[0] * d[0].signum(), c[1] * d[1].signum()]
+    }
+
+    // Force of p2 and all its periodic images on p1, only the nearest image
+    // being softened
+    pub fn gravity(&self, p1: &Particle, p2: &Particle, softening: &Softening) -> [f64; 2] {
+        let d = minimum_image(
+            [
+                p2.position[0] - p1.position[0],
+                p2.position[1] - p1.position[1],
+            ],
+            Some(self.period),
+        );
+        let length = softening.pair_length(p1.softening, p2.softening);
+        let force = compute_gravity_at(p1.mass, p2.mass, d, softening, length);
+        let correction = self.correction(d);
+        [
+            force[0] + p1.mass * p2.mass * correction[0],
+            force[1] + p1.mass * p2.mass * correction[1],
+        ]
+    }
+}
+
+// Ewald sum for the force between unit masses at separation d = x2 - x1,
+// less the Newtonian force at d. The 1 / r potential splits into erfc(alpha r) / r,
+// summed over the nearest images in real space, and erf(alpha r) / r, whose 2D
+// transform 2 pi erfc(k / 2 alpha) / k is summed over the reciprocal lattice.
+fn ewald_correction(d: [f64; 2], period: [f64; 2]) -> [f64; 2] {
+    let alpha = 2.0 / period[0].min(period[1]);
+    let mut force = [0.0, 0.0];
+
+    // The nearest image less the Newtonian force, which is the long-range part
+    // of a split at r_s = 1 / (2 alpha) with the opposite sign
+    let dist_sq = d[0] * d[0] + d[1] * d[1];
+    if dist_sq > 0.0 {
+        let split = ForceSplit {
+            scale: 0.5 / alpha,
+            cutoff: f64::INFINITY,
+            softening: Softening::None,
+        };
+        let dist = dist_sq.sqrt();
+        let f = -split.long_range(dist) / dist;
+        force[0] += f * d[0];
+        force[1] += f * d[1];
+    } else {
+        return force;
+    }
+
+    // Other images, up to erfc(6) ~ 2e-17
+    let images = |side: f64| (6.0 / (alpha * side)).ceil() as i64 + 1;
+    let (nx, ny) = (images(period[0]), images(period[1]));
+    for i in -nx..=nx {
+        for j in -ny..=ny {
+            if i == 0 && j == 0 {
+                continue;
+            }
+            let s = [d[0] + i as f64 * period[0], d[1] + j as f64 * period[1]];
+            let r = (s[0] * s[0] + s[1] * s[1]).sqrt();
+            let f = (libm::erfc(alpha * r) / (r * r)
+                + 2.0 * alpha / PI.sqrt() * (-alpha * alpha * r * r).exp() / r)
+                / r;
+            force[0] += f * s[0];
+            force[1] += f * s[1];
+        }
+    }
+
+    // Reciprocal lattice, up to k / (2 alpha) = 6
+    let waves = |side: f64| (6.0 * alpha * side / PI).ceil() as i64;
+    let (hx, hy) = (waves(period[0]), waves(period[1]));
+    let area = period[0] * period[1];
+    for i in -hx..=hx {
+        for j in -hy..=hy {
+            if i == 0 && j == 0 {
+                continue;
+            }
+            let k = [
+                2.0 * PI * i as f64 / period[0],
+                2.0 * PI * j as f64 / period[1],
+            ];
+            let k_norm = (k[0] * k[0] + k[1] * k[1]).sqrt();
+            let f = 2.0 * PI / area * libm::erfc(k_norm / (2.0 * alpha)) / k_norm
+                * (k[0] * d[0] + k[1] * d[1]).sin();
+            force[0] += f * k[0];
+            force[1] += f * k[1];
+        }
+    }
+
+    [GRAVIT_CONST * force[0], GRAVIT_CONST * force[1]]
+}
@@ -0,0 +1,399 @@
+use crate::forces::{compute_gravity, GRAVIT_CONST};
+use crate::linear_tree::{LinearNode, LinearQuadTree};
+use crate::particle::Particle;
+use crate::quadtree::TreeLimits;
+use crate::softening::Softening;
+use num_complex::Complex64;
+use rayon::prelude::*;
+
+// Target cells with fewer particles than this are handled on a single thread
+const PARALLEL_THRESHOLD: usize = 1024;
+
+// Fast multipole method for the 1/r potential of particles in the plane.
+//
+// With positions as complex numbers, the kernel factorises as
+// 1/|z| = z^(-1/2) conj(z^(-1/2)), so that it expands as a double series in
+// powers of z and conj(z), built from the binomial series of (1 + u)^(-1/2).
+// Expansions of order p keep the terms t^k conj(t)^l with k + l <= p:
+// - multipole moments of a cell about its centre c: M_kl = sum m w^k conj(w)^l,
+//   with w = x - c
+// - local expansion of the potential psi = sum m / |x - x_j| about the centre
+//   of a cell: psi(c + t) = sum L_kl t^k conj(t)^l
+// The acceleration is G grad(psi), or 2 G d(psi)/d(conj(t)) as a complex number.
+//
+// Cells are those of a LinearQuadTree, expanded about their centre of mass.
+// Interactions are found by a dual tree walk, which adapts to clustering: a
+// target cell takes the local expansion of every source cell it is well
+// separated from, and sums leaf-leaf interactions directly.
+#[derive(Debug, Clone)]
+pub struct FmmTree {
+    pub tree: LinearQuadTree,
+    order: usize,
+    theta: f64,
+    softening: Softening,
+    tables: Tables,
+    // Distance from the centre of mass of each node to its farthest particle
+    radii: Vec<f64>,
+    multipoles: Vec<Expansion>,
+}
+
+// Coefficients c_kl of an expansion, for k + l <= order
+#[derive(Debug, Clone)]
+struct Expansion {
+    coefficients: Vec<Complex64>,
+}
+
+impl Expansion {
+    fn zeros(order: usize) -> Self {
+        Expansion {
+            coefficients: vec![Complex64::new(0.0, 0.0); (order + 1) * (order + 2) / 2],
+        }
+    }
+
+    fn index(k: usize, l: usize) -> usize {
+        let degree = k + l;
+        degree * (degree + 1) / 2 + l
+    }
+
+    fn get(&self, k: usize, l: usize) -> Complex64 {
+        self.coefficients[Self::index(k, l)]
+    }
+
+    fn add(&mut self, k: usize, l: usize, value: Complex64) {
+        self.coefficients[Self::index(k, l)] += value;
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Tables {
+    binomial: Vec<Vec<f64>>,
+    // Coefficients of (1 + u)^(-1/2) = sum a_n u^n
+    half: Vec<f64>,
+}
+
+impl Tables {
+    fn new(order: usize) -> Self {
+        let mut binomial = vec![vec![0.0; order + 1]; order + 1];
+        for n in 0..=order {
+            binomial[n][0] = 1.0;
+            for k in 1..=n {
+                binomial[n][k] =
+                    binomial[n - 1][k - 1] + if k < n { binomial[n - 1][k] } else { 0.0 };
+            }
+        }
+        let mut half = vec![1.0; order + 1];
+        for n in 1..=order {
+            half[n] = -half[n - 1] * (2 * n - 1) as f64 / (2 * n) as f64;
+        }
+        Tables { binomial, half }
+    }
+}
+
+// z^0 to z^order
+fn powers(z: Complex64, order: usize) -> Vec<Complex64> {
+    let mut powers = Vec::with_capacity(order + 1);
+    powers.push(Complex64::new(1.0, 0.0));
+    for n in 1..=order {
+        powers.push(powers[n - 1] * z);
+    }
+    powers
+}
+
+fn center(node: &LinearNode) -> Complex64 {
+    Complex64::new(node.center_of_mass[0], node.center_of_mass[1])
+}
+
+impl FmmTree {
+    pub fn build(
+        particles: &[Particle],
+        boundary: [f64; 4],
+        limits: TreeLimits,
+        order: usize,
+        theta: f64,
+        softening: Softening,
+    ) -> Self {
+        let tree = LinearQuadTree::build(particles, boundary, limits);
+        let tables = Tables::new(order);
+        let mut radii = vec![0.0; tree.nodes.len()];
+        let mut multipoles = vec![Expansion::zeros(order); tree.nodes.len()];
+        if !tree.nodes.is_empty() {
+            let upward = Upward {
+                tree: &tree,
+                order,
+                tables: &tables,
+            };
+            upward.run(0, &mut radii, &mut multipoles);
+        }
+        FmmTree {
+            tree,
+            order,
+            theta,
+            softening,
+            tables,
+            radii,
+            multipoles,
+        }
+    }
+
+    // Forces on the particles the tree was built from, in their original order.
+    // Particles left out of the tree feel no force.
+    pub fn compute_forces(&self, forces: &mut [[f64; 2]]) {
+        let mut sorted_forces = vec![[0.0, 0.0]; self.tree.particles.len()];
+        if !self.tree.nodes.is_empty() {
+            self.downward(0, Expansion::zeros(self.order), vec![0], &mut sorted_forces);
+        }
+        forces.fill([0.0, 0.0]);
+        for (&i, force) in self.tree.order.iter().zip(sorted_forces) {
+            forces[i] = force;
+        }
+    }
+
+    fn children(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        let end = index + self.tree.nodes[index].skip;
+        let mut child = index + 1;
+        std::iter::from_fn(move || {
+            if child >= end {
+                return None;
+            }
+            let current = child;
+            child += self.tree.nodes[child].skip;
+            Some(current)
+        })
+    }
+
+    // Cells whose particles are all further apart than the reach of the
+    // softening, and far enough for the expansions to converge
+    fn well_separated(&self, a: usize, b: usize) -> bool {
+        let (node_a, node_b) = (&self.tree.nodes[a], &self.tree.nodes[b]);
+        let distance = (center(node_a) - center(node_b)).norm();
+        let radii = self.radii[a] + self.radii[b];
+        let length = self
+            .softening
+            .pair_length(node_a.max_softening, node_b.max_softening);
+        radii < self.theta * distance && distance - radii >= self.softening.reach(length)
+    }
+
+    // Handles target cell `a`, given the local expansion inherited from its
+    // parent and the source cells which still have to be dealt with
+    fn downward(
+        &self,
+        a: usize,
+        mut local: Expansion,
+        candidates: Vec<usize>,
+        forces: &mut [[f64; 2]],
+    ) {
+        let node_a = &self.tree.nodes[a];
+        let mut stack = candidates;
+        let mut deferred = Vec::new();
+        let mut direct = Vec::new();
+        while let Some(b) = stack.pop() {
+            let node_b = &self.tree.nodes[b];
+            if self.well_separated(a, b) {
+                self.multipole_to_local(b, a, &mut local);
+            } else if node_a.is_leaf() && node_b.is_leaf() {
+                direct.push(b);
+            } else if !node_b.is_leaf() && (node_a.is_leaf() || self.radii[b] >= self.radii[a]) {
+                stack.extend(self.children(b));
+            } else {
+                deferred.push(b);
+            }
+        }
+
+        if node_a.is_leaf() {
+            let particles = &self.tree.particles[node_a.start..node_a.end];
+            for (particle, force) in particles.iter().zip(forces.iter_mut()) {
+                let acc = self.local_to_particle(&local, particle.position, a);
+                *force = [particle.mass * acc[0], particle.mass * acc[1]];
+                for &b in direct.iter() {
+                    let node_b = &self.tree.nodes[b];
+                    for other in self.tree.particles[node_b.start..node_b.end].iter() {
+                        let f = compute_gravity(particle, other, &self.softening);
+                        force[0] += f[0];
+                        force[1] += f[1];
+                    }
+                }
+            }
+            return;
+        }
+
+        // Split the forces of this cell between its children, whose particles
+        // are contiguous in Morton order
+        let mut tasks = Vec::new();
+        let mut rest = forces;
+        for c in self.children(a) {
+            let node_c = &self.tree.nodes[c];
+            let (child_forces, tail) = rest.split_at_mut(node_c.end - node_c.start);
+            rest = tail;
+            tasks.push((c, child_forces));
+        }
+        let run = |(c, child_forces): (usize, &mut [[f64; 2]])| {
+            let child_local = self.local_to_local(&local, a, c);
+            self.downward(c, child_local, deferred.clone(), child_forces);
+        };
+        if node_a.end - node_a.start >= PARALLEL_THRESHOLD {
+            tasks.into_par_iter().for_each(run);
+        } else {
+            tasks.into_iter().for_each(run);
+        }
+    }
+
+    // L_kl += |D|^-1 sum_(n, n') a_n a_n' D^-n conj(D)^-n' C(n, k) C(n', l)
+    //         (-1)^(n - k + n' - l) M_(n - k)(n' - l)
+    // with D the separation of the centres, over n >= k, n' >= l, n + n' <= p
+    fn multipole_to_local(&self, source: usize, target: usize, local: &mut Expansion) {
+        let p = self.order;
+        let d = center(&self.tree.nodes[target]) - center(&self.tree.nodes[source]);
+        let inv_d = powers(d.inv(), p);
+        let inv_d_conj: Vec<Complex64> = inv_d.iter().map(|z| z.conj()).collect();
+        let inv_norm = 1.0 / d.norm();
+        let multipole = &self.multipoles[source];
+        let tables = &self.tables;
+
+        for k in 0..=p {
+            for l in 0..=(p - k) {
+                let mut sum = Complex64::new(0.0, 0.0);
+                for (n, inv_d_n) in inv_d.iter().enumerate().take(p - l + 1).skip(k) {
+                    let factor_n = tables.half[n] * tables.binomial[n][k] * inv_d_n;
+                    let conj_terms = inv_d_conj.iter().enumerate().take(p - n + 1).skip(l);
+                    for (n_conj, inv_d_conj_n) in conj_terms {
+                        let (i, j) = (n - k, n_conj - l);
+                        let sign = if (i + j) % 2 == 0 { 1.0 } else { -1.0 };
+                        sum += factor_n
+                            * (sign * tables.half[n_conj] * tables.binomial[n_conj][l])
+                            * inv_d_conj_n
+                            * multipole.get(i, j);
+                    }
+                }
+                local.add(k, l, sum * inv_norm);
+            }
+        }
+    }
+
+    // L'_ij = sum_(k >= i, l >= j) L_kl C(k, i) C(l, j) e^(k - i) conj(e)^(l - j)
+    // with e the offset of the child centre from the parent centre
+    fn local_to_local(&self, local: &Expansion, parent: usize, child: usize) -> Expansion {
+        let p = self.order;
+        let e = center(&self.tree.nodes[child]) - center(&self.tree.nodes[parent]);
+        let e_powers = powers(e, p);
+        let e_conj_powers = powers(e.conj(), p);
+        let binomial = &self.tables.binomial;
+
+        let mut shifted = Expansion::zeros(p);
+        for i in 0..=p {
+            for j in 0..=(p - i) {
+                let mut sum = Complex64::new(0.0, 0.0);
+                for k in i..=(p - j) {
+                    for l in j..=(p - k) {
+                        sum += local.get(k, l)
+                            * (binomial[k][i] * binomial[l][j])
+                            * e_powers[k - i]
+                            * e_conj_powers[l - j];
+                    }
+                }
+                shifted.add(i, j, sum);
+            }
+        }
+        shifted
+    }
+
+    // Acceleration 2 G sum l L_kl t^k conj(t)^(l - 1) at the given position
+    fn local_to_particle(&self, local: &Expansion, position: [f64; 2], cell: usize) -> [f64; 2] {
+        let p = self.order;
+        let t = Complex64::new(position[0], position[1]) - center(&self.tree.nodes[cell]);
+        let t_powers = powers(t, p);
+        let t_conj_powers = powers(t.conj(), p);
+
+        let mut gradient = Complex64::new(0.0, 0.0);
+        for (k, t_k) in t_powers.iter().enumerate().take(p) {
+            for l in 1..=(p - k) {
+                gradient += local.get(k, l) * (l as f64) * t_k * t_conj_powers[l - 1];
+            }
+        }
+        let acc = gradient * (2.0 * GRAVIT_CONST);
+        [acc.re, acc.im]
+    }
+}
+
+// Bottom-up computation of the multipole moments and radii, over disjoint
+// subtrees in parallel
+struct Upward<'a> {
+    tree: &'a LinearQuadTree,
+    order: usize,
+    tables: &'a Tables,
+}
+
+impl Upward<'_> {
+    // `radii` and `multipoles` cover the subtree of node `index`, this node first
+    fn run(&self, index: usize, radii: &mut [f64], multipoles: &mut [Expansion]) {
+        let node = &self.tree.nodes[index];
+        let c = center(node);
+        let p = self.order;
+
+        if node.is_leaf() {
+            let mut radius: f64 = 0.0;
+            let multipole = &mut multipoles[0];
+            for particle in self.tree.particles[node.start..node.end].iter() {
+                let w = Complex64::new(particle.position[0], particle.position[1]) - c;
+                radius = radius.max(w.norm());
+                let w_powers = powers(w, p);
+                let w_conj_powers = powers(w.conj(), p);
+                for (k, w_k) in w_powers.iter().enumerate() {
+                    for (l, w_conj_l) in w_conj_powers.iter().enumerate().take(p - k + 1) {
+                        multipole.add(k, l, particle.mass * w_k * w_conj_l);
+                    }
+                }
+            }
+            radii[0] = radius;
+            return;
+        }
+
+        // Children subtrees follow this node, one after the other
+        let (own_radius, child_radii) = radii.split_first_mut().unwrap();
+        let (own_multipole, child_multipoles) = multipoles.split_first_mut().unwrap();
+        let mut tasks = Vec::new();
+        let (mut radii_rest, mut multipoles_rest) = (child_radii, child_multipoles);
+        let mut child = index + 1;
+        while child < index + node.skip {
+            let size = self.tree.nodes[child].skip;
+            let (r, r_tail) = radii_rest.split_at_mut(size);
+            let (m, m_tail) = multipoles_rest.split_at_mut(size);
+            tasks.push((child, r, m));
+            radii_rest = r_tail;
+            multipoles_rest = m_tail;
+            child += size;
+        }
+        if node.end - node.start >= PARALLEL_THRESHOLD {
+            tasks
+                .par_iter_mut()
+                .for_each(|(child, r, m)| self.run(*child, r, m));
+        } else {
+            for (child, r, m) in tasks.iter_mut() {
+                self.run(*child, r, m);
+            }
+        }
+
+        // M'_kl = sum_(i <= k, j <= l) C(k, i) C(l, j) d^(k - i) conj(d)^(l - j) M_ij
+        // with d the offset of the child centre from this centre
+        let binomial = &self.tables.binomial;
+        for (child, r, m) in tasks.iter() {
+            let d = center(&self.tree.nodes[*child]) - c;
+            *own_radius = own_radius.max(r[0] + d.norm());
+            let d_powers = powers(d, p);
+            let d_conj_powers = powers(d.conj(), p);
+            for k in 0..=p {
+                for l in 0..=(p - k) {
+                    let mut sum = Complex64::new(0.0, 0.0);
+                    for i in 0..=k {
+                        for j in 0..=l {
+                            sum += m[0].get(i, j)
+                                * (binomial[k][i] * binomial[l][j])
+                                * d_powers[k - i]
+                                * d_conj_powers[l - j];
+                        }
+                    }
+                    own_multipole.add(k, l, sum);
+                }
+            }
+        }
+    }
+}
@@ -1,19 +1,82 @@
+use crate::pair_force::{Gravity, PairForce};
 use crate::particle::Particle;
+use crate::softening::Softening;
 use std::f64::consts::PI;
 
 pub const GRAVIT_CONST: f64 = 4.0 * PI * PI;
 
-pub fn compute_gravity(p1: &Particle, p2: &Particle) -> [f64; 2] {
-    let dx = p2.position[0] - p1.position[0];
-    let dy = p2.position[1] - p1.position[1];
-    let dist_sq = dx * dx + dy * dy;
+// Force exerted on p1 by p2, with the default gravitational constant
+pub fn compute_gravity(p1: &Particle, p2: &Particle, softening: &Softening) -> [f64; 2] {
+    Gravity::with_softening(*softening).force(p1, p2)
+}
+
+// Force on a mass m1 from a mass m2 at separation d = x2 - x1, for a pair with
+// the given softening length
+pub fn compute_gravity_at(
+    m1: f64,
+    m2: f64,
+    d: [f64; 2],
+    softening: &Softening,
+    length: f64,
+) -> [f64; 2] {
+    Gravity::with_softening(*softening).force_at(m1, m2, d, length)
+}
 
-    let dist_sq = dist_sq.max(1.0);
+// Split of the interaction at a scale r_s, as in TreePM codes: a long-range
+// part with potential -G m1 m2 erf(r / 2 r_s) / r, smooth enough for a mesh,
+// and a short-range part falling off as erfc(r / 2 r_s), neglected beyond
+// `cutoff`. The short-range force is the softened force of compute_gravity
+// minus the long-range one, so that both parts always add up to it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ForceSplit {
+    pub scale: f64,
+    pub cutoff: f64,
+    pub softening: Softening,
+}
+
+impl ForceSplit {
+    // Magnitude of the long-range force between unit masses at distance r,
+    // over G
+    pub(crate) fn long_range(&self, r: f64) -> f64 {
+        let x = r / (2.0 * self.scale);
+        let c = 2.0 / PI.sqrt();
+        if x < 1e-2 {
+            // erf(x) - 2x/sqrt(pi) exp(-x^2) cancels to O(x^3) near zero
+            let x2 = x * x;
+            c * x * x2 * (2.0 / 3.0 - 0.4 * x2 + x2 * x2 / 7.0) / (r * r)
+        } else {
+            (libm::erf(x) - c * x * (-x * x).exp()) / (r * r)
+        }
+    }
 
-    let force_mag = GRAVIT_CONST * p1.mass * p2.mass / dist_sq;
+    // Short-range force on a mass m1 from a mass m2 at separation d = x2 - x1,
+    // whatever the distance, for a pair with the given softening length
+    pub fn short_range_force(
+        &self,
+        m1: f64,
+        m2: f64,
+        d: [f64; 2],
+        dist_sq: f64,
+        length: f64,
+    ) -> [f64; 2] {
+        if dist_sq == 0.0 {
+            return [0.0, 0.0];
+        }
+        let gravity = Gravity::with_softening(self.softening);
+        let dist = dist_sq.sqrt();
+        let total = gravity.force_factor(dist_sq, length);
+        let force_mag = m1 * m2 * (total - gravity.constant * self.long_range(dist) / dist);
+        [force_mag * d[0], force_mag * d[1]]
+    }
+}
 
-    let dist = dist_sq.sqrt();
-    let unit_dx = dx / dist;
-    let unit_dy = dy / dist;
-    [force_mag * unit_dx, force_mag * unit_dy]
+// Separation to the nearest periodic image, for a box of the given side lengths
+pub fn minimum_image(d: [f64; 2], period: Option<[f64; 2]>) -> [f64; 2] {
+    match period {
+        Some(period) => [
+            d[0] - period[0] * (d[0] / period[0]).round(),
+            d[1] - period[1] * (d[1] / period[1]).round(),
+        ],
+        None => d,
+    }
 }
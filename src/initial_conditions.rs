@@ -9,6 +9,8 @@ pub fn generate_random_particles(n: usize) -> Vec<Particle> {
             position: [rng.gen_range(0.0..1500.0), rng.gen_range(0.0..900.0)],
             velocity: [rng.gen_range(-100.0..100.0), rng.gen_range(-100.0..100.0)],
             mass: rng.gen_range(10.0..100.0),
+            charge: 0.0,
+            softening: 0.0,
         })
         .collect()
 }
@@ -23,6 +25,8 @@ pub fn generate_random_particles_around_attractor(n: usize) -> Vec<Particle> {
         position: attractor_position,
         velocity: [0.0, 0.0],
         mass: attractor_mass,
+        charge: 0.0,
+        softening: 0.0,
     };
 
     for _ in 0..n - 1 {
@@ -49,6 +53,8 @@ pub fn generate_random_particles_around_attractor(n: usize) -> Vec<Particle> {
             position,
             velocity,
             mass,
+            charge: 0.0,
+            softening: 0.0,
         });
     }
 
@@ -56,3 +62,47 @@ pub fn generate_random_particles_around_attractor(n: usize) -> Vec<Particle> {
 
     particles
 }
+
+// Neutral plasma: alternating charges of +1 and -1 spread over the same box as
+// the random particles, with thermal velocities
+pub fn generate_neutral_plasma(n: usize) -> Vec<Particle> {
+    let mut rng = rand::thread_rng();
+    (0..n)
+        .map(|i| Particle {
+            position: [rng.gen_range(0.0..1500.0), rng.gen_range(0.0..900.0)],
+            velocity: [rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0)],
+            mass: 1.0,
+            charge: if i % 2 == 0 { 1.0 } else { -1.0 },
+            softening: 0.0,
+        })
+        .collect()
+}
+
+// Square lattice of side x side unit masses, `spacing` apart and filling the
+// box [0, side spacing]^2, with random velocities of up to `speed` along each
+// axis and no net momentum
+pub fn generate_square_lattice(side: usize, spacing: f64, speed: f64) -> Vec<Particle> {
+    let mut rng = rand::thread_rng();
+    let mut particles: Vec<Particle> = (0..side * side)
+        .map(|i| Particle {
+            position: [
+                ((i % side) as f64 + 0.5) * spacing,
+                ((i / side) as f64 + 0.5) * spacing,
+            ],
+            velocity: [rng.gen_range(-speed..=speed), rng.gen_range(-speed..=speed)],
+            mass: 1.0,
+            charge: 0.0,
+            softening: 0.0,
+        })
+        .collect();
+
+    let n = particles.len().max(1) as f64;
+    let mean = particles.iter().fold([0.0, 0.0], |m, p| {
+        [m[0] + p.velocity[0] / n, m[1] + p.velocity[1] / n]
+    });
+    for p in particles.iter_mut() {
+        p.velocity[0] -= mean[0];
+        p.velocity[1] -= mean[1];
+    }
+    particles
+}
@@ -1,47 +1,708 @@
+use crate::bonds::{add_bond_forces, Bond};
+use crate::error::ConfigError;
+use crate::ewald::period;
 use crate::particle::Particle;
+use crate::simulation::ForceSolver;
+use rayon::prelude::*;
 
-pub const EULER: i32 = 0;
-pub const LEAPFROG: i32 = 1;
-pub const MIDPOINT: i32 = 2;
+pub trait Integrator: Send + Sync {
+    fn step(&self, particles: &mut [Particle], forces: &mut ForceEvaluator, dt: f64);
 
-pub fn time_integration(particle: &mut Particle, force: &[f64; 2], dt: f64, integrator_type: i32) {
-    if integrator_type == EULER {
-        euler_step(particle, *force, dt);
-    } else if integrator_type == LEAPFROG {
-        leapfrog_step(particle, *force, dt);
-    } else if integrator_type == MIDPOINT {
-        midpoint_step(particle, *force, dt);
+    // Integrators that estimate their own accuracy override this to report it
+    fn step_with_report(
+        &self,
+        particles: &mut [Particle],
+        forces: &mut ForceEvaluator,
+        dt: f64,
+    ) -> StepReport {
+        self.step(particles, forces, dt);
+        StepReport::default()
+    }
+
+    // Integrators needing the time derivative of the forces, which only some
+    // solvers provide
+    fn requires_jerk(&self) -> bool {
+        false
+    }
+
+    // Integrators solving Keplerian motion exactly, which need the
+    // gravitational constant of the solver
+    fn requires_gravity(&self) -> bool {
+        false
+    }
+
+    // Checks settings that depend on the number of particles, such as indices
+    fn validate(&self, _n: usize) -> Result<(), ConfigError> {
+        Ok(())
     }
 }
 
-fn euler_step(p: &mut Particle, force: [f64; 2], dt: f64) {
-    p.velocity[0] += force[0] / p.mass * dt;
-    p.velocity[1] += force[1] / p.mass * dt;
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StepReport {
+    // Scaled local error of the step, values below one are within tolerance
+    pub error: Option<f64>,
+    // Time step the integrator would like to take next
+    pub suggested_dt: Option<f64>,
+}
 
-    p.position[0] += p.velocity[0] * dt;
-    p.position[1] += p.velocity[1] * dt;
+// Gives integrators access to the active solver during a step. Forces are cached
+// and only recomputed when the particles have drifted since the last evaluation,
+// so that damped bonds see the velocities of that evaluation.
+pub struct ForceEvaluator<'a> {
+    solver: &'a mut dyn ForceSolver,
+    forces: &'a mut [[f64; 2]],
+    up_to_date: &'a mut bool,
+    jerks: Option<(&'a mut [[f64; 2]], &'a mut bool)>,
+    bonds: &'a [Bond],
+    overstretched_bonds: Option<&'a mut usize>,
 }
 
-fn midpoint_step(p: &mut Particle, force: [f64; 2], dt: f64) {
-    let half_step = 0.5 * dt;
-    let mid_velocity = [
-        p.velocity[0] + force[0] * half_step / p.mass,
-        p.velocity[1] + force[1] * half_step / p.mass,
-    ];
+impl<'a> ForceEvaluator<'a> {
+    pub fn new(
+        solver: &'a mut dyn ForceSolver,
+        forces: &'a mut [[f64; 2]],
+        up_to_date: &'a mut bool,
+    ) -> Self {
+        ForceEvaluator {
+            solver,
+            forces,
+            up_to_date,
+            jerks: None,
+            bonds: &[],
+            overstretched_bonds: None,
+        }
+    }
+
+    // Adds the forces of bonds between the particles to those of the solver,
+    // and keeps the number of overstretched bonds at the last evaluation.
+    // Bonds have no jerk, so integrators needing one cannot be used with them.
+    pub fn with_bonds(mut self, bonds: &'a [Bond], overstretched: &'a mut usize) -> Self {
+        self.bonds = bonds;
+        self.overstretched_bonds = Some(overstretched);
+        self
+    }
+
+    fn set_overstretched_bonds(&mut self, count: usize) {
+        if let Some(overstretched) = self.overstretched_bonds.as_mut() {
+            **overstretched = count;
+        }
+    }
+
+    // Bonds join the nearest images in a periodic box
+    fn period(&self) -> Option<[f64; 2]> {
+        self.solver.periodic_domain().map(period)
+    }
+
+    // Adds a cache for the force derivatives, which depend on velocities as well
+    pub fn with_jerks(mut self, jerks: &'a mut [[f64; 2]], up_to_date: &'a mut bool) -> Self {
+        self.jerks = Some((jerks, up_to_date));
+        self
+    }
+
+    pub fn forces_and_jerks(&mut self, particles: &[Particle]) -> (&[[f64; 2]], &[[f64; 2]]) {
+        let period = self.period();
+        let (jerks, jerks_up_to_date) = self
+            .jerks
+            .as_mut()
+            .expect("No jerk buffer given to the force evaluator");
+        if !**jerks_up_to_date {
+            self.solver
+                .compute_forces_and_jerks(particles, self.forces, jerks);
+            **jerks_up_to_date = true;
+            let count = add_bond_forces(self.bonds, particles, period, self.forces);
+            if let Some(overstretched) = self.overstretched_bonds.as_mut() {
+                **overstretched = count;
+            }
+            *self.up_to_date = true;
+        }
+        (self.forces, jerks)
+    }
+
+    pub fn forces(&mut self, particles: &[Particle]) -> &[[f64; 2]] {
+        if !*self.up_to_date {
+            self.solver.compute_forces(particles, self.forces);
+            let count = add_bond_forces(self.bonds, particles, self.period(), self.forces);
+            self.set_overstretched_bonds(count);
+            *self.up_to_date = true;
+        }
+        self.forces
+    }
+
+    // Forces from the last evaluation, whether or not the particles moved since
+    pub fn last_forces(&self) -> &[[f64; 2]] {
+        self.forces
+    }
+
+    // Recomputes the forces of the `active` particles only, the cache is
+    // considered up to date again once every particle has been updated
+    pub fn update_active(&mut self, particles: &[Particle], active: &[usize]) {
+        self.solver
+            .compute_active_forces(particles, active, self.forces);
+        if !self.bonds.is_empty() {
+            let mut bond_forces = vec![[0.0, 0.0]; particles.len()];
+            let count = add_bond_forces(self.bonds, particles, self.period(), &mut bond_forces);
+            self.set_overstretched_bonds(count);
+            for &i in active {
+                self.forces[i][0] += bond_forces[i][0];
+                self.forces[i][1] += bond_forces[i][1];
+            }
+        }
+        if active.len() == particles.len() {
+            *self.up_to_date = true;
+        }
+    }
+
+    // Runs the solver on another set of particles, such as a subsystem, without
+    // touching the cache. Bonds, which refer to the particles of the
+    // simulation, are left out, see `add_bond_forces`.
+    pub fn compute_subsystem_forces(&mut self, particles: &[Particle], forces: &mut [[f64; 2]]) {
+        self.solver.compute_forces(particles, forces);
+    }
+
+    // Adds the forces of the bonds to `forces`, for integrators splitting the
+    // solver's forces into parts
+    pub fn add_bond_forces(&mut self, particles: &[Particle], forces: &mut [[f64; 2]]) {
+        let count = add_bond_forces(self.bonds, particles, self.period(), forces);
+        self.set_overstretched_bonds(count);
+    }
+
+    pub fn gravitational_constant(&self) -> Option<f64> {
+        self.solver.gravitational_constant()
+    }
+
+    // Must be called by integrators that move particles by hand
+    pub fn invalidate(&mut self) {
+        *self.up_to_date = false;
+        self.invalidate_jerks();
+    }
+
+    fn invalidate_jerks(&mut self) {
+        if let Some((_, jerks_up_to_date)) = self.jerks.as_mut() {
+            **jerks_up_to_date = false;
+        }
+    }
+
+    pub fn kick(&mut self, particles: &mut [Particle], dt: f64) {
+        let forces = self.forces(particles);
+        particles
+            .par_iter_mut()
+            .zip(forces.par_iter())
+            .for_each(|(p, force)| {
+                p.velocity[0] += force[0] * dt / p.mass;
+                p.velocity[1] += force[1] * dt / p.mass;
+            });
+        self.invalidate_jerks();
+    }
 
-    p.position[0] += mid_velocity[0] * dt;
-    p.position[1] += mid_velocity[1] * dt;
-    p.velocity[0] += force[0] * dt / p.mass;
-    p.velocity[1] += force[1] * dt / p.mass;
+    pub fn drift(&mut self, particles: &mut [Particle], dt: f64) {
+        particles.par_iter_mut().for_each(|p| {
+            p.position[0] += p.velocity[0] * dt;
+            p.position[1] += p.velocity[1] * dt;
+        });
+        self.invalidate();
+    }
+}
+
+// Semi-implicit (symplectic) Euler
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Euler;
+
+// Explicit midpoint method (RK2), with the force re-evaluated at the half step
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Midpoint;
+
+// Kick-drift-kick leapfrog, one force evaluation per step
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LeapfrogKdk;
+
+// Drift-kick-drift leapfrog, one force evaluation per step
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LeapfrogDkd;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VelocityVerlet;
+
+impl Integrator for Euler {
+    fn step(&self, particles: &mut [Particle], forces: &mut ForceEvaluator, dt: f64) {
+        forces.kick(particles, dt);
+        forces.drift(particles, dt);
+    }
 }
 
-fn leapfrog_step(p: &mut Particle, force: [f64; 2], dt: f64) {
-    p.velocity[0] += force[0] * dt / (2.0 * p.mass);
-    p.velocity[1] += force[1] * dt / (2.0 * p.mass);
+impl Integrator for Midpoint {
+    fn step(&self, particles: &mut [Particle], forces: &mut ForceEvaluator, dt: f64) {
+        let start: Vec<Particle> = particles.to_vec();
+        let start_forces: Vec<[f64; 2]> = forces.forces(particles).to_vec();
 
-    p.position[0] += p.velocity[0] * dt;
-    p.position[1] += p.velocity[1] * dt;
+        forces.drift(particles, 0.5 * dt);
+        let mid_forces = forces.forces(particles);
+
+        particles
+            .par_iter_mut()
+            .zip(start.par_iter())
+            .zip(start_forces.par_iter().zip(mid_forces.par_iter()))
+            .for_each(|((p, p0), (force, mid_force))| {
+                let mid_velocity = [
+                    p0.velocity[0] + force[0] * 0.5 * dt / p.mass,
+                    p0.velocity[1] + force[1] * 0.5 * dt / p.mass,
+                ];
+                p.position[0] = p0.position[0] + mid_velocity[0] * dt;
+                p.position[1] = p0.position[1] + mid_velocity[1] * dt;
+                p.velocity[0] = p0.velocity[0] + mid_force[0] * dt / p.mass;
+                p.velocity[1] = p0.velocity[1] + mid_force[1] * dt / p.mass;
+            });
+        forces.invalidate();
+    }
+}
+
+impl Integrator for LeapfrogKdk {
+    fn step(&self, particles: &mut [Particle], forces: &mut ForceEvaluator, dt: f64) {
+        forces.kick(particles, 0.5 * dt);
+        forces.drift(particles, dt);
+        forces.kick(particles, 0.5 * dt);
+    }
+}
+
+impl Integrator for LeapfrogDkd {
+    fn step(&self, particles: &mut [Particle], forces: &mut ForceEvaluator, dt: f64) {
+        forces.drift(particles, 0.5 * dt);
+        forces.kick(particles, dt);
+        forces.drift(particles, 0.5 * dt);
+    }
+}
+
+impl Integrator for VelocityVerlet {
+    fn step(&self, particles: &mut [Particle], forces: &mut ForceEvaluator, dt: f64) {
+        let old_forces: Vec<[f64; 2]> = forces.forces(particles).to_vec();
+
+        // x(t + dt) = x(t) + v(t) dt + a(t) dt^2 / 2
+        particles
+            .par_iter_mut()
+            .zip(old_forces.par_iter())
+            .for_each(|(p, force)| {
+                p.position[0] += p.velocity[0] * dt + 0.5 * force[0] / p.mass * dt * dt;
+                p.position[1] += p.velocity[1] * dt + 0.5 * force[1] / p.mass * dt * dt;
+            });
+        forces.invalidate();
+
+        // v(t + dt) = v(t) + (a(t) + a(t + dt)) dt / 2
+        let new_forces = forces.forces(particles);
+        particles
+            .par_iter_mut()
+            .zip(old_forces.par_iter().zip(new_forces.par_iter()))
+            .for_each(|(p, (old_force, new_force))| {
+                p.velocity[0] += 0.5 * (old_force[0] + new_force[0]) / p.mass * dt;
+                p.velocity[1] += 0.5 * (old_force[1] + new_force[1]) / p.mass * dt;
+            });
+    }
+}
+
+// Symmetric composition of a second-order base step with substeps w_i * dt.
+// With a KDK base, consecutive half kicks reuse the cached force, so each
+// substep costs a single force evaluation.
+#[derive(Debug, Clone)]
+pub struct Composition<I: Integrator> {
+    base: I,
+    weights: Vec<f64>,
+}
+
+impl<I: Integrator> Composition<I> {
+    pub fn new(base: I, weights: Vec<f64>) -> Result<Self, ConfigError> {
+        let sum: f64 = weights.iter().sum();
+        if weights.is_empty() || !sum.is_finite() || (sum - 1.0).abs() > 1e-12 {
+            return Err(ConfigError::InvalidCompositionWeights(sum));
+        }
+        Ok(Composition { base, weights })
+    }
+
+    pub fn weights(&self) -> &[f64] {
+        &self.weights
+    }
+}
+
+// Triple jump weights raising a symmetric scheme of order 2n to order 2n + 2
+fn triple_jump(order: i32) -> [f64; 3] {
+    let k = 2.0_f64.powf(1.0 / (order as f64 + 1.0));
+    let w1 = 1.0 / (2.0 - k);
+    let w0 = -k / (2.0 - k);
+    [w1, w0, w1]
+}
+
+impl Composition<LeapfrogDkd> {
+    // Forest & Ruth (1990), 4th order, position-Verlet based
+    pub fn forest_ruth() -> Self {
+        Composition {
+            base: LeapfrogDkd,
+            weights: triple_jump(2).to_vec(),
+        }
+    }
+}
 
-    p.velocity[0] += force[0] * dt / (2.0 * p.mass);
-    p.velocity[1] += force[1] * dt / (2.0 * p.mass);
+impl Composition<LeapfrogKdk> {
+    // Yoshida (1990), 4th order, velocity-Verlet based
+    pub fn yoshida4() -> Self {
+        Composition {
+            base: LeapfrogKdk,
+            weights: triple_jump(2).to_vec(),
+        }
+    }
+
+    // Yoshida (1990), 6th order, solution A
+    pub fn yoshida6() -> Self {
+        let w1 = -1.177_679_984_178_87;
+        let w2 = 0.235_573_213_359_357;
+        let w3 = 0.784_513_610_477_560;
+        let w0 = 1.0 - 2.0 * (w1 + w2 + w3);
+        Composition {
+            base: LeapfrogKdk,
+            weights: vec![w3, w2, w1, w0, w1, w2, w3],
+        }
+    }
+}
+
+impl<I: Integrator> Integrator for Composition<I> {
+    fn step(&self, particles: &mut [Particle], forces: &mut ForceEvaluator, dt: f64) {
+        for w in self.weights.iter() {
+            self.base.step(particles, forces, w * dt);
+        }
+    }
+
+    fn requires_jerk(&self) -> bool {
+        self.base.requires_jerk()
+    }
+
+    fn requires_gravity(&self) -> bool {
+        self.base.requires_gravity()
+    }
+
+    fn validate(&self, n: usize) -> Result<(), ConfigError> {
+        self.base.validate(n)
+    }
+}
+
+// Classic fourth-order Runge-Kutta on the phase space state (x, v)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RungeKutta4;
+
+// Dormand-Prince 5(4) embedded pair. The error is measured per component against
+// atol + rtol * |y| and reduced to an RMS norm over the whole state vector.
+#[derive(Debug, Clone, Copy)]
+pub struct DormandPrince {
+    atol: f64,
+    rtol: f64,
+}
+
+impl DormandPrince {
+    pub fn new(atol: f64, rtol: f64) -> Result<Self, ConfigError> {
+        if !(atol >= 0.0 && rtol >= 0.0 && atol + rtol > 0.0) {
+            return Err(ConfigError::InvalidTolerance(atol, rtol));
+        }
+        Ok(DormandPrince { atol, rtol })
+    }
+
+    pub fn atol(&self) -> f64 {
+        self.atol
+    }
+
+    pub fn rtol(&self) -> f64 {
+        self.rtol
+    }
+}
+
+impl Default for DormandPrince {
+    fn default() -> Self {
+        DormandPrince {
+            atol: 1e-6,
+            rtol: 1e-6,
+        }
+    }
+}
+
+// Time derivative (v, F / m) of the state of each particle
+fn derivatives(particles: &[Particle], forces: &mut ForceEvaluator) -> Vec<[f64; 4]> {
+    let f = forces.forces(particles);
+    particles
+        .par_iter()
+        .zip(f.par_iter())
+        .map(|(p, force)| {
+            [
+                p.velocity[0],
+                p.velocity[1],
+                force[0] / p.mass,
+                force[1] / p.mass,
+            ]
+        })
+        .collect()
+}
+
+// Sets particles to start + dt * sum_j coeffs[j] * stages[j]
+fn set_stage(
+    particles: &mut [Particle],
+    start: &[Particle],
+    stages: &[Vec<[f64; 4]>],
+    coeffs: &[f64],
+    dt: f64,
+) {
+    particles.par_iter_mut().enumerate().for_each(|(i, p)| {
+        let p0 = &start[i];
+        let mut delta = [0.0; 4];
+        for (k, c) in stages.iter().zip(coeffs) {
+            for d in 0..4 {
+                delta[d] += c * k[i][d];
+            }
+        }
+        p.position[0] = p0.position[0] + dt * delta[0];
+        p.position[1] = p0.position[1] + dt * delta[1];
+        p.velocity[0] = p0.velocity[0] + dt * delta[2];
+        p.velocity[1] = p0.velocity[1] + dt * delta[3];
+    });
+}
+
+// Evaluates the stages of an explicit Runge-Kutta tableau given by its rows a[i]
+fn explicit_stages(
+    particles: &mut [Particle],
+    start: &[Particle],
+    forces: &mut ForceEvaluator,
+    a: &[&[f64]],
+    dt: f64,
+) -> Vec<Vec<[f64; 4]>> {
+    let mut stages = vec![derivatives(particles, forces)];
+    for row in a.iter() {
+        set_stage(particles, start, &stages, row, dt);
+        forces.invalidate();
+        stages.push(derivatives(particles, forces));
+    }
+    stages
+}
+
+impl Integrator for RungeKutta4 {
+    fn step(&self, particles: &mut [Particle], forces: &mut ForceEvaluator, dt: f64) {
+        let start = particles.to_vec();
+        let stages = explicit_stages(
+            particles,
+            &start,
+            forces,
+            &[&[0.5], &[0.0, 0.5], &[0.0, 0.0, 1.0]],
+            dt,
+        );
+        set_stage(
+            particles,
+            &start,
+            &stages,
+            &[1.0 / 6.0, 1.0 / 3.0, 1.0 / 3.0, 1.0 / 6.0],
+            dt,
+        );
+        forces.invalidate();
+    }
+}
+
+const DOPRI_A: [&[f64]; 6] = [
+    &[1.0 / 5.0],
+    &[3.0 / 40.0, 9.0 / 40.0],
+    &[44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0],
+    &[
+        19372.0 / 6561.0,
+        -25360.0 / 2187.0,
+        64448.0 / 6561.0,
+        -212.0 / 729.0,
+    ],
+    &[
+        9017.0 / 3168.0,
+        -355.0 / 33.0,
+        46732.0 / 5247.0,
+        49.0 / 176.0,
+        -5103.0 / 18656.0,
+    ],
+    // Last row equals the 5th order weights, so the final stage is evaluated at
+    // the new state and reused as the first stage of the next step
+    &DOPRI_B5,
+];
+
+const DOPRI_B5: [f64; 6] = [
+    35.0 / 384.0,
+    0.0,
+    500.0 / 1113.0,
+    125.0 / 192.0,
+    -2187.0 / 6784.0,
+    11.0 / 84.0,
+];
+
+// Difference between the 5th and embedded 4th order weights
+const DOPRI_E: [f64; 7] = [
+    35.0 / 384.0 - 5179.0 / 57600.0,
+    0.0,
+    500.0 / 1113.0 - 7571.0 / 16695.0,
+    125.0 / 192.0 - 393.0 / 640.0,
+    -2187.0 / 6784.0 + 92097.0 / 339200.0,
+    11.0 / 84.0 - 187.0 / 2100.0,
+    -1.0 / 40.0,
+];
+
+impl Integrator for DormandPrince {
+    fn step(&self, particles: &mut [Particle], forces: &mut ForceEvaluator, dt: f64) {
+        self.step_with_report(particles, forces, dt);
+    }
+
+    fn step_with_report(
+        &self,
+        particles: &mut [Particle],
+        forces: &mut ForceEvaluator,
+        dt: f64,
+    ) -> StepReport {
+        let start = particles.to_vec();
+        // The particles are left at the 5th order solution by the last stage
+        let stages = explicit_stages(particles, &start, forces, &DOPRI_A, dt);
+
+        let squares: Vec<f64> = particles
+            .par_iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let p0 = &start[i];
+                let old = [
+                    p0.position[0],
+                    p0.position[1],
+                    p0.velocity[0],
+                    p0.velocity[1],
+                ];
+                let new = [p.position[0], p.position[1], p.velocity[0], p.velocity[1]];
+                let mut sum = 0.0;
+                for d in 0..4 {
+                    let mut err = 0.0;
+                    for (k, e) in stages.iter().zip(DOPRI_E.iter()) {
+                        err += e * k[i][d];
+                    }
+                    let scale = self.atol + self.rtol * old[d].abs().max(new[d].abs());
+                    sum += (dt * err / scale).powi(2);
+                }
+                sum
+            })
+            .collect();
+        let sum_sq: f64 = squares.iter().sum();
+
+        StepReport {
+            error: Some((sum_sq / (4 * particles.len().max(1)) as f64).sqrt()),
+            suggested_dt: None,
+        }
+    }
+}
+
+// Fourth-order Hermite predictor-corrector (Makino & Aarseth 1992), for solvers
+// computing jerks. As usual for P(EC) Hermite, the forces evaluated at the
+// predicted state are reused at the start of the next step. The next time step
+// is suggested from the Aarseth criterion with accuracy parameter `eta`.
+#[derive(Debug, Clone, Copy)]
+pub struct Hermite {
+    eta: f64,
+}
+
+impl Hermite {
+    pub fn new(eta: f64) -> Result<Self, ConfigError> {
+        if !(eta > 0.0 && eta.is_finite()) {
+            return Err(ConfigError::InvalidAccuracyParameter(eta));
+        }
+        Ok(Hermite { eta })
+    }
+
+    pub fn eta(&self) -> f64 {
+        self.eta
+    }
+}
+
+impl Default for Hermite {
+    fn default() -> Self {
+        Hermite { eta: 0.02 }
+    }
+}
+
+fn norm(v: [f64; 2]) -> f64 {
+    (v[0] * v[0] + v[1] * v[1]).sqrt()
+}
+
+impl Integrator for Hermite {
+    fn step(&self, particles: &mut [Particle], forces: &mut ForceEvaluator, dt: f64) {
+        self.step_with_report(particles, forces, dt);
+    }
+
+    fn step_with_report(
+        &self,
+        particles: &mut [Particle],
+        forces: &mut ForceEvaluator,
+        dt: f64,
+    ) -> StepReport {
+        let start = particles.to_vec();
+        let (start_forces, start_jerks) = {
+            let (f, j) = forces.forces_and_jerks(particles);
+            (f.to_vec(), j.to_vec())
+        };
+
+        // Predictor, Taylor expansion to the jerk
+        particles
+            .par_iter_mut()
+            .zip(start_forces.par_iter().zip(start_jerks.par_iter()))
+            .for_each(|(p, (force, jerk))| {
+                for d in 0..2 {
+                    let a = force[d] / p.mass;
+                    let j = jerk[d] / p.mass;
+                    p.position[d] +=
+                        p.velocity[d] * dt + a * dt * dt / 2.0 + j * dt * dt * dt / 6.0;
+                    p.velocity[d] += a * dt + j * dt * dt / 2.0;
+                }
+            });
+        forces.invalidate();
+
+        // Corrector, using the forces and jerks at the predicted state
+        let (end_forces, end_jerks) = forces.forces_and_jerks(particles);
+        let suggested_dt = particles
+            .par_iter_mut()
+            .enumerate()
+            .map(|(i, p)| {
+                let p0 = &start[i];
+                let mut a0 = [0.0; 2];
+                let mut a1 = [0.0; 2];
+                let mut j0 = [0.0; 2];
+                let mut j1 = [0.0; 2];
+                for d in 0..2 {
+                    a0[d] = start_forces[i][d] / p.mass;
+                    a1[d] = end_forces[i][d] / p.mass;
+                    j0[d] = start_jerks[i][d] / p.mass;
+                    j1[d] = end_jerks[i][d] / p.mass;
+                }
+
+                let mut snap = [0.0; 2];
+                let mut crackle = [0.0; 2];
+                for d in 0..2 {
+                    p.velocity[d] = p0.velocity[d]
+                        + (a0[d] + a1[d]) * dt / 2.0
+                        + (j0[d] - j1[d]) * dt * dt / 12.0;
+                    p.position[d] = p0.position[d]
+                        + (p0.velocity[d] + p.velocity[d]) * dt / 2.0
+                        + (a0[d] - a1[d]) * dt * dt / 12.0;
+
+                    // Higher derivatives from the Hermite interpolant, at the end of the step
+                    crackle[d] = (12.0 * (a0[d] - a1[d]) + 6.0 * dt * (j0[d] + j1[d])) / dt.powi(3);
+                    snap[d] = (-6.0 * (a0[d] - a1[d]) - dt * (4.0 * j0[d] + 2.0 * j1[d]))
+                        / (dt * dt)
+                        + dt * crackle[d];
+                }
+
+                // Aarseth criterion
+                let numerator = norm(a1) * norm(snap) + norm(j1).powi(2);
+                let denominator = norm(j1) * norm(crackle) + norm(snap).powi(2);
+                if denominator > 0.0 {
+                    (self.eta * numerator / denominator).sqrt()
+                } else {
+                    f64::INFINITY
+                }
+            })
+            .reduce(|| f64::INFINITY, f64::min);
+
+        StepReport {
+            error: None,
+            suggested_dt: suggested_dt.is_finite().then_some(suggested_dt),
+        }
+    }
+
+    fn requires_jerk(&self) -> bool {
+        true
+    }
 }
@@ -0,0 +1,38 @@
+// Project: ParticleSimRust
+// A simple gravity simulation using the Barnes-Hut algorithm
+// Time unit : 1 year
+// Distance unit : 1 AU
+// Mass unit : 1 solar mass
+// Gravitational constant : 4 * pi^2
+// -------------------------------------
+// Author: Maxime Renault, 2024
+
+pub mod block_timestep;
+pub mod bonds;
+pub mod diagnostics;
+pub mod error;
+pub mod ewald;
+pub mod fmm;
+pub mod forces;
+pub mod initial_conditions;
+pub mod integrator;
+pub mod linear_tree;
+pub mod neighbour_list;
+pub mod pair_force;
+pub mod particle;
+pub mod pm;
+pub mod quadtree;
+pub mod reversibility;
+pub mod simulation;
+pub mod softening;
+pub mod timestep;
+pub mod wisdom_holman;
+
+pub use crate::diagnostics::Diagnostics;
+pub use crate::error::ConfigError;
+pub use crate::integrator::Integrator;
+pub use crate::pair_force::PairForce;
+pub use crate::particle::Particle;
+pub use crate::quadtree::QuadTree;
+pub use crate::simulation::{ForceSolver, Simulation};
+pub use crate::softening::Softening;
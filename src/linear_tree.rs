@@ -0,0 +1,584 @@
+use crate::ewald::EwaldTable;
+use crate::forces::{minimum_image, ForceSplit};
+use crate::pair_force::{Coupling, PairForce};
+use crate::particle::Particle;
+use crate::quadtree::{
+    add_shifted_moments, bucket_moments, pair_force, separation, Cell, TreeLimits, TreeWalk,
+};
+use rayon::prelude::*;
+
+// Levels of the tree, limited by the 32 bits per coordinate of the Morton keys
+const MAX_LEVEL: u32 = TreeLimits::MAX_DEPTH;
+// Subtrees with fewer particles than this are built on a single thread
+const PARALLEL_THRESHOLD: usize = 4096;
+// Buckets with fewer keys than this are sorted by comparison
+const RADIX_SORT_THRESHOLD: usize = 64;
+
+// Node of the linear tree. Nodes are stored in depth-first order, the children
+// of a node (only the non-empty ones) following it directly, so that a tree
+// walk is a single loop over the array: `skip` is the number of nodes in the
+// subtree of the node, itself included, and jumping by `skip` moves on to the
+// next node once the node is accepted.
+#[derive(Debug, Clone, Copy)]
+pub struct LinearNode {
+    pub boundary: [f64; 4], // [x_min, y_min, x_max, y_max]
+    pub mass: f64,
+    pub abs_mass: f64,
+    pub center_of_mass: [f64; 2],
+    pub dipole: [f64; 2],
+    pub second_moment: [f64; 3],
+    pub third_moment: [f64; 4],
+    // Largest softening length of the particles, for adaptive softening
+    pub max_softening: f64,
+    // Range of the node in the particles sorted by Morton key
+    pub start: usize,
+    pub end: usize,
+    pub skip: usize,
+}
+
+impl LinearNode {
+    pub fn is_leaf(&self) -> bool {
+        self.skip == 1
+    }
+}
+
+impl Cell for LinearNode {
+    fn boundary(&self) -> [f64; 4] {
+        self.boundary
+    }
+
+    fn mass(&self) -> f64 {
+        self.mass
+    }
+
+    fn abs_mass(&self) -> f64 {
+        self.abs_mass
+    }
+
+    fn center_of_mass(&self) -> [f64; 2] {
+        self.center_of_mass
+    }
+
+    fn dipole(&self) -> [f64; 2] {
+        self.dipole
+    }
+
+    fn second_moment(&self) -> [f64; 3] {
+        self.second_moment
+    }
+
+    fn third_moment(&self) -> [f64; 4] {
+        self.third_moment
+    }
+
+    fn max_softening(&self) -> f64 {
+        self.max_softening
+    }
+}
+
+// Array-backed quadtree built from the particles sorted along the Z-order curve,
+// so that the particles of any node are contiguous. Children are ordered as in
+// QuadTree: [bottom left, bottom right, top left, top right], and node masses,
+// centres and moments are those of the coupling the tree is built with, as in
+// QuadTree.
+#[derive(Debug, Clone)]
+pub struct LinearQuadTree {
+    pub nodes: Vec<LinearNode>,
+    pub particles: Vec<Particle>,
+    // Index of each sorted particle in the original slice
+    pub order: Vec<usize>,
+    // Particles outside of the boundary, left out of the tree
+    pub dropped: usize,
+}
+
+// Z-order key of a position inside the boundary, the two bits of each level
+// being (y, x) from the most significant end. The bits come from the same
+// midpoint comparisons as QuadTree, so that both trees put particles lying on
+// the edge of a cell on the same side.
+pub fn morton_key(position: [f64; 2], boundary: [f64; 4]) -> u64 {
+    let [mut x_min, mut y_min, mut x_max, mut y_max] = boundary;
+    let mut key = 0;
+    for _ in 0..MAX_LEVEL {
+        let mid_x = (x_min + x_max) / 2.0;
+        let mid_y = (y_min + y_max) / 2.0;
+        let right = position[0] >= mid_x;
+        let top = position[1] >= mid_y;
+        key = (key << 2) | ((top as u64) << 1) | (right as u64);
+        if right {
+            x_min = mid_x;
+        } else {
+            x_max = mid_x;
+        }
+        if top {
+            y_min = mid_y;
+        } else {
+            y_max = mid_y;
+        }
+    }
+    key
+}
+
+// Sorts (key, index) pairs by key, most significant byte first. Each pass
+// scatters a bucket into 256 sub-buckets which are then sorted in parallel.
+pub fn par_radix_sort(items: &mut [(u64, usize)]) {
+    let mut buffer = items.to_vec();
+    radix_sort_pass(items, &mut buffer, 56);
+}
+
+fn radix_sort_pass(items: &mut [(u64, usize)], buffer: &mut [(u64, usize)], shift: u32) {
+    if items.len() < RADIX_SORT_THRESHOLD {
+        items.sort_unstable_by_key(|item| item.0);
+        return;
+    }
+
+    let digit = |key: u64| ((key >> shift) & 0xFF) as usize;
+    let counts = if items.len() >= PARALLEL_THRESHOLD {
+        items
+            .par_chunks(PARALLEL_THRESHOLD)
+            .map(|chunk| {
+                let mut counts = [0usize; 256];
+                for item in chunk {
+                    counts[digit(item.0)] += 1;
+                }
+                counts
+            })
+            .reduce(
+                || [0usize; 256],
+                |mut a, b| {
+                    for (a, b) in a.iter_mut().zip(b.iter()) {
+                        *a += b;
+                    }
+                    a
+                },
+            )
+    } else {
+        let mut counts = [0usize; 256];
+        for item in items.iter() {
+            counts[digit(item.0)] += 1;
+        }
+        counts
+    };
+
+    let mut offsets = [0usize; 256];
+    for d in 1..256 {
+        offsets[d] = offsets[d - 1] + counts[d - 1];
+    }
+    let mut next = offsets;
+    for item in items.iter() {
+        let d = digit(item.0);
+        buffer[next[d]] = *item;
+        next[d] += 1;
+    }
+    items.copy_from_slice(buffer);
+    if shift == 0 {
+        return;
+    }
+
+    // Split both slices into the buckets, which are independent from now on
+    let mut buckets = Vec::with_capacity(256);
+    let (mut items_rest, mut buffer_rest) = (items, buffer);
+    for &count in counts.iter() {
+        let (bucket_items, items_tail) = items_rest.split_at_mut(count);
+        let (bucket_buffer, buffer_tail) = buffer_rest.split_at_mut(count);
+        if count > 1 {
+            buckets.push((bucket_items, bucket_buffer));
+        }
+        items_rest = items_tail;
+        buffer_rest = buffer_tail;
+    }
+    buckets
+        .into_par_iter()
+        .for_each(|(bucket_items, bucket_buffer)| {
+            radix_sort_pass(bucket_items, bucket_buffer, shift - 8)
+        });
+}
+
+impl LinearQuadTree {
+    pub fn build(particles: &[Particle], boundary: [f64; 4], limits: TreeLimits) -> Self {
+        LinearQuadTree::build_with_coupling(particles, boundary, limits, Coupling::Mass)
+    }
+
+    pub fn build_with_coupling(
+        particles: &[Particle],
+        boundary: [f64; 4],
+        limits: TreeLimits,
+        coupling: Coupling,
+    ) -> Self {
+        let mut keyed: Vec<(u64, usize)> = particles
+            .par_iter()
+            .enumerate()
+            .filter(|(_, p)| Self::in_boundary(p, boundary))
+            .map(|(i, p)| (morton_key(p.position, boundary), i))
+            .collect();
+        let dropped = particles.len() - keyed.len();
+        par_radix_sort(&mut keyed);
+
+        let keys: Vec<u64> = keyed.iter().map(|&(key, _)| key).collect();
+        let order: Vec<usize> = keyed.iter().map(|&(_, i)| i).collect();
+        let sorted: Vec<Particle> = order.iter().map(|&i| particles[i]).collect();
+
+        let nodes = if sorted.is_empty() {
+            Vec::new()
+        } else {
+            let builder = Builder {
+                keys: &keys,
+                particles: &sorted,
+                limits,
+                coupling,
+            };
+            builder
+                .build_parallel(0, sorted.len(), boundary, 0)
+                .concat()
+        };
+
+        LinearQuadTree {
+            nodes,
+            particles: sorted,
+            order,
+            dropped,
+        }
+    }
+
+    fn in_boundary(particle: &Particle, boundary: [f64; 4]) -> bool {
+        let [x_min, y_min, x_max, y_max] = boundary;
+        (particle.position[0] >= x_min)
+            && (particle.position[0] <= x_max)
+            && (particle.position[1] >= y_min)
+            && (particle.position[1] <= y_max)
+    }
+
+    // `previous_acc` is the magnitude of the acceleration of the particle at the
+    // previous evaluation, used by the relative opening criterion
+    pub fn compute_force<F: PairForce>(
+        &self,
+        particle: &Particle,
+        walk: &TreeWalk<F>,
+        previous_acc: Option<f64>,
+        ewald: Option<&EwaldTable>,
+    ) -> [f64; 2] {
+        let mut total_force = [0.0, 0.0];
+        let mut i = 0;
+        while i < self.nodes.len() {
+            let node = &self.nodes[i];
+            let d = separation(particle.position, node.center_of_mass, ewald);
+            let dist_sq = d[0] * d[0] + d[1] * d[1];
+
+            if walk.accepts(node, particle, dist_sq, previous_acc) {
+                let force = node.far_field_force(particle, d, dist_sq, walk, ewald);
+                total_force[0] += force[0];
+                total_force[1] += force[1];
+                i += node.skip;
+            } else if node.is_leaf() {
+                // Leaves are evaluated exactly, which gives no force on the particle itself
+                for other in self.particles[node.start..node.end].iter() {
+                    let force = pair_force(particle, other, &walk.force, ewald);
+                    total_force[0] += force[0];
+                    total_force[1] += force[1];
+                }
+                i += 1;
+            } else {
+                i += 1;
+            }
+        }
+        total_force
+    }
+
+    // Short-range force of a ForceSplit on a particle, using only monopoles.
+    // Nodes entirely beyond the cutoff are skipped, while accepted nodes
+    // straddling it count whole. With a period, separations
+    // go to the nearest periodic image, which needs the cutoff to be below half
+    // the period.
+    pub fn compute_short_range_force(
+        &self,
+        particle: &Particle,
+        walk: &TreeWalk,
+        split: &ForceSplit,
+        period: Option<[f64; 2]>,
+    ) -> [f64; 2] {
+        let separation = |position: [f64; 2]| {
+            minimum_image(
+                [
+                    position[0] - particle.position[0],
+                    position[1] - particle.position[1],
+                ],
+                period,
+            )
+        };
+
+        let mut total_force = [0.0, 0.0];
+        let mut i = 0;
+        while i < self.nodes.len() {
+            let node = &self.nodes[i];
+            let [x_min, y_min, x_max, y_max] = node.boundary;
+            let to_center = separation([(x_min + x_max) / 2.0, (y_min + y_max) / 2.0]);
+            let gap = [
+                (to_center[0].abs() - (x_max - x_min) / 2.0).max(0.0),
+                (to_center[1].abs() - (y_max - y_min) / 2.0).max(0.0),
+            ];
+            if gap[0] * gap[0] + gap[1] * gap[1] >= split.cutoff * split.cutoff {
+                i += node.skip;
+                continue;
+            }
+
+            let d = separation(node.center_of_mass);
+            let dist_sq = d[0] * d[0] + d[1] * d[1];
+            if walk.accepts(node, particle, dist_sq, None) {
+                let length = split
+                    .softening
+                    .pair_length(particle.softening, node.max_softening);
+                let force = split.short_range_force(particle.mass, node.mass, d, dist_sq, length);
+                total_force[0] += force[0];
+                total_force[1] += force[1];
+                i += node.skip;
+            } else {
+                if node.is_leaf() {
+                    for other in self.particles[node.start..node.end].iter() {
+                        let d = separation(other.position);
+                        let dist_sq = d[0] * d[0] + d[1] * d[1];
+                        if dist_sq < split.cutoff * split.cutoff {
+                            let length = split
+                                .softening
+                                .pair_length(particle.softening, other.softening);
+                            let force = split.short_range_force(
+                                particle.mass,
+                                other.mass,
+                                d,
+                                dist_sq,
+                                length,
+                            );
+                            total_force[0] += force[0];
+                            total_force[1] += force[1];
+                        }
+                    }
+                }
+                i += 1;
+            }
+        }
+        total_force
+    }
+
+    // Distance from `position` to the k-th nearest particle of the tree, a
+    // particle at `position` itself counting as the nearest. Nodes are skipped
+    // when no particle in them can be closer than the k nearest found so far.
+    // With a period, distances are to the nearest images.
+    pub fn nearest_distance(&self, position: [f64; 2], k: usize, period: Option<[f64; 2]>) -> f64 {
+        let k = k.min(self.particles.len());
+        if k == 0 {
+            return f64::INFINITY;
+        }
+
+        // Squared distances of the k nearest particles found so far, sorted
+        let mut nearest: Vec<f64> = Vec::with_capacity(k + 1);
+        let mut i = 0;
+        while i < self.nodes.len() {
+            let node = &self.nodes[i];
+            let [x_min, y_min, x_max, y_max] = node.boundary;
+            let offset = minimum_image(
+                [
+                    position[0] - 0.5 * (x_min + x_max),
+                    position[1] - 0.5 * (y_min + y_max),
+                ],
+                period,
+            );
+            let gap = [
+                (offset[0].abs() - 0.5 * (x_max - x_min)).max(0.0),
+                (offset[1].abs() - 0.5 * (y_max - y_min)).max(0.0),
+            ];
+            if nearest.len() == k && gap[0] * gap[0] + gap[1] * gap[1] >= nearest[k - 1] {
+                i += node.skip;
+                continue;
+            }
+
+            if node.is_leaf() {
+                for other in self.particles[node.start..node.end].iter() {
+                    let d = minimum_image(
+                        [
+                            other.position[0] - position[0],
+                            other.position[1] - position[1],
+                        ],
+                        period,
+                    );
+                    let dist_sq = d[0] * d[0] + d[1] * d[1];
+                    if nearest.len() < k || dist_sq < nearest[k - 1] {
+                        let index = nearest.partition_point(|&x| x <= dist_sq);
+                        nearest.insert(index, dist_sq);
+                        nearest.truncate(k);
+                    }
+                }
+            }
+            i += 1;
+        }
+        nearest[k - 1].sqrt()
+    }
+}
+
+struct Builder<'a> {
+    keys: &'a [u64],
+    particles: &'a [Particle],
+    limits: TreeLimits,
+    coupling: Coupling,
+}
+
+impl Builder<'_> {
+    // Non-empty children of the node holding [start, end) at `level`, as
+    // (start, end, boundary)
+    fn children(
+        &self,
+        start: usize,
+        end: usize,
+        boundary: [f64; 4],
+        level: u32,
+    ) -> Vec<(usize, usize, [f64; 4])> {
+        let [x_min, y_min, x_max, y_max] = boundary;
+        let mid_x = (x_min + x_max) / 2.0;
+        let mid_y = (y_min + y_max) / 2.0;
+        let quadrants = [
+            [x_min, y_min, mid_x, mid_y],
+            [mid_x, y_min, x_max, mid_y],
+            [x_min, mid_y, mid_x, y_max],
+            [mid_x, mid_y, x_max, y_max],
+        ];
+
+        let shift = 2 * (MAX_LEVEL - 1 - level);
+        let keys = &self.keys[start..end];
+        let mut children = Vec::with_capacity(4);
+        let mut child_start = start;
+        for (quadrant, quadrant_boundary) in quadrants.iter().enumerate() {
+            let child_end =
+                start + keys.partition_point(|&key| ((key >> shift) & 3) as usize <= quadrant);
+            if child_end > child_start {
+                children.push((child_start, child_end, *quadrant_boundary));
+            }
+            child_start = child_end;
+        }
+        children
+    }
+
+    fn is_leaf(&self, start: usize, end: usize, level: u32) -> bool {
+        end - start <= self.limits.leaf_capacity || level >= self.limits.max_depth.min(MAX_LEVEL)
+    }
+
+    // Builds the subtree of the node holding [start, end) as segments which,
+    // concatenated, give its nodes in depth-first order. Joining the segments
+    // once at the end avoids copying the nodes at every parallel level.
+    fn build_parallel(
+        &self,
+        start: usize,
+        end: usize,
+        boundary: [f64; 4],
+        level: u32,
+    ) -> Vec<Vec<LinearNode>> {
+        if end - start < PARALLEL_THRESHOLD || self.is_leaf(start, end, level) {
+            let mut nodes = Vec::new();
+            self.build_serial(&mut nodes, start, end, boundary, level);
+            return vec![nodes];
+        }
+
+        let subtrees: Vec<Vec<Vec<LinearNode>>> = self
+            .children(start, end, boundary, level)
+            .into_par_iter()
+            .map(|(child_start, child_end, child_boundary)| {
+                self.build_parallel(child_start, child_end, child_boundary, level + 1)
+            })
+            .collect();
+
+        let child_roots: Vec<LinearNode> = subtrees.iter().map(|s| s[0][0]).collect();
+        let mut node = Self::combine(Self::empty_node(start, end, boundary), &child_roots);
+        node.skip = 1 + child_roots.iter().map(|c| c.skip).sum::<usize>();
+
+        let mut segments = vec![vec![node]];
+        segments.extend(subtrees.into_iter().flatten());
+        segments
+    }
+
+    fn build_serial(
+        &self,
+        nodes: &mut Vec<LinearNode>,
+        start: usize,
+        end: usize,
+        boundary: [f64; 4],
+        level: u32,
+    ) {
+        let index = nodes.len();
+        nodes.push(Self::empty_node(start, end, boundary));
+        if self.is_leaf(start, end, level) {
+            nodes[index] = self.leaf(start, end, boundary);
+            return;
+        }
+
+        let mut child_roots = Vec::with_capacity(4);
+        for (child_start, child_end, child_boundary) in self.children(start, end, boundary, level) {
+            let child_index = nodes.len();
+            self.build_serial(nodes, child_start, child_end, child_boundary, level + 1);
+            child_roots.push(nodes[child_index]);
+        }
+        let mut node = Self::combine(nodes[index], &child_roots);
+        node.skip = nodes.len() - index;
+        nodes[index] = node;
+    }
+
+    fn empty_node(start: usize, end: usize, boundary: [f64; 4]) -> LinearNode {
+        LinearNode {
+            boundary,
+            mass: 0.0,
+            abs_mass: 0.0,
+            center_of_mass: [0.0, 0.0],
+            dipole: [0.0; 2],
+            second_moment: [0.0; 3],
+            third_moment: [0.0; 4],
+            max_softening: 0.0,
+            start,
+            end,
+            skip: 1,
+        }
+    }
+
+    // Leaves hold up to the leaf capacity, or any number of particles at the
+    // maximum depth
+    fn leaf(&self, start: usize, end: usize, boundary: [f64; 4]) -> LinearNode {
+        let mut node = Self::empty_node(start, end, boundary);
+        let particles = &self.particles[start..end];
+        for p in particles {
+            let mass = self.coupling.of(p);
+            node.mass += mass;
+            node.abs_mass += mass.abs();
+            node.center_of_mass[0] += mass.abs() * p.position[0];
+            node.center_of_mass[1] += mass.abs() * p.position[1];
+            node.max_softening = node.max_softening.max(p.softening);
+        }
+        if node.abs_mass != 0.0 {
+            node.center_of_mass[0] /= node.abs_mass;
+            node.center_of_mass[1] /= node.abs_mass;
+        }
+        (node.dipole, node.second_moment, node.third_moment) =
+            bucket_moments(particles, node.center_of_mass, self.coupling);
+        node
+    }
+
+    // Mass, centre of mass, dipole and moments of a node from those of its children
+    fn combine(mut node: LinearNode, children: &[LinearNode]) -> LinearNode {
+        for child in children {
+            node.mass += child.mass;
+            node.abs_mass += child.abs_mass;
+            node.center_of_mass[0] += child.abs_mass * child.center_of_mass[0];
+            node.center_of_mass[1] += child.abs_mass * child.center_of_mass[1];
+            node.max_softening = node.max_softening.max(child.max_softening);
+        }
+        if node.abs_mass != 0.0 {
+            node.center_of_mass[0] /= node.abs_mass;
+            node.center_of_mass[1] /= node.abs_mass;
+        }
+        for child in children {
+            if child.abs_mass != 0.0 {
+                add_shifted_moments(
+                    &mut node.dipole,
+                    &mut node.second_moment,
+                    &mut node.third_moment,
+                    node.center_of_mass,
+                    child,
+                );
+            }
+        }
+        node
+    }
+}
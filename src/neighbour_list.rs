@@ -0,0 +1,179 @@
+use crate::forces::minimum_image;
+use crate::particle::Particle;
+use rayon::prelude::*;
+
+// Verlet list: the particles within `reach` of each particle when the list was
+// built, with reach = cutoff + skin. The list holds every pair within the
+// cutoff until some particle has moved by half the skin. Neighbours are found
+// through a grid of cells at least `reach` wide, so that only the 3x3 cells
+// around a particle need to be searched.
+#[derive(Debug, Clone)]
+pub struct VerletList {
+    pub reach: f64,
+    // Neighbours of particle i are neighbours[offsets[i]..offsets[i + 1]]
+    pub offsets: Vec<usize>,
+    pub neighbours: Vec<usize>,
+    // Positions when the list was built
+    positions: Vec<[f64; 2]>,
+    period: Option<[f64; 2]>,
+}
+
+impl VerletList {
+    // `domain` must contain all the particles. With a period, it is the
+    // periodic box, separations go to the nearest image, and `reach` must be
+    // below half the period.
+    pub fn build(
+        particles: &[Particle],
+        reach: f64,
+        domain: [f64; 4],
+        period: Option<[f64; 2]>,
+    ) -> Self {
+        let grid = CellGrid::new(particles, reach, domain, period.is_some());
+        let reach_sq = reach * reach;
+        let lists: Vec<Vec<usize>> = particles
+            .par_iter()
+            .enumerate()
+            .map(|(i, particle)| {
+                let mut list = Vec::new();
+                for cell in grid.neighbouring_cells(particle.position) {
+                    for &j in grid.particles_in(cell) {
+                        let other = &particles[j];
+                        let d = minimum_image(
+                            [
+                                other.position[0] - particle.position[0],
+                                other.position[1] - particle.position[1],
+                            ],
+                            period,
+                        );
+                        if j != i && d[0] * d[0] + d[1] * d[1] < reach_sq {
+                            list.push(j);
+                        }
+                    }
+                }
+                list
+            })
+            .collect();
+
+        let mut offsets = Vec::with_capacity(particles.len() + 1);
+        offsets.push(0);
+        for list in lists.iter() {
+            offsets.push(offsets[offsets.len() - 1] + list.len());
+        }
+        VerletList {
+            reach,
+            offsets,
+            neighbours: lists.concat(),
+            positions: particles.iter().map(|p| p.position).collect(),
+            period,
+        }
+    }
+
+    pub fn neighbours_of(&self, i: usize) -> &[usize] {
+        &self.neighbours[self.offsets[i]..self.offsets[i + 1]]
+    }
+
+    // Whether the list may miss pairs now within `reach - skin`, some particle
+    // having moved by more than half the skin since it was built
+    pub fn is_stale(&self, particles: &[Particle], skin: f64) -> bool {
+        if particles.len() != self.positions.len() {
+            return true;
+        }
+        let limit_sq = 0.25 * skin * skin;
+        particles
+            .par_iter()
+            .zip(self.positions.par_iter())
+            .any(|(p, position)| {
+                let d = minimum_image(
+                    [p.position[0] - position[0], p.position[1] - position[1]],
+                    self.period,
+                );
+                d[0] * d[0] + d[1] * d[1] > limit_sq
+            })
+    }
+}
+
+// Particles binned into a grid of cells, sorted by cell
+struct CellGrid {
+    domain: [f64; 4],
+    cells: [usize; 2],
+    periodic: bool,
+    // Particles of cell c are order[starts[c]..starts[c + 1]]
+    starts: Vec<usize>,
+    order: Vec<usize>,
+}
+
+impl CellGrid {
+    fn new(particles: &[Particle], reach: f64, domain: [f64; 4], periodic: bool) -> Self {
+        // Cells are at least `reach` wide, and no more numerous than about four
+        // per particle, as sparse particles would otherwise make huge grids
+        let max_cells = (2.0 * (particles.len() as f64).sqrt()).ceil().max(1.0);
+        let cells_along = |width: f64| ((width / reach).floor().clamp(1.0, max_cells)) as usize;
+        let cells = [
+            cells_along(domain[2] - domain[0]),
+            cells_along(domain[3] - domain[1]),
+        ];
+
+        let mut grid = CellGrid {
+            domain,
+            cells,
+            periodic,
+            starts: vec![0; cells[0] * cells[1] + 1],
+            order: vec![0; particles.len()],
+        };
+        let indices: Vec<usize> = particles.iter().map(|p| grid.cell_of(p.position)).collect();
+        for &cell in indices.iter() {
+            grid.starts[cell + 1] += 1;
+        }
+        for c in 1..grid.starts.len() {
+            grid.starts[c] += grid.starts[c - 1];
+        }
+        let mut next = grid.starts.clone();
+        for (i, &cell) in indices.iter().enumerate() {
+            grid.order[next[cell]] = i;
+            next[cell] += 1;
+        }
+        grid
+    }
+
+    fn coordinates(&self, position: [f64; 2]) -> [usize; 2] {
+        let coordinate = |d: usize| {
+            let width = (self.domain[d + 2] - self.domain[d]) / self.cells[d] as f64;
+            let u = ((position[d] - self.domain[d]) / width).floor();
+            u.clamp(0.0, (self.cells[d] - 1) as f64) as usize
+        };
+        [coordinate(0), coordinate(1)]
+    }
+
+    fn cell_of(&self, position: [f64; 2]) -> usize {
+        let [x, y] = self.coordinates(position);
+        y * self.cells[0] + x
+    }
+
+    // The cells around the one of `position`, itself included, each once even
+    // when a periodic grid is less than three cells wide
+    fn neighbouring_cells(&self, position: [f64; 2]) -> Vec<usize> {
+        let coordinates = self.coordinates(position);
+        let around = |d: usize| {
+            let n = self.cells[d] as isize;
+            let c = coordinates[d] as isize;
+            let mut indices: Vec<usize> = (c - 1..=c + 1)
+                .filter_map(|k| match (self.periodic, k) {
+                    (true, _) => Some(k.rem_euclid(n) as usize),
+                    (false, k) if k >= 0 && k < n => Some(k as usize),
+                    _ => None,
+                })
+                .collect();
+            indices.sort_unstable();
+            indices.dedup();
+            indices
+        };
+        let (xs, ys) = (around(0), around(1));
+        ys.iter()
+            .flat_map(|&y| xs.iter().map(move |&x| y * self.cells[0] + x))
+            .collect()
+    }
+
+    fn particles_in(&self, cell: usize) -> &[usize] {
+        &self.order[self.starts[cell]..self.starts[cell + 1]]
+    }
+}
@@ -0,0 +1,537 @@
+use crate::error::ConfigError;
+use crate::forces::GRAVIT_CONST;
+use crate::particle::Particle;
+use crate::softening::Softening;
+use std::fmt::Debug;
+
+// Property of the particles a pair force acts on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coupling {
+    Mass,
+    Charge,
+    // The same for every particle, as for atoms of a single species
+    Unit,
+}
+
+impl Coupling {
+    pub fn of(&self, particle: &Particle) -> f64 {
+        match self {
+            Coupling::Mass => particle.mass,
+            Coupling::Charge => particle.charge,
+            Coupling::Unit => 1.0,
+        }
+    }
+}
+
+// Central force between two particles. The force on the first one is
+// s1 s2 f(r) d, with d = x2 - x1 and s the coupling of each particle, so that
+// f > 0 pulls the particles together. The potential energy of the pair is
+// s1 s2 u(r), with u'(r) = r f(r).
+pub trait PairForce: Debug + Clone + Copy + Send + Sync {
+    fn coupling(&self) -> Coupling;
+
+    fn validate(&self) -> Result<(), ConfigError>;
+
+    fn softening(&self) -> Softening {
+        Softening::None
+    }
+
+    // f(r) and f'(r) / r for a pair with the given softening length, the time
+    // derivative of the force being s1 s2 (f dv + f'/r (d.dv) d)
+    fn force_factor_and_derivative(&self, dist_sq: f64, length: f64) -> (f64, f64);
+
+    // u(r) for a pair with the given softening length
+    fn potential(&self, dist: f64, length: f64) -> f64;
+
+    fn force_factor(&self, dist_sq: f64, length: f64) -> f64 {
+        self.force_factor_and_derivative(dist_sq, length).0
+    }
+
+    // Some(c) when f(r) = c / r^3 beyond the reach of the softening, as for
+    // gravity with G = c. Only such forces get the higher multipoles of the
+    // trees and the Ewald sums of periodic boxes.
+    fn inverse_square_strength(&self) -> Option<f64> {
+        None
+    }
+
+    // Some(G) for an attractive inverse-square force between masses, under
+    // which two bodies follow Kepler orbits with mu = G (m1 + m2)
+    fn gravitational_constant(&self) -> Option<f64> {
+        match (self.coupling(), self.inverse_square_strength()) {
+            (Coupling::Mass, Some(strength)) if strength > 0.0 => Some(strength),
+            _ => None,
+        }
+    }
+
+    // Distance beyond which the force vanishes, if any. Only such forces can
+    // be summed over neighbour lists.
+    fn cutoff(&self) -> Option<f64> {
+        None
+    }
+
+    // Force on a particle of coupling s1 from one of coupling s2 at separation
+    // d = x2 - x1
+    fn force_at(&self, s1: f64, s2: f64, d: [f64; 2], length: f64) -> [f64; 2] {
+        let dist_sq = d[0] * d[0] + d[1] * d[1];
+        let force_mag = s1 * s2 * self.force_factor(dist_sq, length);
+        [force_mag * d[0], force_mag * d[1]]
+    }
+
+    // Force exerted on p1 by p2
+    fn force(&self, p1: &Particle, p2: &Particle) -> [f64; 2] {
+        let d = [
+            p2.position[0] - p1.position[0],
+            p2.position[1] - p1.position[1],
+        ];
+        let length = self.softening().pair_length(p1.softening, p2.softening);
+        let coupling = self.coupling();
+        self.force_at(coupling.of(p1), coupling.of(p2), d, length)
+    }
+
+    // Force exerted on p1 by p2 and its time derivative
+    fn force_and_jerk(&self, p1: &Particle, p2: &Particle) -> ([f64; 2], [f64; 2]) {
+        let dx = p2.position[0] - p1.position[0];
+        let dy = p2.position[1] - p1.position[1];
+        let dvx = p2.velocity[0] - p1.velocity[0];
+        let dvy = p2.velocity[1] - p1.velocity[1];
+        let dist_sq = dx * dx + dy * dy;
+        let rv = dx * dvx + dy * dvy;
+
+        let length = self.softening().pair_length(p1.softening, p2.softening);
+        let (f, derivative) = self.force_factor_and_derivative(dist_sq, length);
+        let coupling = self.coupling();
+        let s = coupling.of(p1) * coupling.of(p2);
+        let force = [s * f * dx, s * f * dy];
+        let jerk = [
+            s * (f * dvx + derivative * rv * dx),
+            s * (f * dvy + derivative * rv * dy),
+        ];
+        (force, jerk)
+    }
+
+    fn potential_energy(&self, p1: &Particle, p2: &Particle) -> f64 {
+        let dx = p2.position[0] - p1.position[0];
+        let dy = p2.position[1] - p1.position[1];
+        let dist = (dx * dx + dy * dy).sqrt();
+        let length = self.softening().pair_length(p1.softening, p2.softening);
+        let coupling = self.coupling();
+        coupling.of(p1) * coupling.of(p2) * self.potential(dist, length)
+    }
+}
+
+fn invalid(force: &impl PairForce) -> ConfigError {
+    ConfigError::InvalidPairForce(format!("{:?}", force))
+}
+
+// Newtonian gravity between masses, u = -G phi(r) with phi the softened 1 / r
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gravity {
+    pub constant: f64,
+    pub softening: Softening,
+}
+
+impl Default for Gravity {
+    fn default() -> Self {
+        Gravity {
+            constant: GRAVIT_CONST,
+            softening: Softening::default(),
+        }
+    }
+}
+
+impl Gravity {
+    pub fn with_softening(softening: Softening) -> Self {
+        Gravity {
+            constant: GRAVIT_CONST,
+            softening,
+        }
+    }
+}
+
+impl PairForce for Gravity {
+    fn coupling(&self) -> Coupling {
+        Coupling::Mass
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.softening.validate()?;
+        if !self.constant.is_finite() {
+            return Err(invalid(self));
+        }
+        Ok(())
+    }
+
+    fn softening(&self) -> Softening {
+        self.softening
+    }
+
+    fn force_factor_and_derivative(&self, dist_sq: f64, length: f64) -> (f64, f64) {
+        let (f, derivative) = self.softening.force_factor_and_derivative(dist_sq, length);
+        (self.constant * f, self.constant * derivative)
+    }
+
+    fn potential(&self, dist: f64, length: f64) -> f64 {
+        -self.constant * self.softening.potential(dist, length)
+    }
+
+    fn force_factor(&self, dist_sq: f64, length: f64) -> f64 {
+        self.constant * self.softening.force_factor(dist_sq, length)
+    }
+
+    fn inverse_square_strength(&self) -> Option<f64> {
+        Some(self.constant)
+    }
+}
+
+// Electrostatic force between signed charges, u = k phi(r): like charges repel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coulomb {
+    pub constant: f64,
+    pub softening: Softening,
+}
+
+impl Coulomb {
+    pub fn new(constant: f64) -> Self {
+        Coulomb {
+            constant,
+            softening: Softening::None,
+        }
+    }
+
+    pub fn with_softening(mut self, softening: Softening) -> Self {
+        self.softening = softening;
+        self
+    }
+}
+
+impl PairForce for Coulomb {
+    fn coupling(&self) -> Coupling {
+        Coupling::Charge
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.softening.validate()?;
+        if !self.constant.is_finite() {
+            return Err(invalid(self));
+        }
+        Ok(())
+    }
+
+    fn softening(&self) -> Softening {
+        self.softening
+    }
+
+    fn force_factor_and_derivative(&self, dist_sq: f64, length: f64) -> (f64, f64) {
+        let (f, derivative) = self.softening.force_factor_and_derivative(dist_sq, length);
+        (-self.constant * f, -self.constant * derivative)
+    }
+
+    fn potential(&self, dist: f64, length: f64) -> f64 {
+        self.constant * self.softening.potential(dist, length)
+    }
+
+    fn force_factor(&self, dist_sq: f64, length: f64) -> f64 {
+        -self.constant * self.softening.force_factor(dist_sq, length)
+    }
+
+    fn inverse_square_strength(&self) -> Option<f64> {
+        Some(-self.constant)
+    }
+}
+
+// Screened interaction u = k exp(-r / lambda) phi(r), e.g. Debye-screened
+// charges, or screened gravity between masses with a negative strength
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Yukawa {
+    pub coupling: Coupling,
+    pub strength: f64,
+    pub screening_length: f64,
+    pub softening: Softening,
+}
+
+impl Yukawa {
+    pub fn new(coupling: Coupling, strength: f64, screening_length: f64) -> Self {
+        Yukawa {
+            coupling,
+            strength,
+            screening_length,
+            softening: Softening::None,
+        }
+    }
+
+    pub fn with_softening(mut self, softening: Softening) -> Self {
+        self.softening = softening;
+        self
+    }
+}
+
+impl PairForce for Yukawa {
+    fn coupling(&self) -> Coupling {
+        self.coupling
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.softening.validate()?;
+        let lambda = self.screening_length;
+        if !(self.strength.is_finite() && lambda.is_finite() && lambda > 0.0) {
+            return Err(invalid(self));
+        }
+        Ok(())
+    }
+
+    fn softening(&self) -> Softening {
+        self.softening
+    }
+
+    // With e = exp(-r / lambda), and phi, f_s = -phi'/r and d_s = f_s'/r those
+    // of the softened 1 / r:
+    // f = -k e (f_s + phi / (lambda r))
+    // f'/r = -k e (d_s - 2 f_s / (lambda r) - phi / (lambda^2 r^2) - phi / (lambda r^3))
+    fn force_factor_and_derivative(&self, dist_sq: f64, length: f64) -> (f64, f64) {
+        if dist_sq == 0.0 {
+            return (0.0, 0.0);
+        }
+        let (r, lambda) = (dist_sq.sqrt(), self.screening_length);
+        let (f_s, d_s) = self.softening.force_factor_and_derivative(dist_sq, length);
+        let phi = self.softening.potential(r, length);
+        let scale = -self.strength * (-r / lambda).exp();
+        let lambda_r = lambda * r;
+        (
+            scale * (f_s + phi / lambda_r),
+            scale
+                * (d_s
+                    - 2.0 * f_s / lambda_r
+                    - phi / (lambda_r * lambda_r)
+                    - phi / (lambda_r * dist_sq)),
+        )
+    }
+
+    fn potential(&self, dist: f64, length: f64) -> f64 {
+        self.strength
+            * (-dist / self.screening_length).exp()
+            * self.softening.potential(dist, length)
+    }
+}
+
+// Force of magnitude k s1 s2 / r^n pulling the particles together, n = 2 and
+// positive k on masses being gravity-like. Only Plummer softening applies, as
+// r^2 -> r^2 + epsilon^2.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerLaw {
+    pub coupling: Coupling,
+    pub strength: f64,
+    pub exponent: f64,
+    pub softening: Softening,
+}
+
+impl PowerLaw {
+    pub fn new(coupling: Coupling, strength: f64, exponent: f64) -> Self {
+        PowerLaw {
+            coupling,
+            strength,
+            exponent,
+            softening: Softening::None,
+        }
+    }
+
+    pub fn with_softening(mut self, softening: Softening) -> Self {
+        self.softening = softening;
+        self
+    }
+
+    fn softened_dist_sq(&self, dist_sq: f64, length: f64) -> f64 {
+        match self.softening {
+            Softening::Plummer { .. } => dist_sq + length * length,
+            _ => dist_sq,
+        }
+    }
+}
+
+impl PairForce for PowerLaw {
+    fn coupling(&self) -> Coupling {
+        self.coupling
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.softening.validate()?;
+        let plummer = matches!(self.softening, Softening::None | Softening::Plummer { .. });
+        if !(plummer && self.strength.is_finite() && self.exponent.is_finite()) {
+            return Err(invalid(self));
+        }
+        Ok(())
+    }
+
+    fn softening(&self) -> Softening {
+        self.softening
+    }
+
+    // f = k s^-(n+1) and f'/r = -(n + 1) k s^-(n+3), with s^2 = r^2 + epsilon^2
+    fn force_factor_and_derivative(&self, dist_sq: f64, length: f64) -> (f64, f64) {
+        if dist_sq == 0.0 {
+            return (0.0, 0.0);
+        }
+        let s_sq = self.softened_dist_sq(dist_sq, length);
+        let f = self.strength * s_sq.powf(-0.5 * (self.exponent + 1.0));
+        (f, -(self.exponent + 1.0) * f / s_sq)
+    }
+
+    // u = k s^(1-n) / (1 - n), or k ln(s) for n = 1
+    fn potential(&self, dist: f64, length: f64) -> f64 {
+        let s_sq = self.softened_dist_sq(dist * dist, length);
+        if self.exponent == 1.0 {
+            0.5 * self.strength * s_sq.ln()
+        } else {
+            self.strength * s_sq.powf(0.5 * (1.0 - self.exponent)) / (1.0 - self.exponent)
+        }
+    }
+
+    fn inverse_square_strength(&self) -> Option<f64> {
+        (self.exponent == 2.0).then_some(self.strength)
+    }
+}
+
+// Lennard-Jones interaction between atoms, u = 4 epsilon ((sigma / r)^12 -
+// (sigma / r)^6), truncated at the cutoff and shifted to vanish there so that
+// the energy stays continuous. The force itself jumps at the cutoff, by less
+// than 2% of its minimum for the usual cutoff of 2.5 sigma.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LennardJones {
+    pub epsilon: f64,
+    pub sigma: f64,
+    pub cutoff: f64,
+}
+
+impl LennardJones {
+    pub fn new(epsilon: f64, sigma: f64, cutoff: f64) -> Self {
+        LennardJones {
+            epsilon,
+            sigma,
+            cutoff,
+        }
+    }
+
+    // Weeks-Chandler-Andersen: only the repulsive part, cut at the minimum
+    // 2^(1/6) sigma, where the shifted potential and the force both vanish
+    pub fn wca(epsilon: f64, sigma: f64) -> Self {
+        LennardJones::new(epsilon, sigma, 2f64.powf(1.0 / 6.0) * sigma)
+    }
+
+    fn unshifted(&self, dist_sq: f64) -> f64 {
+        let s6 = (self.sigma * self.sigma / dist_sq).powi(3);
+        4.0 * self.epsilon * (s6 * s6 - s6)
+    }
+}
+
+impl PairForce for LennardJones {
+    fn coupling(&self) -> Coupling {
+        Coupling::Unit
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let valid = [self.epsilon, self.sigma, self.cutoff]
+            .iter()
+            .all(|x| x.is_finite() && *x > 0.0);
+        if !valid {
+            return Err(invalid(self));
+        }
+        Ok(())
+    }
+
+    // f = 24 epsilon (s6 - 2 s12) / r^2 and f'/r = 24 epsilon (28 s12 - 8 s6) / r^4,
+    // with s6 = (sigma / r)^6 and s12 = s6^2
+    fn force_factor_and_derivative(&self, dist_sq: f64, _length: f64) -> (f64, f64) {
+        if dist_sq == 0.0 || dist_sq >= self.cutoff * self.cutoff {
+            return (0.0, 0.0);
+        }
+        let s6 = (self.sigma * self.sigma / dist_sq).powi(3);
+        let s12 = s6 * s6;
+        let scale = 24.0 * self.epsilon / dist_sq;
+        (
+            scale * (s6 - 2.0 * s12),
+            scale * (28.0 * s12 - 8.0 * s6) / dist_sq,
+        )
+    }
+
+    fn potential(&self, dist: f64, _length: f64) -> f64 {
+        if dist >= self.cutoff {
+            return 0.0;
+        }
+        self.unshifted(dist * dist) - self.unshifted(self.cutoff * self.cutoff)
+    }
+
+    fn cutoff(&self) -> Option<f64> {
+        Some(self.cutoff)
+    }
+}
+
+// Morse bond between atoms, u = D ((1 - exp(-a (r - r0)))^2 - 1), of depth D at
+// the equilibrium distance r0, truncated and shifted as LennardJones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Morse {
+    pub depth: f64,
+    pub stiffness: f64,
+    pub equilibrium: f64,
+    pub cutoff: f64,
+}
+
+impl Morse {
+    pub fn new(depth: f64, stiffness: f64, equilibrium: f64, cutoff: f64) -> Self {
+        Morse {
+            depth,
+            stiffness,
+            equilibrium,
+            cutoff,
+        }
+    }
+
+    // exp(-a (r - r0))
+    fn decay(&self, dist: f64) -> f64 {
+        (-self.stiffness * (dist - self.equilibrium)).exp()
+    }
+
+    fn unshifted(&self, dist: f64) -> f64 {
+        let e = self.decay(dist);
+        self.depth * (e * e - 2.0 * e)
+    }
+}
+
+impl PairForce for Morse {
+    fn coupling(&self) -> Coupling {
+        Coupling::Unit
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let valid = [self.depth, self.stiffness, self.equilibrium, self.cutoff]
+            .iter()
+            .all(|x| x.is_finite() && *x >= 0.0);
+        if !(valid && self.stiffness > 0.0 && self.cutoff > 0.0) {
+            return Err(invalid(self));
+        }
+        Ok(())
+    }
+
+    // With e = exp(-a (r - r0)), u' = 2 a D (e - e^2) and
+    // u'' = 2 a^2 D (2 e^2 - e), f = u'/r and f'/r = (u'' - f) / r^2
+    fn force_factor_and_derivative(&self, dist_sq: f64, _length: f64) -> (f64, f64) {
+        if dist_sq == 0.0 || dist_sq >= self.cutoff * self.cutoff {
+            return (0.0, 0.0);
+        }
+        let r = dist_sq.sqrt();
+        let e = self.decay(r);
+        let a = self.stiffness;
+        let f = 2.0 * a * self.depth * (e - e * e) / r;
+        let second = 2.0 * a * a * self.depth * (2.0 * e * e - e);
+        (f, (second - f) / dist_sq)
+    }
+
+    fn potential(&self, dist: f64, _length: f64) -> f64 {
+        if dist >= self.cutoff {
+            return 0.0;
+        }
+        self.unshifted(dist) - self.unshifted(self.cutoff)
+    }
+
+    fn cutoff(&self) -> Option<f64> {
+        Some(self.cutoff)
+    }
+}
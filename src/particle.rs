@@ -3,6 +3,10 @@ pub struct Particle {
     pub position: [f64; 2],
     pub velocity: [f64; 2],
     pub mass: f64,
+    // Signed charge, the coupling of electrostatic pair forces
+    pub charge: f64,
+    // Softening length of the particle, used by adaptive softening
+    pub softening: f64,
 }
 
 impl Particle {
@@ -11,6 +15,13 @@ impl Particle {
             position,
             velocity,
             mass,
+            charge: 0.0,
+            softening: 0.0,
         }
     }
+
+    pub fn with_charge(mut self, charge: f64) -> Self {
+        self.charge = charge;
+        self
+    }
 }
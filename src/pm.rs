@@ -0,0 +1,463 @@
+use crate::forces::GRAVIT_CONST;
+use crate::particle::Particle;
+use crate::softening::Softening;
+use num_complex::Complex64;
+use rayon::prelude::*;
+use rustfft::{Fft, FftPlanner};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// Cells left between the particles and the edge of an isolated mesh, so that
+// neither the assignment kernels nor the finite difference stencil reach it
+pub const ISOLATED_MARGIN: usize = 4;
+
+// Kernel spreading the mass of a particle over the mesh, and interpolating the
+// forces back with the same weights so that particles exert no force on
+// themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MassAssignment {
+    // Nearest grid point
+    Ngp,
+    // Cloud in cell
+    Cic,
+    // Triangular shaped cloud
+    Tsc,
+}
+
+impl MassAssignment {
+    // Power of the sinc of the kernel's Fourier transform
+    fn order(&self) -> i32 {
+        match self {
+            MassAssignment::Ngp => 1,
+            MassAssignment::Cic => 2,
+            MassAssignment::Tsc => 3,
+        }
+    }
+
+    // First cell and weights of the kernel at u, in grid units with cell centres
+    // at integers
+    fn weights(&self, u: f64) -> (i64, [f64; 3], usize) {
+        match self {
+            MassAssignment::Ngp => (u.round() as i64, [1.0, 0.0, 0.0], 1),
+            MassAssignment::Cic => {
+                let i = u.floor();
+                let d = u - i;
+                (i as i64, [1.0 - d, d, 0.0], 2)
+            }
+            MassAssignment::Tsc => {
+                let i = u.round();
+                let d = u - i;
+                (
+                    i as i64 - 1,
+                    [
+                        0.5 * (0.5 - d) * (0.5 - d),
+                        0.75 - d * d,
+                        0.5 * (0.5 + d) * (0.5 + d),
+                    ],
+                    3,
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshBoundary {
+    // The domain is one cell of an infinite lattice, and forces include all
+    // the periodic images. A uniform background cancels the mean density.
+    Periodic,
+    // Zero padding to twice the mesh size, which gives the forces of the
+    // particles alone
+    Isolated,
+}
+
+// Mesh solving for the gravitational potential of particles in the plane. The
+// potential of a surface density in a thin sheet obeys psi(k) = -2 pi G sigma(k) / |k|
+// in Fourier space, used as is for periodic meshes. Isolated meshes convolve
+// with the softened real-space kernel -G phi(r) instead, averaged over the
+// central cell where phi is singular. With a split scale, the mesh only gives the long-range
+// part of ForceSplit: psi(k) is filtered by erfc(k r_s), and the real-space
+// kernel becomes -G erf(r / 2 r_s) / r.
+pub struct Mesh {
+    size: usize,
+    boundary: MeshBoundary,
+    assignment: MassAssignment,
+    // Split scale in cells
+    split: Option<f64>,
+    softening: Softening,
+    // Domain and Fourier-space Green's function, kept while the cell size does
+    // not change
+    domain: [f64; 4],
+    green: Vec<Complex64>,
+    fft: Arc<dyn Fft<f64>>,
+    ifft: Arc<dyn Fft<f64>>,
+}
+
+impl std::fmt::Debug for Mesh {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mesh")
+            .field("size", &self.size)
+            .field("boundary", &self.boundary)
+            .field("assignment", &self.assignment)
+            .field("split", &self.split)
+            .field("softening", &self.softening)
+            .field("domain", &self.domain)
+            .finish()
+    }
+}
+
+impl Clone for Mesh {
+    fn clone(&self) -> Self {
+        Mesh {
+            size: self.size,
+            boundary: self.boundary,
+            assignment: self.assignment,
+            split: self.split,
+            softening: self.softening,
+            domain: self.domain,
+            green: self.green.clone(),
+            fft: Arc::clone(&self.fft),
+            ifft: Arc::clone(&self.ifft),
+        }
+    }
+}
+
+impl Mesh {
+    pub fn new(size: usize, boundary: MeshBoundary, assignment: MassAssignment) -> Self {
+        let fft_size = match boundary {
+            MeshBoundary::Periodic => size,
+            MeshBoundary::Isolated => 2 * size,
+        };
+        let mut planner = FftPlanner::new();
+        Mesh {
+            size,
+            boundary,
+            assignment,
+            split: None,
+            softening: Softening::None,
+            domain: [0.0; 4],
+            green: Vec::new(),
+            fft: planner.plan_fft_forward(fft_size),
+            ifft: planner.plan_fft_inverse(fft_size),
+        }
+    }
+
+    // Keeps only the long-range forces of a split at `cells` mesh cells
+    pub fn with_split(mut self, cells: f64) -> Self {
+        self.split = Some(cells);
+        self.green = Vec::new();
+        self
+    }
+
+    // Softening of the isolated kernel, with the fixed length of the kernel:
+    // adaptive lengths are left to the mesh resolution
+    pub fn with_softening(mut self, softening: Softening) -> Self {
+        self.softening = softening;
+        self.green = Vec::new();
+        self
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Larger side of the cells of a mesh over `domain`
+    pub fn cell_length(&self, domain: [f64; 4]) -> f64 {
+        let [x_min, y_min, x_max, y_max] = domain;
+        (x_max - x_min).max(y_max - y_min) / self.size as f64
+    }
+
+    // Split scale r_s over `domain`
+    pub fn split_scale(&self, domain: [f64; 4]) -> Option<f64> {
+        self.split.map(|cells| cells * self.cell_length(domain))
+    }
+
+    // Domain to give an isolated mesh so that the particles lie far enough
+    // from its edges, from their bounding square. The cells are rounded up to
+    // a power of 2^(1/16), so that the Green's function, which only depends on
+    // their size, is kept until the particles spread by several percent.
+    pub fn isolated_domain(size: usize, bounding_square: [f64; 4]) -> [f64; 4] {
+        let [x_min, y_min, x_max, y_max] = bounding_square;
+        let inner_cells = (size - 2 * ISOLATED_MARGIN) as f64;
+        let cell = (x_max - x_min).max(y_max - y_min) / inner_cells;
+        let cell = ((16.0 * cell.log2()).ceil() / 16.0).exp2();
+        let half_side = 0.5 * size as f64 * cell;
+        let centre = [0.5 * (x_min + x_max), 0.5 * (y_min + y_max)];
+        [
+            centre[0] - half_side,
+            centre[1] - half_side,
+            centre[0] + half_side,
+            centre[1] + half_side,
+        ]
+    }
+
+    fn cell_size(&self) -> [f64; 2] {
+        let [x_min, y_min, x_max, y_max] = self.domain;
+        let n = self.size as f64;
+        [(x_max - x_min) / n, (y_max - y_min) / n]
+    }
+
+    fn fft_size(&self) -> usize {
+        self.fft.len()
+    }
+
+    // Accelerations of the particles from the mesh over `domain`. Particles
+    // outside of an isolated domain are left out and get no acceleration;
+    // periodic domains wrap the positions.
+    pub fn accelerations(&mut self, particles: &[Particle], domain: [f64; 4]) -> Vec<[f64; 2]> {
+        // Up to the round-off of domains snapped by `isolated_domain`
+        let previous_cell = self.cell_size();
+        self.domain = domain;
+        let cell = self.cell_size();
+        let changed = (0..2).any(|d| (cell[d] - previous_cell[d]).abs() > 1e-12 * cell[d]);
+        if self.green.is_empty() || changed {
+            self.green = self.green_function();
+        }
+
+        let density = self.assign(particles);
+        let potential = self.solve(density);
+        let field = self.gradient(&potential);
+        particles
+            .par_iter()
+            .map(|p| match self.grid_position(p.position) {
+                Some(u) => {
+                    let g = self.interpolate(&field, u);
+                    [-g[0], -g[1]]
+                }
+                None => [0.0, 0.0],
+            })
+            .collect()
+    }
+
+    // Position in grid units, wrapped for periodic meshes, or None outside of
+    // an isolated mesh
+    fn grid_position(&self, position: [f64; 2]) -> Option<[f64; 2]> {
+        let [x_min, y_min, x_max, y_max] = self.domain;
+        let h = self.cell_size();
+        let n = self.size as f64;
+        let mut u = [(position[0] - x_min) / h[0], (position[1] - y_min) / h[1]];
+        match self.boundary {
+            MeshBoundary::Periodic => {
+                u[0] = u[0].rem_euclid(n);
+                u[1] = u[1].rem_euclid(n);
+            }
+            MeshBoundary::Isolated => {
+                if !(position[0] >= x_min
+                    && position[0] <= x_max
+                    && position[1] >= y_min
+                    && position[1] <= y_max)
+                {
+                    return None;
+                }
+            }
+        }
+        Some([u[0] - 0.5, u[1] - 0.5])
+    }
+
+    // Cells covered by the kernel at u, with their weights
+    fn stencil(&self, u: [f64; 2]) -> impl Iterator<Item = (usize, f64)> {
+        let n = self.size as i64;
+        let periodic = self.boundary == MeshBoundary::Periodic;
+        let (ix, wx, len_x) = self.assignment.weights(u[0]);
+        let (iy, wy, len_y) = self.assignment.weights(u[1]);
+        let wrap = move |i: i64| {
+            if periodic {
+                i.rem_euclid(n)
+            } else {
+                i.clamp(0, n - 1)
+            }
+        };
+        (0..len_y).flat_map(move |b| {
+            (0..len_x).map(move |a| {
+                let cell = wrap(iy + b as i64) * n + wrap(ix + a as i64);
+                (cell as usize, wx[a] * wy[b])
+            })
+        })
+    }
+
+    // Chunks are assigned in parallel and summed in order, so that the density
+    // does not depend on the scheduling
+    fn assign(&self, particles: &[Particle]) -> Vec<f64> {
+        let cells = self.size * self.size;
+        let chunks: Vec<Vec<f64>> = particles
+            .par_chunks(4096)
+            .map(|chunk| {
+                let mut density = vec![0.0; cells];
+                for p in chunk {
+                    if let Some(u) = self.grid_position(p.position) {
+                        for (cell, w) in self.stencil(u) {
+                            density[cell] += p.mass * w;
+                        }
+                    }
+                }
+                density
+            })
+            .collect();
+        let mut density = vec![0.0; cells];
+        for chunk in chunks {
+            for (a, b) in density.iter_mut().zip(chunk.iter()) {
+                *a += b;
+            }
+        }
+        density
+    }
+
+    fn interpolate(&self, field: &[[f64; 2]], u: [f64; 2]) -> [f64; 2] {
+        let mut value = [0.0, 0.0];
+        for (cell, w) in self.stencil(u) {
+            value[0] += w * field[cell][0];
+            value[1] += w * field[cell][1];
+        }
+        value
+    }
+
+    // Green's function of the mesh in Fourier space, scaled so that the
+    // potential is its product with the transform of the cell masses
+    fn green_function(&self) -> Vec<Complex64> {
+        let m = self.fft_size();
+        let h = self.cell_size();
+        let split = self.split_scale(self.domain);
+        let mut green = vec![Complex64::new(0.0, 0.0); m * m];
+        match self.boundary {
+            MeshBoundary::Periodic => {
+                let length = [h[0] * m as f64, h[1] * m as f64];
+                let wave_number = |i: usize, length: f64| {
+                    let i = if i <= m / 2 {
+                        i as f64
+                    } else {
+                        i as f64 - m as f64
+                    };
+                    2.0 * PI * i / length
+                };
+                green.par_iter_mut().enumerate().for_each(|(cell, g)| {
+                    let kx = wave_number(cell % m, length[0]);
+                    let ky = wave_number(cell / m, length[1]);
+                    let k = (kx * kx + ky * ky).sqrt();
+                    if k > 0.0 {
+                        let filter = split.map_or(1.0, |r_s| libm::erfc(k * r_s));
+                        *g = Complex64::new(
+                            -2.0 * PI * GRAVIT_CONST / k / (h[0] * h[1]) * filter,
+                            0.0,
+                        );
+                    }
+                });
+            }
+            MeshBoundary::Isolated => {
+                // Offsets from -m/2 to m/2 - 1 cells, in wrapped order
+                let offset = |i: usize| {
+                    if i < m / 2 {
+                        i as f64
+                    } else {
+                        i as f64 - m as f64
+                    }
+                };
+                let length = self.softening.pair_length(0.0, 0.0);
+                // Mean of 1 / r over the central cell, that of a square of side
+                // h being 4 ln(1 + sqrt(2)) / h
+                let cell_average = 4.0 * (1.0 + 2.0f64.sqrt()).ln() / (h[0] * h[1]).sqrt();
+                green.par_iter_mut().enumerate().for_each(|(cell, g)| {
+                    let dx = offset(cell % m) * h[0];
+                    let dy = offset(cell / m) * h[1];
+                    let r = (dx * dx + dy * dy).sqrt();
+                    let potential = if let Some(r_s) = split {
+                        if r > 0.0 {
+                            -GRAVIT_CONST * libm::erf(r / (2.0 * r_s)) / r
+                        } else {
+                            -GRAVIT_CONST / (r_s * PI.sqrt())
+                        }
+                    } else if r > 0.0 || length > 0.0 {
+                        -GRAVIT_CONST * self.softening.potential(r, length)
+                    } else {
+                        -GRAVIT_CONST * cell_average
+                    };
+                    *g = Complex64::new(potential, 0.0);
+                });
+                fft_2d(&mut green, m, &self.fft);
+            }
+        }
+
+        // Undo the smoothing of the assignment and interpolation, which would
+        // otherwise bias the forces near the split scale. Without a split,
+        // this would amplify the aliased high frequencies instead.
+        if split.is_some() {
+            let window = |i: usize| {
+                let i = if i <= m / 2 { i } else { m - i };
+                let x = PI * i as f64 / m as f64;
+                let sinc = if i == 0 { 1.0 } else { x.sin() / x };
+                sinc.powi(self.assignment.order())
+            };
+            green.par_iter_mut().enumerate().for_each(|(cell, g)| {
+                let w = window(cell % m) * window(cell / m);
+                *g /= w * w;
+            });
+        }
+        green
+    }
+
+    // Potential per unit mass at the cell centres
+    fn solve(&self, density: Vec<f64>) -> Vec<f64> {
+        let n = self.size;
+        let m = self.fft_size();
+        let mut data = vec![Complex64::new(0.0, 0.0); m * m];
+        for (row, density_row) in data.chunks_mut(m).zip(density.chunks(n)) {
+            for (d, &rho) in row.iter_mut().zip(density_row) {
+                *d = Complex64::new(rho, 0.0);
+            }
+        }
+
+        fft_2d(&mut data, m, &self.fft);
+        data.par_iter_mut()
+            .zip(self.green.par_iter())
+            .for_each(|(d, g)| *d *= g);
+        fft_2d(&mut data, m, &self.ifft);
+
+        let scale = 1.0 / (m * m) as f64;
+        data.chunks(m)
+            .take(n)
+            .flat_map(|row| row[..n].iter().map(|d| d.re * scale))
+            .collect()
+    }
+
+    // Gradient of the potential by fourth-order central differences
+    fn gradient(&self, potential: &[f64]) -> Vec<[f64; 2]> {
+        let n = self.size as i64;
+        let h = self.cell_size();
+        let periodic = self.boundary == MeshBoundary::Periodic;
+        let at = |i: i64, j: i64| {
+            let (i, j) = if periodic {
+                (i.rem_euclid(n), j.rem_euclid(n))
+            } else {
+                (i.clamp(0, n - 1), j.clamp(0, n - 1))
+            };
+            potential[(j * n + i) as usize]
+        };
+        (0..n * n)
+            .into_par_iter()
+            .map(|cell| {
+                let (i, j) = (cell % n, cell / n);
+                let dx = (8.0 * (at(i + 1, j) - at(i - 1, j)) - (at(i + 2, j) - at(i - 2, j)))
+                    / (12.0 * h[0]);
+                let dy = (8.0 * (at(i, j + 1) - at(i, j - 1)) - (at(i, j + 2) - at(i, j - 2)))
+                    / (12.0 * h[1]);
+                [dx, dy]
+            })
+            .collect()
+    }
+}
+
+// In-place 2D transform of a row-major m x m array, rows then columns
+fn fft_2d(data: &mut [Complex64], m: usize, fft: &Arc<dyn Fft<f64>>) {
+    data.par_chunks_mut(m).for_each(|row| fft.process(row));
+    let mut transposed = transpose(data, m);
+    transposed
+        .par_chunks_mut(m)
+        .for_each(|column| fft.process(column));
+    data.copy_from_slice(&transpose(&transposed, m));
+}
+
+fn transpose(data: &[Complex64], m: usize) -> Vec<Complex64> {
+    (0..m * m)
+        .into_par_iter()
+        .map(|cell| data[(cell % m) * m + cell / m])
+        .collect()
+}
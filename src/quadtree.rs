@@ -1,28 +1,441 @@
-use crate::forces::GRAVIT_CONST;
+use crate::ewald::EwaldTable;
+use crate::forces::{minimum_image, GRAVIT_CONST};
+use crate::pair_force::{Coupling, Gravity, PairForce};
 use crate::particle::Particle;
+use rayon::prelude::*;
 
+// Highest multipole moment used for the far field of the nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MultipoleOrder {
+    Monopole,
+    Quadrupole,
+    Octupole,
+}
+
+// When a node is far enough from a particle to be used as a whole
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpeningCriterion {
+    // width / d < theta, with d the distance to the centre of mass
+    Geometric,
+    // bmax / d < theta (Salmon & Warren), with bmax the distance from the centre
+    // of mass to the farthest corner of the node
+    SalmonWarren,
+    // G M width^2 / d^4 <= alpha |a| (GADGET), with |a| the acceleration of the
+    // particle at the previous evaluation. Falls back on the geometric criterion
+    // when no previous acceleration is known.
+    Relative { alpha: f64 },
+}
+
+// Parameters of a tree walk, for a tree built with the coupling of the force
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TreeWalk<F = Gravity> {
+    pub theta: f64,
+    pub criterion: OpeningCriterion,
+    // Always open the nodes containing the particle, whatever the criterion says
+    pub open_containing: bool,
+    pub multipole_order: MultipoleOrder,
+    pub force: F,
+}
+
+impl<F: PairForce> TreeWalk<F> {
+    pub(crate) fn accepts(
+        &self,
+        node: &impl Cell,
+        particle: &Particle,
+        dist_sq: f64,
+        previous_acc: Option<f64>,
+    ) -> bool {
+        if dist_sq == 0.0 || (self.open_containing && node.contains_position(particle.position)) {
+            return false;
+        }
+
+        let boundary = node.boundary();
+        let width = boundary[2] - boundary[0];
+        // The relative criterion estimates the error of inverse-square forces
+        let strength = self.force.inverse_square_strength();
+        match (self.criterion, previous_acc, strength) {
+            (OpeningCriterion::SalmonWarren, _, _) => {
+                let bmax = node.bmax();
+                bmax * bmax < self.theta * self.theta * dist_sq
+            }
+            (OpeningCriterion::Relative { alpha }, Some(acc), Some(strength)) => {
+                let mass = node.abs_mass();
+                strength.abs() * mass * width * width <= alpha * acc * dist_sq * dist_sq
+            }
+            _ => width * width < self.theta * self.theta * dist_sq,
+        }
+    }
+}
+
+// Node of a tree, as seen by the far-field evaluation. The moments follow the
+// layout of the QuadTree fields.
+pub(crate) trait Cell {
+    fn boundary(&self) -> [f64; 4];
+    fn mass(&self) -> f64;
+    fn abs_mass(&self) -> f64;
+    fn center_of_mass(&self) -> [f64; 2];
+    fn dipole(&self) -> [f64; 2];
+    fn second_moment(&self) -> [f64; 3];
+    fn third_moment(&self) -> [f64; 4];
+    fn max_softening(&self) -> f64;
+
+    fn contains_position(&self, position: [f64; 2]) -> bool {
+        let [x_min, y_min, x_max, y_max] = self.boundary();
+        (position[0] >= x_min)
+            && (position[0] <= x_max)
+            && (position[1] >= y_min)
+            && (position[1] <= y_max)
+    }
+
+    // Distance from the centre of mass to the farthest corner
+    fn bmax(&self) -> f64 {
+        let [x_min, y_min, x_max, y_max] = self.boundary();
+        let com = self.center_of_mass();
+        let dx = (com[0] - x_min).max(x_max - com[0]);
+        let dy = (com[1] - y_min).max(y_max - com[1]);
+        (dx * dx + dy * dy).sqrt()
+    }
+
+    // Force of the whole cell on a particle, `dx` and `dist_sq` being the
+    // separation from the particle to the centre of mass. The monopole and the
+    // dipole are softened with the largest length of the cell; higher orders
+    // are only added where that softening no longer matters.
+    fn far_field_force<F: PairForce>(
+        &self,
+        particle: &Particle,
+        dx: [f64; 2],
+        dist_sq: f64,
+        walk: &TreeWalk<F>,
+        ewald: Option<&EwaldTable>,
+    ) -> [f64; 2] {
+        let force = &walk.force;
+        let softening = force.softening();
+        let length = softening.pair_length(particle.softening, self.max_softening());
+        let coupling = force.coupling().of(particle);
+        let mut total_force = force.force_at(coupling, self.mass(), dx, length);
+        // Masses are all positive, so that their dipole about the centre of
+        // mass vanishes. Signed charges keep one, the leading term of the
+        // field of a neutral cell: the gradient of s u(r) along the dipole,
+        // s (f p + f'/r (p.d) d).
+        if force.coupling() == Coupling::Charge {
+            let p = self.dipole();
+            let (f, derivative) = force.force_factor_and_derivative(dist_sq, length);
+            let pd = p[0] * dx[0] + p[1] * dx[1];
+            total_force[0] += coupling * (f * p[0] + derivative * pd * dx[0]);
+            total_force[1] += coupling * (f * p[1] + derivative * pd * dx[1]);
+        }
+        let Some(strength) = force.inverse_square_strength() else {
+            return total_force;
+        };
+
+        let reach = softening.reach(length);
+        if walk.multipole_order >= MultipoleOrder::Quadrupole && dist_sq > reach * reach {
+            let order = walk.multipole_order;
+            let acc = self.higher_order_acceleration([-dx[0], -dx[1]], dist_sq, order);
+            total_force[0] += strength * coupling * acc[0];
+            total_force[1] += strength * coupling * acc[1];
+        }
+        // The other images only see the monopole
+        if let Some(ewald) = ewald {
+            let correction = ewald.correction(dx);
+            let scale = strength / GRAVIT_CONST * self.mass() * coupling;
+            total_force[0] += scale * correction[0];
+            total_force[1] += scale * correction[1];
+        }
+        total_force
+    }
+
+    // Quadrupole and octupole terms of the acceleration at x from the centre of
+    // mass for G = 1, which hold about any centre, from the traceless tensors
+    // Q = 3 I2 - tr(I2) and O_ijk = 15 I3_ijk - 3 (V_i d_jk + V_j d_ik + V_k d_ij), V_i = I3_ill
+    fn higher_order_acceleration(&self, x: [f64; 2], r_sq: f64, order: MultipoleOrder) -> [f64; 2] {
+        let r2 = r_sq;
+        let r5 = r2 * r2 * r2.sqrt();
+        let r7 = r5 * r2;
+
+        let [i_xx, i_xy, i_yy] = self.second_moment();
+        let trace = i_xx + i_yy;
+        let qx = [
+            (3.0 * i_xx - trace) * x[0] + 3.0 * i_xy * x[1],
+            3.0 * i_xy * x[0] + (3.0 * i_yy - trace) * x[1],
+        ];
+        let xqx = x[0] * qx[0] + x[1] * qx[1];
+        let mut acc = [
+            qx[0] / r5 - 2.5 * xqx * x[0] / r7,
+            qx[1] / r5 - 2.5 * xqx * x[1] / r7,
+        ];
+
+        if order >= MultipoleOrder::Octupole {
+            let r9 = r7 * r2;
+            let [i_xxx, i_xxy, i_xyy, i_yyy] = self.third_moment();
+            let v = [i_xxx + i_xyy, i_xxy + i_yyy];
+            let v_dot_x = v[0] * x[0] + v[1] * x[1];
+            // I3 contracted twice with x
+            let t = [
+                i_xxx * x[0] * x[0] + 2.0 * i_xxy * x[0] * x[1] + i_xyy * x[1] * x[1],
+                i_xxy * x[0] * x[0] + 2.0 * i_xyy * x[0] * x[1] + i_yyy * x[1] * x[1],
+            ];
+            let oxxx = 15.0 * (t[0] * x[0] + t[1] * x[1]) - 9.0 * r2 * v_dot_x;
+            for d in 0..2 {
+                let oxx = 15.0 * t[d] - 3.0 * (v[d] * r2 + 2.0 * x[d] * v_dot_x);
+                acc[d] += 0.5 * oxx / r7 - 7.0 / 6.0 * oxxx * x[d] / r9;
+            }
+        }
+
+        acc
+    }
+}
+
+// Separation from `position` to `target`, to the nearest image in a periodic box
+pub(crate) fn separation(
+    position: [f64; 2],
+    target: [f64; 2],
+    ewald: Option<&EwaldTable>,
+) -> [f64; 2] {
+    let d = [target[0] - position[0], target[1] - position[1]];
+    minimum_image(d, ewald.map(EwaldTable::period))
+}
+
+// Exact force of `other` on `particle`, and of its periodic images if any.
+// Forces other than inverse-square ones only see the nearest image.
+pub(crate) fn pair_force<F: PairForce>(
+    particle: &Particle,
+    other: &Particle,
+    force: &F,
+    ewald: Option<&EwaldTable>,
+) -> [f64; 2] {
+    let d = separation(particle.position, other.position, ewald);
+    let length = force
+        .softening()
+        .pair_length(particle.softening, other.softening);
+    let coupling = force.coupling();
+    let (s1, s2) = (coupling.of(particle), coupling.of(other));
+    let mut total_force = force.force_at(s1, s2, d, length);
+    if let (Some(ewald), Some(strength)) = (ewald, force.inverse_square_strength()) {
+        let correction = ewald.correction(d);
+        let scale = strength / GRAVIT_CONST * s1 * s2;
+        total_force[0] += scale * correction[0];
+        total_force[1] += scale * correction[1];
+    }
+    total_force
+}
+
+// Adds the moments of `child`, shifted to `center`, to the given dipole and
+// moments
+pub(crate) fn add_shifted_moments(
+    dipole: &mut [f64; 2],
+    second_moment: &mut [f64; 3],
+    third_moment: &mut [f64; 4],
+    center: [f64; 2],
+    child: &impl Cell,
+) {
+    let child_com = child.center_of_mass();
+    let d = [child_com[0] - center[0], child_com[1] - center[1]];
+    let m = child.mass();
+    let p = child.dipole();
+    dipole[0] += p[0] + m * d[0];
+    dipole[1] += p[1] + m * d[1];
+
+    let [i_xx, i_xy, i_yy] = child.second_moment();
+    second_moment[0] += i_xx + 2.0 * p[0] * d[0] + m * d[0] * d[0];
+    second_moment[1] += i_xy + p[0] * d[1] + p[1] * d[0] + m * d[0] * d[1];
+    second_moment[2] += i_yy + 2.0 * p[1] * d[1] + m * d[1] * d[1];
+
+    let [i_xxx, i_xxy, i_xyy, i_yyy] = child.third_moment();
+    let (dxx, dxy, dyy) = (d[0] * d[0], d[0] * d[1], d[1] * d[1]);
+    third_moment[0] += i_xxx + 3.0 * i_xx * d[0] + 3.0 * p[0] * dxx + m * dxx * d[0];
+    third_moment[1] +=
+        i_xxy + i_xx * d[1] + 2.0 * i_xy * d[0] + p[1] * dxx + 2.0 * p[0] * dxy + m * dxx * d[1];
+    third_moment[2] +=
+        i_xyy + i_yy * d[0] + 2.0 * i_xy * d[1] + p[0] * dyy + 2.0 * p[1] * dxy + m * d[0] * dyy;
+    third_moment[3] += i_yyy + 3.0 * i_yy * d[1] + 3.0 * p[1] * dyy + m * dyy * d[1];
+}
+
+// Leaves hold up to `leaf_capacity` particles before being split, and are never
+// split beyond `max_depth`, where they keep any number of particles. This bounds
+// the recursion when particles coincide or nearly do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeLimits {
+    pub leaf_capacity: usize,
+    pub max_depth: u32,
+}
+
+impl TreeLimits {
+    // Levels of the linear tree are limited by the 32 bits per coordinate of
+    // its Morton keys, and cells deeper than that are below the resolution of
+    // most positions anyway
+    pub const MAX_DEPTH: u32 = 32;
+}
+
+impl Default for TreeLimits {
+    fn default() -> Self {
+        TreeLimits {
+            leaf_capacity: 1,
+            max_depth: TreeLimits::MAX_DEPTH,
+        }
+    }
+}
+
+// Dipole and raw moments of a set of particles about `center`, weighted by
+// their coupling
+pub(crate) fn bucket_moments(
+    particles: &[Particle],
+    center: [f64; 2],
+    coupling: Coupling,
+) -> ([f64; 2], [f64; 3], [f64; 4]) {
+    let mut dipole = [0.0; 2];
+    let mut second_moment = [0.0; 3];
+    let mut third_moment = [0.0; 4];
+    for p in particles {
+        let s = [p.position[0] - center[0], p.position[1] - center[1]];
+        let m = coupling.of(p);
+        dipole[0] += m * s[0];
+        dipole[1] += m * s[1];
+        second_moment[0] += m * s[0] * s[0];
+        second_moment[1] += m * s[0] * s[1];
+        second_moment[2] += m * s[1] * s[1];
+        third_moment[0] += m * s[0] * s[0] * s[0];
+        third_moment[1] += m * s[0] * s[0] * s[1];
+        third_moment[2] += m * s[0] * s[1] * s[1];
+        third_moment[3] += m * s[1] * s[1] * s[1];
+    }
+    (dipole, second_moment, third_moment)
+}
+
+// Masses and moments are those of the coupling of the tree: the mass of the
+// particles for gravity, their charge for electrostatics. Signed charges may
+// cancel out, so the centre of mass is weighted by the absolute couplings,
+// which keeps it inside the node, and the moments about it start with a
+// dipole.
 #[derive(Debug)]
 pub struct QuadTree {
     pub boundary: [f64; 4], // [x_min, y_min, x_max, y_max]
     pub mass: f64,
+    // Sum of the absolute couplings, equal to the mass for gravity
+    pub abs_mass: f64,
     pub center_of_mass: [f64; 2],
-    pub particle: Option<Particle>,
+    // sum(m s) of the positions s relative to the centre of mass, zero for
+    // positive masses
+    pub dipole: [f64; 2],
+    // Raw moments sum(m s s) and sum(m s s s) of the positions s relative to the
+    // centre of mass, stored as [xx, xy, yy] and [xxx, xxy, xyy, yyy]
+    pub second_moment: [f64; 3],
+    pub third_moment: [f64; 4],
+    // Largest softening length of the particles, for adaptive softening
+    pub max_softening: f64,
+    // Particles of a leaf
+    pub particles: Vec<Particle>,
     pub children: Option<Box<[QuadTree; 4]>>, // 4 children for 2D quadtree
+    pub depth: u32,
+    pub limits: TreeLimits,
+    pub coupling: Coupling,
 }
 
 impl QuadTree {
     pub fn new(boundary: [f64; 4]) -> Self {
+        QuadTree::with_limits(boundary, TreeLimits::default())
+    }
+
+    pub fn with_limits(boundary: [f64; 4], limits: TreeLimits) -> Self {
+        QuadTree::with_coupling(boundary, limits, Coupling::Mass)
+    }
+
+    pub fn with_coupling(boundary: [f64; 4], limits: TreeLimits, coupling: Coupling) -> Self {
+        QuadTree::node(boundary, limits, coupling, 0)
+    }
+
+    fn node(boundary: [f64; 4], limits: TreeLimits, coupling: Coupling, depth: u32) -> Self {
         QuadTree {
             boundary,
             mass: 0.0,
+            abs_mass: 0.0,
             center_of_mass: [0.0, 0.0],
-            particle: None,
+            dipole: [0.0; 2],
+            second_moment: [0.0; 3],
+            third_moment: [0.0; 4],
+            max_softening: 0.0,
+            particles: Vec::new(),
             children: None,
+            depth,
+            limits,
+            coupling,
         }
     }
 }
 
+impl Cell for QuadTree {
+    fn boundary(&self) -> [f64; 4] {
+        self.boundary
+    }
+
+    fn mass(&self) -> f64 {
+        self.mass
+    }
+
+    fn abs_mass(&self) -> f64 {
+        self.abs_mass
+    }
+
+    fn center_of_mass(&self) -> [f64; 2] {
+        self.center_of_mass
+    }
+
+    fn dipole(&self) -> [f64; 2] {
+        self.dipole
+    }
+
+    fn second_moment(&self) -> [f64; 3] {
+        self.second_moment
+    }
+
+    fn third_moment(&self) -> [f64; 4] {
+        self.third_moment
+    }
+
+    fn max_softening(&self) -> f64 {
+        self.max_softening
+    }
+}
+
 impl QuadTree {
+    // Smallest square containing all the particles, padded so that none of them
+    // lies on its boundary
+    pub fn bounding_square(particles: &[Particle]) -> [f64; 4] {
+        let [x_min, y_min, x_max, y_max] = particles
+            .par_iter()
+            .map(|p| [p.position[0], p.position[1], p.position[0], p.position[1]])
+            .reduce(
+                || {
+                    [
+                        f64::INFINITY,
+                        f64::INFINITY,
+                        f64::NEG_INFINITY,
+                        f64::NEG_INFINITY,
+                    ]
+                },
+                |a, b| {
+                    [
+                        a[0].min(b[0]),
+                        a[1].min(b[1]),
+                        a[2].max(b[2]),
+                        a[3].max(b[3]),
+                    ]
+                },
+            );
+        if particles.is_empty() {
+            return [-1.0, -1.0, 1.0, 1.0];
+        }
+
+        let center = [0.5 * (x_min + x_max), 0.5 * (y_min + y_max)];
+        let half_width = (0.5 * (x_max - x_min).max(y_max - y_min) * 1.01).max(1e-8);
+        [
+            center[0] - half_width,
+            center[1] - half_width,
+            center[0] + half_width,
+            center[1] + half_width,
+        ]
+    }
+
     pub fn insert(&mut self, particle: Particle) -> bool {
         // Check if the particle is out of bounds
         if !self.contains(&particle) {
@@ -31,26 +444,23 @@ impl QuadTree {
 
         self.add_mass(particle);
 
-        // If the node is empty, insert the particle
-        if self.particle.is_none() && self.children.is_none() {
-            self.particle = Some(particle);
-            return true;
-        }
-
         // If the node is already subdivided, pass the particle to the children
-        if !self.children.is_none() {
+        if self.children.is_some() {
             return self.insert_child(particle);
         }
 
-        // If the node contains a particle, subdivide and redistribute
-        if self.particle.is_some() {
-            self.subdivide();
-            let existing_particle = self.particle.take().unwrap();
-            self.insert_child(existing_particle);
-            return self.insert_child(particle);
+        // If the leaf has room left, or cannot be split any more, keep the particle
+        if self.particles.len() < self.limits.leaf_capacity || self.depth >= self.limits.max_depth {
+            self.particles.push(particle);
+            return true;
         }
 
-        false
+        // Otherwise, subdivide and redistribute
+        self.subdivide();
+        for existing_particle in std::mem::take(&mut self.particles) {
+            self.insert_child(existing_particle);
+        }
+        self.insert_child(particle)
     }
 
     fn contains(&self, particle: &Particle) -> bool {
@@ -66,11 +476,12 @@ impl QuadTree {
         let mid_x = (x_min + x_max) / 2.0;
         let mid_y = (y_min + y_max) / 2.0;
 
+        let (limits, coupling, depth) = (self.limits, self.coupling, self.depth + 1);
         self.children = Some(Box::new([
-            QuadTree::new([x_min, y_min, mid_x, mid_y]),
-            QuadTree::new([mid_x, y_min, x_max, mid_y]),
-            QuadTree::new([x_min, mid_y, mid_x, y_max]),
-            QuadTree::new([mid_x, mid_y, x_max, y_max]),
+            QuadTree::node([x_min, y_min, mid_x, mid_y], limits, coupling, depth),
+            QuadTree::node([mid_x, y_min, x_max, mid_y], limits, coupling, depth),
+            QuadTree::node([x_min, mid_y, mid_x, y_max], limits, coupling, depth),
+            QuadTree::node([mid_x, mid_y, x_max, y_max], limits, coupling, depth),
         ]));
     }
 
@@ -79,101 +490,151 @@ impl QuadTree {
         let mid_x = (x_min + x_max) / 2.0;
         let mid_y = (y_min + y_max) / 2.0;
 
-        if particle.position[0] >= mid_x {
-            if particle.position[1] >= mid_y {
-                return self.children.as_mut().unwrap()[3].insert(particle);
-            } else {
-                return self.children.as_mut().unwrap()[1].insert(particle);
-            }
-        } else {
-            if particle.position[1] >= mid_y {
-                return self.children.as_mut().unwrap()[2].insert(particle);
-            } else {
-                return self.children.as_mut().unwrap()[0].insert(particle);
-            }
+        let children = self.children.as_mut().unwrap();
+        match (particle.position[0] >= mid_x, particle.position[1] >= mid_y) {
+            (true, true) => children[3].insert(particle),
+            (true, false) => children[1].insert(particle),
+            (false, true) => children[2].insert(particle),
+            (false, false) => children[0].insert(particle),
         }
     }
 
     fn add_mass(&mut self, particle: Particle) {
-        self.center_of_mass[0] += particle.mass * particle.position[0];
-        self.center_of_mass[1] += particle.mass * particle.position[1];
-        self.mass += particle.mass;
+        let mass = self.coupling.of(&particle);
+        self.center_of_mass[0] += mass.abs() * particle.position[0];
+        self.center_of_mass[1] += mass.abs() * particle.position[1];
+        self.mass += mass;
+        self.abs_mass += mass.abs();
+        self.max_softening = self.max_softening.max(particle.softening);
     }
 
     pub fn finalize(&mut self) {
-        if self.mass != 0.0 {
-            self.center_of_mass[0] /= self.mass;
-            self.center_of_mass[1] /= self.mass;
+        if self.abs_mass != 0.0 {
+            self.center_of_mass[0] /= self.abs_mass;
+            self.center_of_mass[1] /= self.abs_mass;
         }
 
-        if !self.children.is_none() {
-            for child in self.children.as_mut().unwrap().iter_mut() {
+        // Moments of the particles of a leaf, or of the children shifted to the
+        // centre of mass of this node
+        (self.dipole, self.second_moment, self.third_moment) =
+            bucket_moments(&self.particles, self.center_of_mass, self.coupling);
+        if let Some(children) = self.children.as_mut() {
+            for child in children.iter_mut() {
                 child.finalize();
+                if child.abs_mass != 0.0 {
+                    add_shifted_moments(
+                        &mut self.dipole,
+                        &mut self.second_moment,
+                        &mut self.third_moment,
+                        self.center_of_mass,
+                        child,
+                    );
+                }
             }
         }
     }
 
-    pub fn compute_force(&self, particle: &Particle, theta: f64) -> [f64; 2] {
-        if self.mass == 0.0 {
+    // `previous_acc` is the magnitude of the acceleration of the particle at the
+    // previous evaluation, used by the relative opening criterion. With an Ewald
+    // table, the tree covers a periodic box: separations go to the nearest
+    // image, and the forces include all the periodic images.
+    pub fn compute_force<F: PairForce>(
+        &self,
+        particle: &Particle,
+        walk: &TreeWalk<F>,
+        previous_acc: Option<f64>,
+        ewald: Option<&EwaldTable>,
+    ) -> [f64; 2] {
+        if self.abs_mass == 0.0 {
             return [0.0, 0.0];
         }
 
-        let dx = self.center_of_mass[0] - particle.position[0];
-        let dy = self.center_of_mass[1] - particle.position[1];
-        let dist_sq = dx * dx + dy * dy;
-        let dist = dist_sq.sqrt();
+        let d = separation(particle.position, self.center_of_mass, ewald);
+        let dist_sq = d[0] * d[0] + d[1] * d[1];
 
         // If the node is far enough, use approximation
-        if dist > 0.0 && (self.boundary[2] - self.boundary[0]) / dist < theta {
-            let dist_sq = dist_sq.max(1.0);
-            let force = GRAVIT_CONST * self.mass * particle.mass / dist_sq;
-            return [force * dx / dist, force * dy / dist];
+        if walk.accepts(self, particle, dist_sq, previous_acc) {
+            return self.far_field_force(particle, d, dist_sq, walk, ewald);
         }
 
         // Otherwise, traverse into children
         if let Some(children) = &self.children {
             let mut total_force = [0.0, 0.0];
             for child in children.iter() {
-                let child_force = child.compute_force(particle, theta);
+                let child_force = child.compute_force(particle, walk, previous_acc, ewald);
                 total_force[0] += child_force[0];
                 total_force[1] += child_force[1];
             }
             return total_force;
         }
 
-        [0.0, 0.0]
+        // Leaves are evaluated exactly, which gives no force on the particle itself
+        let mut total_force = [0.0, 0.0];
+        for other in self.particles.iter() {
+            let force = pair_force(particle, other, &walk.force, ewald);
+            total_force[0] += force[0];
+            total_force[1] += force[1];
+        }
+        total_force
     }
 
+    // Merges a tree built over the same region into this one, before either is
+    // finalized, so that the result is the tree a serial build over both sets
+    // of particles would give. `other` is left empty.
     pub fn merge(&mut self, other: &mut QuadTree) {
         assert_eq!(
             self.boundary, other.boundary,
             "Regions must match for merging"
         );
+        assert_eq!(self.limits, other.limits, "Limits must match for merging");
+        assert_eq!(
+            self.coupling, other.coupling,
+            "Couplings must match for merging"
+        );
 
-        if let Some(particle) = other.particle {
-            self.insert(particle);
+        // The particles of a leaf of the other tree are inserted one by one,
+        // which accounts for their mass on the way down
+        if other.children.is_none() {
+            for particle in std::mem::take(&mut other.particles) {
+                self.insert(particle);
+            }
+            other.mass = 0.0;
+            other.abs_mass = 0.0;
+            other.center_of_mass = [0.0, 0.0];
             return;
         }
 
-        if other.mass > 0.0 {
-            self.mass += other.mass;
-            self.center_of_mass = [
-                (self.center_of_mass[0] + other.center_of_mass[0]),
-                (self.center_of_mass[1] + other.center_of_mass[1]),
-            ];
-        }
+        let Some(mut other_children) = other.children.take() else {
+            return;
+        };
 
-        if let Some(mut self_children) = self.children.take() {
-            if let Some(other_children) = other.children.take().as_mut() {
+        // Mass and mass-weighted position are plain sums until finalize
+        self.mass += other.mass;
+        self.abs_mass += other.abs_mass;
+        self.center_of_mass[0] += other.center_of_mass[0];
+        self.center_of_mass[1] += other.center_of_mass[1];
+        self.max_softening = self.max_softening.max(other.max_softening);
+        other.mass = 0.0;
+        other.abs_mass = 0.0;
+        other.center_of_mass = [0.0, 0.0];
+        other.max_softening = 0.0;
+
+        match self.children.as_mut() {
+            Some(self_children) => {
                 for (self_child, other_child) in
                     self_children.iter_mut().zip(other_children.iter_mut())
                 {
                     self_child.merge(other_child);
                 }
-                self.children = Some(self_children);
             }
-        } else if let Some(other_children) = other.children.take() {
-            self.children = Some(other_children);
+            None => {
+                // Adopt the other children, and push down the particles of this
+                // leaf. Their mass is already counted here.
+                self.children = Some(other_children);
+                for particle in std::mem::take(&mut self.particles) {
+                    self.insert_child(particle);
+                }
+            }
         }
     }
 }
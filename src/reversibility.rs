@@ -0,0 +1,84 @@
+use crate::particle::Particle;
+use crate::simulation::Simulation;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReversalMethod {
+    // Run back by flipping the velocities and stepping forward in time
+    FlipVelocities,
+    // Run back with negated time steps
+    NegateTimestep,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReversibilityReport {
+    pub steps: usize,
+    pub max_position_error: f64,
+    pub rms_position_error: f64,
+    pub max_velocity_error: f64,
+    // Relative energy difference between the initial and the final state, see
+    // Diagnostics::energy_error
+    pub energy_error: f64,
+}
+
+// Runs `steps` steps forward, then the same steps backward, and compares the
+// result with the initial particles. With a time step controller, the time
+// steps chosen on the way forward are replayed in reverse order on the way back.
+// Exactly time-reversible schemes, such as the leapfrog, only differ from the
+// initial state by round-off errors.
+pub fn time_reversal_test(
+    simulation: &mut Simulation,
+    steps: usize,
+    method: ReversalMethod,
+) -> ReversibilityReport {
+    let initial: Vec<Particle> = simulation.particles.clone();
+    let initial_diagnostics = simulation.diagnostics();
+    let initial_time = simulation.time;
+    let initial_dt = simulation.dt;
+
+    let mut timesteps = Vec::with_capacity(steps);
+    for _ in 0..steps {
+        simulation.simulation_step();
+        timesteps.push(simulation.dt);
+    }
+
+    let controller = simulation.take_timestep_controller();
+    if method == ReversalMethod::FlipVelocities {
+        simulation.reverse_velocities();
+    }
+    for &dt in timesteps.iter().rev() {
+        simulation.dt = match method {
+            ReversalMethod::FlipVelocities => dt,
+            ReversalMethod::NegateTimestep => -dt,
+        };
+        simulation.simulation_step();
+    }
+    if method == ReversalMethod::FlipVelocities {
+        simulation.reverse_velocities();
+    }
+
+    simulation.set_timestep_controller(controller);
+    simulation.dt = initial_dt;
+    simulation.time = initial_time;
+
+    let mut max_position_error: f64 = 0.0;
+    let mut sum_sq_position_error = 0.0;
+    let mut max_velocity_error: f64 = 0.0;
+    for (p, p0) in simulation.particles.iter().zip(initial.iter()) {
+        let dx = p.position[0] - p0.position[0];
+        let dy = p.position[1] - p0.position[1];
+        let dvx = p.velocity[0] - p0.velocity[0];
+        let dvy = p.velocity[1] - p0.velocity[1];
+        let position_error = (dx * dx + dy * dy).sqrt();
+        max_position_error = max_position_error.max(position_error);
+        sum_sq_position_error += position_error * position_error;
+        max_velocity_error = max_velocity_error.max((dvx * dvx + dvy * dvy).sqrt());
+    }
+
+    ReversibilityReport {
+        steps,
+        max_position_error,
+        rms_position_error: (sum_sq_position_error / initial.len().max(1) as f64).sqrt(),
+        max_velocity_error,
+        energy_error: simulation.diagnostics().energy_error(&initial_diagnostics),
+    }
+}
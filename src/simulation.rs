@@ -1,164 +1,1822 @@
-use crate::forces::compute_gravity;
-use crate::integrator::time_integration;
+use crate::bonds::{bond_energy, Bond};
+use crate::diagnostics::{pair_potential_energy, Diagnostics};
+use crate::error::{validate_domain, validate_limits, ConfigError};
+use crate::ewald::{period, wrap_position, EwaldTable, EWALD_TABLE_SIZE};
+use crate::fmm::FmmTree;
+use crate::forces::{minimum_image, ForceSplit, GRAVIT_CONST};
+use crate::integrator::{ForceEvaluator, Integrator, StepReport};
+use crate::linear_tree::LinearQuadTree;
+use crate::neighbour_list::VerletList;
+use crate::pair_force::{Gravity, PairForce};
 use crate::particle::Particle;
-use crate::quadtree::QuadTree;
+use crate::pm::{MassAssignment, Mesh, MeshBoundary, ISOLATED_MARGIN};
+use crate::quadtree::{MultipoleOrder, OpeningCriterion, QuadTree, TreeLimits, TreeWalk};
+use crate::softening::{adapt_softening_lengths, Softening};
+use crate::timestep::TimestepController;
 use rayon::prelude::*;
+use std::borrow::Cow;
 
-pub const DIRECT_SUM: i32 = 0;
-pub const DIRECT_SUM_PARALLEL: i32 = 1;
-pub const BARNES_HUT: i32 = 2;
-pub const BARNES_HUT_PARALLEL: i32 = 3;
+pub trait ForceSolver: Send {
+    // Overwrites `forces` with the total force acting on each particle
+    fn compute_forces(&mut self, particles: &[Particle], forces: &mut [[f64; 2]]);
+
+    // Updates the forces of the `active` particles only, leaving the others
+    // untouched. Solvers that can do better than a full evaluation override this.
+    fn compute_active_forces(
+        &mut self,
+        particles: &[Particle],
+        active: &[usize],
+        forces: &mut [[f64; 2]],
+    ) {
+        let mut all_forces = vec![[0.0, 0.0]; particles.len()];
+        self.compute_forces(particles, &mut all_forces);
+        for &i in active {
+            forces[i] = all_forces[i];
+        }
+    }
+
+    // Solvers able to compute the time derivative of the forces override both
+    fn supports_jerk(&self) -> bool {
+        false
+    }
+
+    fn compute_forces_and_jerks(
+        &mut self,
+        _particles: &[Particle],
+        _forces: &mut [[f64; 2]],
+        _jerks: &mut [[f64; 2]],
+    ) {
+        panic!("This solver does not compute jerks");
+    }
+
+    // Particles left out of the last evaluation, e.g. outside of a fixed domain
+    fn dropped_particles(&self) -> usize {
+        0
+    }
+
+    // Box of a solver treating space as periodic, into which the simulation
+    // wraps the positions after each step
+    fn periodic_domain(&self) -> Option<[f64; 4]> {
+        None
+    }
+
+    // Softening of the forces, which the simulation also needs to set adaptive
+    // softening lengths
+    fn softening(&self) -> Softening {
+        Softening::None
+    }
+
+    // G of solvers computing Newtonian gravity between masses in an isolated
+    // domain, for integrators solving the Keplerian part of the motion
+    fn gravitational_constant(&self) -> Option<f64> {
+        None
+    }
+
+    // Potential energy of the particles under the forces of the solver, taking
+    // only the nearest image of each pair in a periodic domain
+    fn potential_energy(&self, particles: &[Particle]) -> f64 {
+        let gravity = Gravity::with_softening(self.softening());
+        pair_potential_energy(particles, &gravity, self.periodic_domain().map(period))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DirectSum<F = Gravity> {
+    force: F,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DirectSumParallel<F = Gravity> {
+    force: F,
+}
+
+impl Default for DirectSum {
+    fn default() -> Self {
+        DirectSum {
+            force: Gravity::default(),
+        }
+    }
+}
+
+impl Default for DirectSumParallel {
+    fn default() -> Self {
+        DirectSumParallel {
+            force: Gravity::default(),
+        }
+    }
+}
+
+impl DirectSum {
+    pub fn new(softening: Softening) -> Result<Self, ConfigError> {
+        DirectSum::with_force(Gravity::with_softening(softening))
+    }
+}
+
+impl DirectSumParallel {
+    pub fn new(softening: Softening) -> Result<Self, ConfigError> {
+        DirectSumParallel::with_force(Gravity::with_softening(softening))
+    }
+}
+
+impl<F: PairForce> DirectSum<F> {
+    pub fn with_force(force: F) -> Result<Self, ConfigError> {
+        force.validate()?;
+        Ok(DirectSum { force })
+    }
+
+    pub fn force(&self) -> F {
+        self.force
+    }
+}
+
+impl<F: PairForce> DirectSumParallel<F> {
+    pub fn with_force(force: F) -> Result<Self, ConfigError> {
+        force.validate()?;
+        Ok(DirectSumParallel { force })
+    }
+
+    pub fn force(&self) -> F {
+        self.force
+    }
+}
+
+// Direct summation in a periodic box, over the nearest images of the particles
+// plus an Ewald correction for all the others
+#[derive(Debug, Clone)]
+pub struct PeriodicDirectSum {
+    domain: [f64; 4],
+    ewald: EwaldTable,
+    softening: Softening,
+}
+
+impl PeriodicDirectSum {
+    pub fn new(domain: [f64; 4]) -> Result<Self, ConfigError> {
+        validate_domain(domain)?;
+        Ok(PeriodicDirectSum {
+            domain,
+            ewald: EwaldTable::new(period(domain), EWALD_TABLE_SIZE),
+            softening: Softening::default(),
+        })
+    }
+
+    pub fn with_softening(mut self, softening: Softening) -> Result<Self, ConfigError> {
+        softening.validate()?;
+        self.softening = softening;
+        Ok(self)
+    }
+}
+
+// Barnes-Hut for any pair force. Quadrupoles, octupoles and Ewald sums only
+// apply to inverse-square forces, see PairForce::inverse_square_strength.
+#[derive(Debug, Clone, Copy)]
+pub struct BarnesHutConfig<F = Gravity> {
+    theta: f64,
+    // Fixed root cell [x_min, y_min, x_max, y_max]. If not given, the root cell
+    // is the bounding square of the particles, recomputed at each evaluation.
+    domain: Option<[f64; 4]>,
+    multipole_order: MultipoleOrder,
+    criterion: OpeningCriterion,
+    open_containing: bool,
+    limits: TreeLimits,
+    // The domain is a periodic box, see with_periodic_domain
+    periodic: bool,
+    force: F,
+}
+
+impl BarnesHutConfig {
+    pub fn new(theta: f64) -> Result<Self, ConfigError> {
+        BarnesHutConfig {
+            theta: 0.0,
+            domain: None,
+            multipole_order: MultipoleOrder::Monopole,
+            criterion: OpeningCriterion::Geometric,
+            open_containing: false,
+            limits: TreeLimits::default(),
+            periodic: false,
+            force: Gravity::default(),
+        }
+        .with_theta(theta)
+    }
+
+    pub fn with_softening(mut self, softening: Softening) -> Result<Self, ConfigError> {
+        softening.validate()?;
+        self.force.softening = softening;
+        Ok(self)
+    }
+}
+
+impl<F: PairForce> BarnesHutConfig<F> {
+    pub fn with_force<G: PairForce>(self, force: G) -> Result<BarnesHutConfig<G>, ConfigError> {
+        force.validate()?;
+        Ok(BarnesHutConfig {
+            theta: self.theta,
+            domain: self.domain,
+            multipole_order: self.multipole_order,
+            criterion: self.criterion,
+            open_containing: self.open_containing,
+            limits: self.limits,
+            periodic: self.periodic,
+            force,
+        })
+    }
+
+    pub fn with_theta(mut self, theta: f64) -> Result<Self, ConfigError> {
+        if !theta.is_finite() || theta < 0.0 {
+            return Err(ConfigError::InvalidTheta(theta));
+        }
+        self.theta = theta;
+        Ok(self)
+    }
+
+    pub fn theta(&self) -> f64 {
+        self.theta
+    }
+
+    pub fn domain(&self) -> Option<[f64; 4]> {
+        self.domain
+    }
+
+    pub fn multipole_order(&self) -> MultipoleOrder {
+        self.multipole_order
+    }
+
+    pub fn criterion(&self) -> OpeningCriterion {
+        self.criterion
+    }
+
+    pub fn limits(&self) -> TreeLimits {
+        self.limits
+    }
+
+    pub fn force(&self) -> F {
+        self.force
+    }
+
+    // Larger leaves mean fewer, shallower nodes, with more direct summation in
+    // each leaf
+    pub fn with_limits(mut self, limits: TreeLimits) -> Result<Self, ConfigError> {
+        validate_limits(limits)?;
+        self.limits = limits;
+        Ok(self)
+    }
+
+    pub fn with_opening_criterion(
+        mut self,
+        criterion: OpeningCriterion,
+    ) -> Result<Self, ConfigError> {
+        if let OpeningCriterion::Relative { alpha } = criterion {
+            if !(alpha.is_finite() && alpha > 0.0) {
+                return Err(ConfigError::InvalidOpeningCriterion(criterion));
+            }
+        }
+        self.criterion = criterion;
+        Ok(self)
+    }
+
+    // Always open the cells containing the target particle. This guards against
+    // accepting a nearby cell whose centre of mass happens to be far away.
+    pub fn with_open_containing(mut self, open_containing: bool) -> Self {
+        self.open_containing = open_containing;
+        self
+    }
+
+    // Higher orders give the same accuracy at a larger theta
+    pub fn with_multipole_order(mut self, order: MultipoleOrder) -> Self {
+        self.multipole_order = order;
+        self
+    }
+
+    // Particles outside of a fixed domain are left out of the tree, and feel no
+    // force. Their number is reported by `ForceSolver::dropped_particles`.
+    pub fn with_domain(mut self, domain: [f64; 4]) -> Result<Self, ConfigError> {
+        validate_domain(domain)?;
+        self.domain = Some(domain);
+        Ok(self)
+    }
+
+    // Makes `domain` one cell of an infinite periodic lattice: positions are
+    // wrapped into it, and forces include all the periodic images through an
+    // Ewald correction. Other forces than inverse-square ones only see the
+    // nearest image.
+    pub fn with_periodic_domain(self, domain: [f64; 4]) -> Result<Self, ConfigError> {
+        let mut config = self.with_domain(domain)?;
+        config.periodic = true;
+        Ok(config)
+    }
+
+    fn ewald_table(&self) -> Option<EwaldTable> {
+        match (self.periodic, self.domain) {
+            (true, Some(domain)) => Some(EwaldTable::new(period(domain), EWALD_TABLE_SIZE)),
+            _ => None,
+        }
+    }
+
+    fn periodic_domain(&self) -> Option<[f64; 4]> {
+        self.domain.filter(|_| self.periodic)
+    }
+
+    // Particles as the tree sees them, wrapped into a periodic domain
+    fn wrapped<'a>(&self, particles: &'a [Particle]) -> Cow<'a, [Particle]> {
+        match self.periodic_domain() {
+            Some(domain) => Cow::Owned(wrap_particles(particles, domain)),
+            None => Cow::Borrowed(particles),
+        }
+    }
+
+    fn walk(&self) -> TreeWalk<F> {
+        TreeWalk {
+            theta: self.theta,
+            criterion: self.criterion,
+            open_containing: self.open_containing,
+            multipole_order: self.multipole_order,
+            force: self.force,
+        }
+    }
+
+    fn root_boundary(&self, particles: &[Particle]) -> [f64; 4] {
+        self.domain
+            .unwrap_or_else(|| QuadTree::bounding_square(particles))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BarnesHut<F = Gravity> {
+    config: BarnesHutConfig<F>,
+    dropped: usize,
+    // Accelerations from the last evaluation, for the relative criterion
+    previous_acc: Vec<f64>,
+    ewald: Option<EwaldTable>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BarnesHutParallel<F = Gravity> {
+    config: BarnesHutConfig<F>,
+    dropped: usize,
+    previous_acc: Vec<f64>,
+    ewald: Option<EwaldTable>,
+}
+
+// Barnes-Hut on a LinearQuadTree, built and walked in parallel
+#[derive(Debug, Clone)]
+pub struct LinearBarnesHut<F = Gravity> {
+    config: BarnesHutConfig<F>,
+    dropped: usize,
+    previous_acc: Vec<f64>,
+    ewald: Option<EwaldTable>,
+}
+
+impl<F: PairForce> BarnesHut<F> {
+    pub fn new(config: BarnesHutConfig<F>) -> Self {
+        BarnesHut {
+            config,
+            dropped: 0,
+            previous_acc: Vec::new(),
+            ewald: config.ewald_table(),
+        }
+    }
+}
+
+impl<F: PairForce> BarnesHutParallel<F> {
+    pub fn new(config: BarnesHutConfig<F>) -> Self {
+        BarnesHutParallel {
+            config,
+            dropped: 0,
+            previous_acc: Vec::new(),
+            ewald: config.ewald_table(),
+        }
+    }
+}
+
+impl<F: PairForce> LinearBarnesHut<F> {
+    pub fn new(config: BarnesHutConfig<F>) -> Self {
+        LinearBarnesHut {
+            config,
+            dropped: 0,
+            previous_acc: Vec::new(),
+            ewald: config.ewald_table(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FmmConfig {
+    // Expansions keep the terms of total degree up to `order`, and cells
+    // interact through them when (r_a + r_b) < theta * d. The error goes
+    // roughly as theta^(order + 1).
+    order: usize,
+    theta: f64,
+    domain: Option<[f64; 4]>,
+    limits: TreeLimits,
+    softening: Softening,
+}
+
+impl FmmConfig {
+    pub fn new(order: usize, theta: f64) -> Result<Self, ConfigError> {
+        if !((1..=30).contains(&order) && theta > 0.0 && theta < 1.0) {
+            return Err(ConfigError::InvalidFmmConfig(order, theta));
+        }
+        Ok(FmmConfig {
+            order,
+            theta,
+            domain: None,
+            // Larger leaves than for Barnes-Hut, as direct sums are cheap next
+            // to the expansions
+            limits: TreeLimits {
+                leaf_capacity: 16,
+                max_depth: TreeLimits::MAX_DEPTH,
+            },
+            softening: Softening::default(),
+        })
+    }
+
+    pub fn with_domain(mut self, domain: [f64; 4]) -> Result<Self, ConfigError> {
+        validate_domain(domain)?;
+        self.domain = Some(domain);
+        Ok(self)
+    }
+
+    pub fn with_limits(mut self, limits: TreeLimits) -> Result<Self, ConfigError> {
+        validate_limits(limits)?;
+        self.limits = limits;
+        Ok(self)
+    }
+
+    pub fn with_softening(mut self, softening: Softening) -> Result<Self, ConfigError> {
+        softening.validate()?;
+        self.softening = softening;
+        Ok(self)
+    }
+
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    pub fn theta(&self) -> f64 {
+        self.theta
+    }
+
+    pub fn domain(&self) -> Option<[f64; 4]> {
+        self.domain
+    }
+
+    pub fn limits(&self) -> TreeLimits {
+        self.limits
+    }
+
+    pub fn softening(&self) -> Softening {
+        self.softening
+    }
+}
+
+// Fast multipole method on a LinearQuadTree, see FmmTree
+#[derive(Debug, Clone)]
+pub struct FastMultipole {
+    config: FmmConfig,
+    dropped: usize,
+}
+
+impl FastMultipole {
+    pub fn new(config: FmmConfig) -> Self {
+        FastMultipole { config, dropped: 0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PmConfig {
+    // Cells per side of the mesh
+    grid_size: usize,
+    boundary: MeshBoundary,
+    assignment: MassAssignment,
+    // Periodic box, or fixed domain of an isolated mesh. If not given, an
+    // isolated mesh covers the bounding square of the particles plus a margin.
+    domain: Option<[f64; 4]>,
+    // Softening of the isolated kernel, and of the short-range forces of
+    // TreePM. Adaptive lengths only apply to the latter, the mesh resolving
+    // nothing below a cell anyway.
+    softening: Softening,
+}
+
+impl PmConfig {
+    pub fn isolated(grid_size: usize) -> Result<Self, ConfigError> {
+        if grid_size < 4 * ISOLATED_MARGIN {
+            return Err(ConfigError::InvalidGridSize(grid_size));
+        }
+        Ok(PmConfig {
+            grid_size,
+            boundary: MeshBoundary::Isolated,
+            assignment: MassAssignment::Cic,
+            domain: None,
+            softening: Softening::default(),
+        })
+    }
+
+    pub fn periodic(grid_size: usize, domain: [f64; 4]) -> Result<Self, ConfigError> {
+        if grid_size < 4 {
+            return Err(ConfigError::InvalidGridSize(grid_size));
+        }
+        PmConfig {
+            grid_size,
+            boundary: MeshBoundary::Periodic,
+            assignment: MassAssignment::Cic,
+            domain: None,
+            softening: Softening::default(),
+        }
+        .with_domain(domain)
+    }
+
+    fn periodic_domain(&self) -> Option<[f64; 4]> {
+        self.domain
+            .filter(|_| self.boundary == MeshBoundary::Periodic)
+    }
+
+    pub fn with_assignment(mut self, assignment: MassAssignment) -> Self {
+        self.assignment = assignment;
+        self
+    }
+
+    pub fn with_softening(mut self, softening: Softening) -> Result<Self, ConfigError> {
+        softening.validate()?;
+        self.softening = softening;
+        Ok(self)
+    }
+
+    pub fn grid_size(&self) -> usize {
+        self.grid_size
+    }
+
+    pub fn boundary(&self) -> MeshBoundary {
+        self.boundary
+    }
+
+    pub fn assignment(&self) -> MassAssignment {
+        self.assignment
+    }
+
+    pub fn domain(&self) -> Option<[f64; 4]> {
+        self.domain
+    }
+
+    pub fn softening(&self) -> Softening {
+        self.softening
+    }
+
+    fn mesh(&self) -> Mesh {
+        Mesh::new(self.grid_size, self.boundary, self.assignment).with_softening(self.softening)
+    }
+
+    // Particles outside of the domain of an isolated mesh are left out, and
+    // feel no force. Those within ISOLATED_MARGIN cells of its edges get
+    // less accurate forces.
+    pub fn with_domain(mut self, domain: [f64; 4]) -> Result<Self, ConfigError> {
+        validate_domain(domain)?;
+        self.domain = Some(domain);
+        Ok(self)
+    }
+}
+
+// Particle-mesh solver: forces smoothed over a few cells, at a cost dominated
+// by the FFTs of the mesh
+#[derive(Debug, Clone)]
+pub struct ParticleMesh {
+    config: PmConfig,
+    mesh: Mesh,
+    dropped: usize,
+}
+
+impl ParticleMesh {
+    pub fn new(config: PmConfig) -> Self {
+        ParticleMesh {
+            config,
+            mesh: config.mesh(),
+            dropped: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TreePmConfig {
+    mesh: PmConfig,
+    // Scale r_s of the force split in mesh cells, and cutoff of the
+    // short-range forces in units of r_s
+    split: f64,
+    cutoff: f64,
+    // Opening angle of the short-range tree walk
+    theta: f64,
+    limits: TreeLimits,
+}
+
+impl TreePmConfig {
+    pub fn new(mesh: PmConfig, theta: f64) -> Result<Self, ConfigError> {
+        if !theta.is_finite() || theta < 0.0 {
+            return Err(ConfigError::InvalidTheta(theta));
+        }
+        // GADGET's defaults: the long-range forces stay well resolved by the
+        // mesh, and the short-range ones are down to 2% of the full force at
+        // the cutoff
+        let config = TreePmConfig {
+            mesh,
+            split: 1.25,
+            cutoff: 4.5,
+            theta,
+            limits: TreeLimits::default(),
+        };
+        config.check_split(config.split, config.cutoff)?;
+        Ok(config)
+    }
+
+    pub fn with_split(mut self, split: f64, cutoff: f64) -> Result<Self, ConfigError> {
+        self.check_split(split, cutoff)?;
+        self.split = split;
+        self.cutoff = cutoff;
+        Ok(self)
+    }
+
+    fn check_split(&self, split: f64, cutoff: f64) -> Result<(), ConfigError> {
+        if !(split.is_finite() && split > 0.0 && cutoff.is_finite() && cutoff > 0.0) {
+            return Err(ConfigError::InvalidForceSplit(split, cutoff));
+        }
+        // Short-range forces only reach the nearest periodic image
+        if let (MeshBoundary::Periodic, Some([x_min, y_min, x_max, y_max])) =
+            (self.mesh.boundary, self.mesh.domain)
+        {
+            let (width, height) = (x_max - x_min, y_max - y_min);
+            let cell = width.max(height) / self.mesh.grid_size as f64;
+            if split * cutoff * cell >= 0.5 * width.min(height) {
+                return Err(ConfigError::InvalidForceSplit(split, cutoff));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn with_limits(mut self, limits: TreeLimits) -> Result<Self, ConfigError> {
+        validate_limits(limits)?;
+        self.limits = limits;
+        Ok(self)
+    }
+
+    pub fn mesh(&self) -> PmConfig {
+        self.mesh
+    }
+
+    pub fn split(&self) -> f64 {
+        self.split
+    }
+
+    pub fn cutoff(&self) -> f64 {
+        self.cutoff
+    }
+
+    pub fn theta(&self) -> f64 {
+        self.theta
+    }
+
+    pub fn limits(&self) -> TreeLimits {
+        self.limits
+    }
+}
+
+// TreePM: long-range forces from a particle mesh, short-range forces from a
+// walk of a LinearQuadTree limited to the cutoff. In periodic boxes, this gives
+// the forces of all the periodic images without any Ewald summation.
+#[derive(Debug, Clone)]
+pub struct TreePm {
+    config: TreePmConfig,
+    mesh: Mesh,
+    dropped: usize,
+}
+
+impl TreePmConfig {
+    fn periodic_domain(&self) -> Option<[f64; 4]> {
+        self.mesh.periodic_domain()
+    }
+}
+
+impl TreePm {
+    pub fn new(config: TreePmConfig) -> Self {
+        TreePm {
+            config,
+            mesh: config.mesh.mesh().with_split(config.split),
+            dropped: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NeighbourListConfig<F> {
+    // Force with a cutoff, e.g. LennardJones or Morse
+    force: F,
+    // Margin beyond the cutoff kept in the lists, which are built anew once a
+    // particle has moved by half of it
+    skin: f64,
+    // Periodic box, if any
+    domain: Option<[f64; 4]>,
+}
+
+impl<F: PairForce> NeighbourListConfig<F> {
+    // The default skin of a tenth of the cutoff is the usual 0.25 sigma for
+    // Lennard-Jones with a cutoff of 2.5 sigma
+    pub fn new(force: F) -> Result<Self, ConfigError> {
+        force.validate()?;
+        let cutoff = force.cutoff().unwrap_or(f64::INFINITY);
+        NeighbourListConfig {
+            force,
+            skin: 0.0,
+            domain: None,
+        }
+        .with_skin(0.1 * cutoff)
+    }
+
+    pub fn with_skin(mut self, skin: f64) -> Result<Self, ConfigError> {
+        self.skin = skin;
+        self.validate()?;
+        Ok(self)
+    }
+
+    // Makes `domain` one cell of an infinite periodic lattice. Pairs only
+    // interact through their nearest image, so the cutoff and the skin must
+    // reach less than half the box.
+    pub fn with_periodic_domain(mut self, domain: [f64; 4]) -> Result<Self, ConfigError> {
+        validate_domain(domain)?;
+        self.domain = Some(domain);
+        self.validate()?;
+        Ok(self)
+    }
+
+    pub fn force(&self) -> F {
+        self.force
+    }
+
+    pub fn skin(&self) -> f64 {
+        self.skin
+    }
+
+    pub fn domain(&self) -> Option<[f64; 4]> {
+        self.domain
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let cutoff = self.force.cutoff().unwrap_or(f64::INFINITY);
+        let reach = cutoff + self.skin;
+        let half_box = match self.domain {
+            Some(domain) => {
+                let [width, height] = period(domain);
+                0.5 * width.min(height)
+            }
+            None => f64::INFINITY,
+        };
+        if !(cutoff.is_finite() && self.skin.is_finite() && self.skin >= 0.0 && reach < half_box) {
+            return Err(ConfigError::InvalidNeighbourList(cutoff, self.skin));
+        }
+        Ok(())
+    }
+}
+
+// Short-range forces summed over Verlet lists built from cell lists, in O(N)
+// for a fixed density, in place of a tree
+#[derive(Debug, Clone)]
+pub struct NeighbourList<F> {
+    config: NeighbourListConfig<F>,
+    list: Option<VerletList>,
+    rebuilds: usize,
+}
+
+impl<F: PairForce> NeighbourList<F> {
+    pub fn new(config: NeighbourListConfig<F>) -> Self {
+        NeighbourList {
+            config,
+            list: None,
+            rebuilds: 0,
+        }
+    }
+
+    // Number of times the lists were built so far
+    pub fn rebuilds(&self) -> usize {
+        self.rebuilds
+    }
+
+    // Particles wrapped into the periodic box if any, with lists up to date
+    fn prepare<'a>(&mut self, particles: &'a [Particle]) -> Cow<'a, [Particle]> {
+        let particles = match self.config.domain {
+            Some(domain) => Cow::Owned(wrap_particles(particles, domain)),
+            None => Cow::Borrowed(particles),
+        };
+        let stale = match &self.list {
+            Some(list) => list.is_stale(&particles, self.config.skin),
+            None => true,
+        };
+        if stale {
+            let cutoff = self.config.force.cutoff().unwrap();
+            let domain = self
+                .config
+                .domain
+                .unwrap_or_else(|| QuadTree::bounding_square(&particles));
+            self.list = Some(VerletList::build(
+                &particles,
+                cutoff + self.config.skin,
+                domain,
+                self.config.domain.map(period),
+            ));
+            self.rebuilds += 1;
+        }
+        particles
+    }
+
+    // Neighbour j of particle i, moved to its nearest image
+    fn image(&self, particles: &[Particle], i: usize, j: usize) -> Particle {
+        let (particle, mut other) = (&particles[i], particles[j]);
+        let d = minimum_image(
+            [
+                other.position[0] - particle.position[0],
+                other.position[1] - particle.position[1],
+            ],
+            self.config.domain.map(period),
+        );
+        other.position = [particle.position[0] + d[0], particle.position[1] + d[1]];
+        other
+    }
+
+    fn force_on(&self, particles: &[Particle], i: usize) -> [f64; 2] {
+        let list = self.list.as_ref().unwrap();
+        let mut total_force = [0.0, 0.0];
+        for &j in list.neighbours_of(i) {
+            let f = self
+                .config
+                .force
+                .force(&particles[i], &self.image(particles, i, j));
+            total_force[0] += f[0];
+            total_force[1] += f[1];
+        }
+        total_force
+    }
+}
 
 pub struct Simulation {
     pub particles: Vec<Particle>,
     pub total_forces: Vec<[f64; 2]>,
     pub dt: f64,
-    simulation_type: i32,
-    integrator_type: i32,
-    theta: Option<f64>,
+    pub time: f64,
+    solver: Box<dyn ForceSolver>,
+    integrator: Box<dyn Integrator>,
+    timestep_controller: Option<TimestepController>,
+    forces_up_to_date: bool,
+    total_jerks: Vec<[f64; 2]>,
+    jerks_up_to_date: bool,
+    last_report: StepReport,
+    bonds: Vec<Bond>,
+    overstretched_bonds: usize,
 }
 
-impl Simulation {
-    pub fn new(
-        particles: Vec<Particle>,
-        dt: f64,
-        simulation_type: i32,
-        integrator_type: i32,
-        theta: Option<f64>,
-    ) -> Self {
-        let total_forces = vec![[0.0, 0.0]; particles.len()];
-        Simulation {
-            particles,
-            total_forces,
-            dt,
-            simulation_type,
-            integrator_type,
-            theta,
+impl Simulation {
+    pub fn new(
+        particles: Vec<Particle>,
+        dt: f64,
+        solver: impl ForceSolver + 'static,
+        integrator: impl Integrator + 'static,
+    ) -> Result<Self, ConfigError> {
+        if !dt.is_finite() || dt <= 0.0 {
+            return Err(ConfigError::InvalidTimestep(dt));
+        }
+        if integrator.requires_jerk() && !solver.supports_jerk() {
+            return Err(ConfigError::JerkNotSupported);
+        }
+        if integrator.requires_gravity() && solver.gravitational_constant().is_none() {
+            return Err(ConfigError::GravityRequired);
+        }
+        integrator.validate(particles.len())?;
+
+        let total_forces = vec![[0.0, 0.0]; particles.len()];
+        let total_jerks = if integrator.requires_jerk() {
+            vec![[0.0, 0.0]; particles.len()]
+        } else {
+            Vec::new()
+        };
+        let mut simulation = Simulation {
+            particles,
+            total_forces,
+            dt,
+            time: 0.0,
+            solver: Box::new(solver),
+            integrator: Box::new(integrator),
+            timestep_controller: None,
+            forces_up_to_date: false,
+            total_jerks,
+            jerks_up_to_date: false,
+            last_report: StepReport::default(),
+            bonds: Vec::new(),
+            overstretched_bonds: 0,
+        };
+        simulation.adapt_softening();
+        Ok(simulation)
+    }
+
+    // With a controller, `dt` is chosen anew before each step and holds the time
+    // step actually taken once the step is done
+    pub fn set_timestep_controller(&mut self, controller: Option<TimestepController>) {
+        self.timestep_controller = controller;
+    }
+
+    pub fn take_timestep_controller(&mut self) -> Option<TimestepController> {
+        self.timestep_controller.take()
+    }
+
+    // Bonds between the particles, whose forces are added to those of the
+    // solver at every evaluation. Their indices must stay valid, so particles
+    // should not be added or removed while bonded.
+    pub fn set_bonds(&mut self, bonds: Vec<Bond>) -> Result<(), ConfigError> {
+        for bond in bonds.iter() {
+            bond.validate(self.particles.len())?;
+        }
+        if !bonds.is_empty() && self.integrator.requires_jerk() {
+            return Err(ConfigError::JerkNotSupported);
+        }
+        self.bonds = bonds;
+        self.overstretched_bonds = 0;
+        self.invalidate_forces();
+        Ok(())
+    }
+
+    pub fn bonds(&self) -> &[Bond] {
+        &self.bonds
+    }
+
+    // Number of FENE bonds stretched past `FENE_MAX_STRETCH` at the last force
+    // evaluation, whose energy went on as a parabola rather than diverging. Any
+    // means the time step is too long for the bonds.
+    pub fn overstretched_bonds(&self) -> usize {
+        self.overstretched_bonds
+    }
+
+    // Flips all velocities, after which stepping runs the system backwards
+    pub fn reverse_velocities(&mut self) {
+        for particle in self.particles.iter_mut() {
+            particle.velocity[0] = -particle.velocity[0];
+            particle.velocity[1] = -particle.velocity[1];
+        }
+        // Forces only depend on positions, but jerks depend on velocities too,
+        // as do the forces of damped bonds
+        self.jerks_up_to_date = false;
+        if !self.bonds.is_empty() {
+            self.forces_up_to_date = false;
+        }
+    }
+
+    pub fn simulation_step(&mut self) {
+        // Before the controller evaluates any force, so that it is not redone
+        self.adapt_softening();
+        let Some(controller) = self.timestep_controller.take() else {
+            self.advance(self.dt);
+            self.time += self.dt;
+            return;
+        };
+
+        let mut dt = {
+            let mut forces = ForceEvaluator::new(
+                &mut *self.solver,
+                &mut self.total_forces,
+                &mut self.forces_up_to_date,
+            )
+            .with_bonds(&self.bonds, &mut self.overstretched_bonds);
+            controller.next_dt(&self.particles, &mut forces, self.dt, &self.last_report)
+        };
+
+        if controller.uses_error_estimate() {
+            // Steps whose error exceeds the tolerance are rejected and retried
+            let start = self.particles.clone();
+            loop {
+                self.advance(dt);
+                match self.last_report.error {
+                    Some(err) if err > 1.0 && dt > controller.dt_min() => {
+                        self.particles.copy_from_slice(&start);
+                        self.invalidate_forces();
+                        dt = controller.retry_dt(dt, err);
+                    }
+                    _ => break,
+                }
+            }
+        } else {
+            self.advance(dt);
+        }
+
+        self.dt = dt;
+        self.time += dt;
+        self.timestep_controller = Some(controller);
+    }
+
+    fn advance(&mut self, dt: f64) {
+        let mut forces = ForceEvaluator::new(
+            &mut *self.solver,
+            &mut self.total_forces,
+            &mut self.forces_up_to_date,
+        )
+        .with_bonds(&self.bonds, &mut self.overstretched_bonds);
+        if !self.total_jerks.is_empty() {
+            forces = forces.with_jerks(&mut self.total_jerks, &mut self.jerks_up_to_date);
+        }
+        self.last_report = self
+            .integrator
+            .step_with_report(&mut self.particles, &mut forces, dt);
+
+        // Forces are periodic as well, so wrapping leaves them up to date
+        if let Some(domain) = self.solver.periodic_domain() {
+            for particle in self.particles.iter_mut() {
+                particle.position = wrap_position(particle.position, domain);
+            }
         }
     }
 
-    pub fn simulation_step(&mut self) {
-        if self.simulation_type == DIRECT_SUM {
-            self.direct_sum_step()
-        } else if self.simulation_type == DIRECT_SUM_PARALLEL {
-            self.direct_sum_parallel_step()
-        } else if self.simulation_type == BARNES_HUT {
-            self.barnes_hut_step(self.theta.expect("Barnes-Hut expects a parameter theta!"))
-        } else if self.simulation_type == BARNES_HUT_PARALLEL {
-            self.barnes_hut_parallel_step(
-                self.theta.expect("Barnes-Hut expects a parameter theta!"),
-            )
+    // Sets adaptive softening lengths from the current positions. The forces
+    // depend on the lengths, so those of the last step are stale.
+    fn adapt_softening(&mut self) {
+        if let Softening::Adaptive { neighbours, eta } = self.solver.softening() {
+            let domain = self.solver.periodic_domain();
+            adapt_softening_lengths(&mut self.particles, neighbours, eta, domain);
+            self.invalidate_forces();
         }
     }
 
-    fn direct_sum_step(&mut self) {
-        let particles = &mut self.particles;
-        let total_forces = &mut self.total_forces;
-        let dt = self.dt;
+    // Scaled local error of the last step, for integrators that estimate it
+    pub fn local_error(&self) -> Option<f64> {
+        self.last_report.error
+    }
+
+    pub fn last_report(&self) -> &StepReport {
+        &self.last_report
+    }
+
+    // To be called after editing `particles` by hand between two steps
+    pub fn invalidate_forces(&mut self) {
+        self.forces_up_to_date = false;
+        self.jerks_up_to_date = false;
+    }
+
+    pub fn solver(&self) -> &dyn ForceSolver {
+        &*self.solver
+    }
+
+    // Diagnostics with the potential energy of the solver's forces and of the
+    // bonds, which the integrators conserve
+    pub fn diagnostics(&self) -> Diagnostics {
+        let period = self.solver.periodic_domain().map(period);
+        let potential_energy = self.solver.potential_energy(&self.particles)
+            + bond_energy(&self.bonds, &self.particles, period);
+        Diagnostics::with_potential_energy(&self.particles, potential_energy)
+    }
+
+    pub fn get_particle_positions(&self) -> Vec<[f32; 2]> {
+        self.particles
+            .iter()
+            .map(|p| [p.position[0] as f32, p.position[1] as f32])
+            .collect()
+    }
+}
+
+impl<F: PairForce> ForceSolver for DirectSum<F> {
+    fn compute_forces(&mut self, particles: &[Particle], total_forces: &mut [[f64; 2]]) {
+        total_forces.fill([0.0, 0.0]);
 
         for i in 0..particles.len() {
             for j in i + 1..particles.len() {
-                let force = compute_gravity(&particles[i], &particles[j]);
+                let force = self.force.force(&particles[i], &particles[j]);
                 total_forces[i][0] += force[0];
                 total_forces[i][1] += force[1];
                 total_forces[j][0] -= force[0];
                 total_forces[j][1] -= force[1];
             }
         }
+    }
+
+    fn compute_active_forces(
+        &mut self,
+        particles: &[Particle],
+        active: &[usize],
+        total_forces: &mut [[f64; 2]],
+    ) {
+        for &i in active {
+            total_forces[i] = direct_force_on(particles, i, &self.force);
+        }
+    }
+
+    fn supports_jerk(&self) -> bool {
+        true
+    }
 
-        for (force, particle) in total_forces.iter_mut().zip(particles.iter_mut()) {
-            time_integration(particle, force, dt, self.integrator_type);
-            *force = [0.0, 0.0];
+    fn compute_forces_and_jerks(
+        &mut self,
+        particles: &[Particle],
+        total_forces: &mut [[f64; 2]],
+        total_jerks: &mut [[f64; 2]],
+    ) {
+        total_forces.fill([0.0, 0.0]);
+        total_jerks.fill([0.0, 0.0]);
+
+        for i in 0..particles.len() {
+            for j in i + 1..particles.len() {
+                let (force, jerk) = self.force.force_and_jerk(&particles[i], &particles[j]);
+                for d in 0..2 {
+                    total_forces[i][d] += force[d];
+                    total_jerks[i][d] += jerk[d];
+                    total_forces[j][d] -= force[d];
+                    total_jerks[j][d] -= jerk[d];
+                }
+            }
         }
     }
 
-    fn direct_sum_parallel_step(&mut self) {
-        let particles = &mut self.particles;
-        let total_forces = &mut self.total_forces;
-        let dt = self.dt;
+    fn softening(&self) -> Softening {
+        self.force.softening()
+    }
+
+    fn gravitational_constant(&self) -> Option<f64> {
+        self.force.gravitational_constant()
+    }
+
+    fn potential_energy(&self, particles: &[Particle]) -> f64 {
+        pair_potential_energy(particles, &self.force, None)
+    }
+}
+
+// Force exerted on particle i by all the others
+fn direct_force_on(particles: &[Particle], i: usize, force: &impl PairForce) -> [f64; 2] {
+    let mut total_force = [0.0, 0.0];
+    for (j, other) in particles.iter().enumerate() {
+        if j != i {
+            let f = force.force(&particles[i], other);
+            total_force[0] += f[0];
+            total_force[1] += f[1];
+        }
+    }
+    total_force
+}
 
-        // Create thread-local buffers for forces
-        let mut thread_local_forces: Vec<Vec<[f64; 2]>> =
-            vec![vec![[0.0, 0.0]; particles.len()]; rayon::current_num_threads()];
+// Force and jerk exerted on particle i by all the others
+fn direct_force_and_jerk_on(
+    particles: &[Particle],
+    i: usize,
+    force: &impl PairForce,
+) -> ([f64; 2], [f64; 2]) {
+    let mut total_force = [0.0, 0.0];
+    let mut total_jerk = [0.0, 0.0];
+    for (j, other) in particles.iter().enumerate() {
+        if j != i {
+            let (f, jerk) = force.force_and_jerk(&particles[i], other);
+            for d in 0..2 {
+                total_force[d] += f[d];
+                total_jerk[d] += jerk[d];
+            }
+        }
+    }
+    (total_force, total_jerk)
+}
 
-        // Compute gravitational forces in parallel
-        thread_local_forces
+// Each particle gathers the forces acting on it in index order, so the results
+// do not depend on the number of threads or on how rayon schedules the work.
+// This evaluates every pair twice, unlike the serial direct sum.
+impl<F: PairForce> ForceSolver for DirectSumParallel<F> {
+    fn compute_forces(&mut self, particles: &[Particle], total_forces: &mut [[f64; 2]]) {
+        let pair_force = &self.force;
+        total_forces
             .par_iter_mut()
             .enumerate()
-            .for_each(|(thread_id, local_forces)| {
-                for i in (thread_id..particles.len()).step_by(rayon::current_num_threads()) {
-                    for j in i + 1..particles.len() {
-                        let force = compute_gravity(&particles[i], &particles[j]);
-                        local_forces[i][0] += force[0];
-                        local_forces[i][1] += force[1];
-                        local_forces[j][0] -= force[0];
-                        local_forces[j][1] -= force[1];
-                    }
-                }
+            .for_each(|(i, force)| {
+                *force = direct_force_on(particles, i, pair_force);
             });
+    }
 
-        // Aggregate forces from thread-local buffers
-        for local_forces in thread_local_forces {
-            for (global_force, local_force) in total_forces.iter_mut().zip(local_forces) {
-                global_force[0] += local_force[0];
-                global_force[1] += local_force[1];
-            }
+    fn compute_active_forces(
+        &mut self,
+        particles: &[Particle],
+        active: &[usize],
+        total_forces: &mut [[f64; 2]],
+    ) {
+        let pair_force = &self.force;
+        let active_forces: Vec<[f64; 2]> = active
+            .par_iter()
+            .map(|&i| direct_force_on(particles, i, pair_force))
+            .collect();
+        for (&i, force) in active.iter().zip(active_forces) {
+            total_forces[i] = force;
         }
+    }
+
+    fn supports_jerk(&self) -> bool {
+        true
+    }
 
-        // Integrate the motion of particles
+    fn compute_forces_and_jerks(
+        &mut self,
+        particles: &[Particle],
+        total_forces: &mut [[f64; 2]],
+        total_jerks: &mut [[f64; 2]],
+    ) {
+        let pair_force = &self.force;
         total_forces
             .par_iter_mut()
-            .zip(particles.par_iter_mut())
-            .for_each(|(force, particle)| {
-                time_integration(particle, force, dt, self.integrator_type);
-                *force = [0.0, 0.0]; // Reset the force
+            .zip(total_jerks.par_iter_mut())
+            .enumerate()
+            .for_each(|(i, (force, jerk))| {
+                (*force, *jerk) = direct_force_and_jerk_on(particles, i, pair_force);
+            });
+    }
+
+    fn softening(&self) -> Softening {
+        self.force.softening()
+    }
+
+    fn gravitational_constant(&self) -> Option<f64> {
+        self.force.gravitational_constant()
+    }
+
+    fn potential_energy(&self, particles: &[Particle]) -> f64 {
+        pair_potential_energy(particles, &self.force, None)
+    }
+}
+
+impl ForceSolver for PeriodicDirectSum {
+    fn compute_forces(&mut self, particles: &[Particle], total_forces: &mut [[f64; 2]]) {
+        let (ewald, softening) = (&self.ewald, &self.softening);
+        total_forces
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, force)| {
+                *force = [0.0, 0.0];
+                for (j, other) in particles.iter().enumerate() {
+                    if j != i {
+                        let f = ewald.gravity(&particles[i], other, softening);
+                        force[0] += f[0];
+                        force[1] += f[1];
+                    }
+                }
             });
     }
 
-    fn barnes_hut_step(&mut self, theta: f64) {
-        let mut root = QuadTree::new([0.0, 0.0, 1500.0, 900.0]);
-        for particle in self.particles.iter() {
-            root.insert(*particle);
+    fn periodic_domain(&self) -> Option<[f64; 4]> {
+        Some(self.domain)
+    }
+
+    fn softening(&self) -> Softening {
+        self.softening
+    }
+}
+
+impl<F: PairForce> BarnesHut<F> {
+    fn build_tree(&mut self, particles: &[Particle]) -> QuadTree {
+        let mut root = QuadTree::with_coupling(
+            self.config.root_boundary(particles),
+            self.config.limits,
+            self.config.force.coupling(),
+        );
+        self.dropped = 0;
+        for particle in particles.iter() {
+            if !root.insert(*particle) {
+                self.dropped += 1;
+            }
         }
 
         root.finalize();
+        root
+    }
+}
 
-        for particle in self.particles.iter_mut() {
-            let force = root.compute_force(particle, theta);
-            time_integration(particle, &force, self.dt, self.integrator_type);
+impl<F: PairForce> ForceSolver for BarnesHut<F> {
+    fn compute_forces(&mut self, particles: &[Particle], total_forces: &mut [[f64; 2]]) {
+        let particles = &*self.config.wrapped(particles);
+        let root = self.build_tree(particles);
+
+        let walk = self.config.walk();
+        let ewald = self.ewald.as_ref();
+        for (i, (force, particle)) in total_forces.iter_mut().zip(particles.iter()).enumerate() {
+            let previous_acc = previous_acceleration(&self.previous_acc, particles.len(), i);
+            *force = root.compute_force(particle, &walk, previous_acc, ewald);
         }
+        record_accelerations(
+            &mut self.previous_acc,
+            &self.config,
+            particles,
+            total_forces,
+            None,
+        );
     }
 
-    fn barnes_hut_parallel_step(&mut self, theta: f64) {
-        let mut root = QuadTree::new([0.0, 0.0, 1500.0, 900.0]);
-        let mut thread_trees: Vec<QuadTree> = self
-            .particles
+    fn compute_active_forces(
+        &mut self,
+        particles: &[Particle],
+        active: &[usize],
+        total_forces: &mut [[f64; 2]],
+    ) {
+        let particles = &*self.config.wrapped(particles);
+        let root = self.build_tree(particles);
+
+        let walk = self.config.walk();
+        let ewald = self.ewald.as_ref();
+        for &i in active {
+            let previous_acc = previous_acceleration(&self.previous_acc, particles.len(), i);
+            total_forces[i] = root.compute_force(&particles[i], &walk, previous_acc, ewald);
+        }
+        record_accelerations(
+            &mut self.previous_acc,
+            &self.config,
+            particles,
+            total_forces,
+            Some(active),
+        );
+    }
+
+    fn dropped_particles(&self) -> usize {
+        self.dropped
+    }
+
+    fn periodic_domain(&self) -> Option<[f64; 4]> {
+        self.config.periodic_domain()
+    }
+
+    fn softening(&self) -> Softening {
+        self.config.force.softening()
+    }
+
+    fn gravitational_constant(&self) -> Option<f64> {
+        let constant = self.config.force.gravitational_constant();
+        constant.filter(|_| self.periodic_domain().is_none())
+    }
+
+    fn potential_energy(&self, particles: &[Particle]) -> f64 {
+        pair_potential_energy(
+            particles,
+            &self.config.force,
+            self.periodic_domain().map(period),
+        )
+    }
+}
+
+impl<F: PairForce> BarnesHutParallel<F> {
+    fn build_tree(&mut self, particles: &[Particle]) -> QuadTree {
+        let boundary = self.config.root_boundary(particles);
+        let (limits, coupling) = (self.config.limits, self.config.force.coupling());
+        let mut root = QuadTree::with_coupling(boundary, limits, coupling);
+        let mut thread_trees: Vec<(QuadTree, usize)> = particles
             .par_chunks(100) // Each thread processes a chunk of 100 particles
             .map(|chunk| {
-                let mut local_tree = QuadTree::new([0.0, 0.0, 1500.0, 900.0]);
+                let mut local_tree = QuadTree::with_coupling(boundary, limits, coupling);
+                let mut dropped = 0;
                 for particle in chunk {
-                    local_tree.insert(*particle);
+                    if !local_tree.insert(*particle) {
+                        dropped += 1;
+                    }
                 }
-                local_tree
+                (local_tree, dropped)
             })
             .collect();
 
-        for tree in thread_trees.iter_mut() {
+        self.dropped = 0;
+        for (tree, dropped) in thread_trees.iter_mut() {
             root.merge(tree);
+            self.dropped += *dropped;
         }
 
         root.finalize();
+        root
+    }
+}
+
+impl<F: PairForce> ForceSolver for BarnesHutParallel<F> {
+    fn compute_forces(&mut self, particles: &[Particle], total_forces: &mut [[f64; 2]]) {
+        let particles = &*self.config.wrapped(particles);
+        let root = self.build_tree(particles);
+
+        let walk = self.config.walk();
+        let ewald = self.ewald.as_ref();
+        let previous = &self.previous_acc;
+        total_forces
+            .par_iter_mut()
+            .zip(particles.par_iter())
+            .enumerate()
+            .for_each(|(i, (force, particle))| {
+                let previous_acc = previous_acceleration(previous, particles.len(), i);
+                *force = root.compute_force(particle, &walk, previous_acc, ewald);
+            });
+        record_accelerations(
+            &mut self.previous_acc,
+            &self.config,
+            particles,
+            total_forces,
+            None,
+        );
+    }
+
+    fn compute_active_forces(
+        &mut self,
+        particles: &[Particle],
+        active: &[usize],
+        total_forces: &mut [[f64; 2]],
+    ) {
+        let particles = &*self.config.wrapped(particles);
+        let root = self.build_tree(particles);
+
+        let walk = self.config.walk();
+        let ewald = self.ewald.as_ref();
+        let previous = &self.previous_acc;
+        let active_forces: Vec<[f64; 2]> = active
+            .par_iter()
+            .map(|&i| {
+                let previous_acc = previous_acceleration(previous, particles.len(), i);
+                root.compute_force(&particles[i], &walk, previous_acc, ewald)
+            })
+            .collect();
+        for (&i, force) in active.iter().zip(active_forces) {
+            total_forces[i] = force;
+        }
+        record_accelerations(
+            &mut self.previous_acc,
+            &self.config,
+            particles,
+            total_forces,
+            Some(active),
+        );
+    }
+
+    fn dropped_particles(&self) -> usize {
+        self.dropped
+    }
+
+    fn periodic_domain(&self) -> Option<[f64; 4]> {
+        self.config.periodic_domain()
+    }
+
+    fn softening(&self) -> Softening {
+        self.config.force.softening()
+    }
+
+    fn gravitational_constant(&self) -> Option<f64> {
+        let constant = self.config.force.gravitational_constant();
+        constant.filter(|_| self.periodic_domain().is_none())
+    }
+
+    fn potential_energy(&self, particles: &[Particle]) -> f64 {
+        pair_potential_energy(
+            particles,
+            &self.config.force,
+            self.periodic_domain().map(period),
+        )
+    }
+}
+
+impl<F: PairForce> LinearBarnesHut<F> {
+    fn build_tree(&mut self, particles: &[Particle]) -> LinearQuadTree {
+        let tree = LinearQuadTree::build_with_coupling(
+            particles,
+            self.config.root_boundary(particles),
+            self.config.limits,
+            self.config.force.coupling(),
+        );
+        self.dropped = tree.dropped;
+        tree
+    }
+}
+
+impl<F: PairForce> ForceSolver for LinearBarnesHut<F> {
+    fn compute_forces(&mut self, particles: &[Particle], total_forces: &mut [[f64; 2]]) {
+        let particles = &*self.config.wrapped(particles);
+        let tree = self.build_tree(particles);
+
+        // Walk the tree in Morton order, so that consecutive walks visit
+        // mostly the same nodes
+        let walk = self.config.walk();
+        let ewald = self.ewald.as_ref();
+        let previous = &self.previous_acc;
+        let sorted_forces: Vec<[f64; 2]> = tree
+            .particles
+            .par_iter()
+            .zip(tree.order.par_iter())
+            .map(|(particle, &i)| {
+                let previous_acc = previous_acceleration(previous, particles.len(), i);
+                tree.compute_force(particle, &walk, previous_acc, ewald)
+            })
+            .collect();
+        // Dropped particles feel no force
+        total_forces.fill([0.0, 0.0]);
+        for (&i, force) in tree.order.iter().zip(sorted_forces) {
+            total_forces[i] = force;
+        }
+        record_accelerations(
+            &mut self.previous_acc,
+            &self.config,
+            particles,
+            total_forces,
+            None,
+        );
+    }
+
+    fn compute_active_forces(
+        &mut self,
+        particles: &[Particle],
+        active: &[usize],
+        total_forces: &mut [[f64; 2]],
+    ) {
+        let particles = &*self.config.wrapped(particles);
+        let tree = self.build_tree(particles);
+
+        let walk = self.config.walk();
+        let ewald = self.ewald.as_ref();
+        let previous = &self.previous_acc;
+        let active_forces: Vec<[f64; 2]> = active
+            .par_iter()
+            .map(|&i| {
+                let previous_acc = previous_acceleration(previous, particles.len(), i);
+                tree.compute_force(&particles[i], &walk, previous_acc, ewald)
+            })
+            .collect();
+        for (&i, force) in active.iter().zip(active_forces) {
+            total_forces[i] = force;
+        }
+        record_accelerations(
+            &mut self.previous_acc,
+            &self.config,
+            particles,
+            total_forces,
+            Some(active),
+        );
+    }
+
+    fn dropped_particles(&self) -> usize {
+        self.dropped
+    }
+
+    fn periodic_domain(&self) -> Option<[f64; 4]> {
+        self.config.periodic_domain()
+    }
+
+    fn softening(&self) -> Softening {
+        self.config.force.softening()
+    }
+
+    fn gravitational_constant(&self) -> Option<f64> {
+        let constant = self.config.force.gravitational_constant();
+        constant.filter(|_| self.periodic_domain().is_none())
+    }
+
+    fn potential_energy(&self, particles: &[Particle]) -> f64 {
+        pair_potential_energy(
+            particles,
+            &self.config.force,
+            self.periodic_domain().map(period),
+        )
+    }
+}
 
-        self.particles.par_iter_mut().for_each(|particle| {
-            let force = root.compute_force(particle, theta);
-            time_integration(particle, &force, self.dt, self.integrator_type);
+impl ForceSolver for FastMultipole {
+    fn compute_forces(&mut self, particles: &[Particle], total_forces: &mut [[f64; 2]]) {
+        let boundary = self
+            .config
+            .domain
+            .unwrap_or_else(|| QuadTree::bounding_square(particles));
+        let tree = FmmTree::build(
+            particles,
+            boundary,
+            self.config.limits,
+            self.config.order,
+            self.config.theta,
+            self.config.softening,
+        );
+        self.dropped = tree.tree.dropped;
+        tree.compute_forces(total_forces);
+    }
+
+    fn dropped_particles(&self) -> usize {
+        self.dropped
+    }
+
+    fn softening(&self) -> Softening {
+        self.config.softening
+    }
+
+    fn gravitational_constant(&self) -> Option<f64> {
+        Some(GRAVIT_CONST)
+    }
+}
+
+impl ForceSolver for ParticleMesh {
+    fn compute_forces(&mut self, particles: &[Particle], total_forces: &mut [[f64; 2]]) {
+        let domain = self.config.domain.unwrap_or_else(|| {
+            Mesh::isolated_domain(self.config.grid_size, QuadTree::bounding_square(particles))
         });
+        let accelerations = self.mesh.accelerations(particles, domain);
+
+        self.dropped = 0;
+        for ((force, acc), p) in total_forces.iter_mut().zip(accelerations).zip(particles) {
+            *force = [p.mass * acc[0], p.mass * acc[1]];
+            let [x_min, y_min, x_max, y_max] = domain;
+            let inside = p.position[0] >= x_min
+                && p.position[0] <= x_max
+                && p.position[1] >= y_min
+                && p.position[1] <= y_max;
+            if self.config.boundary == MeshBoundary::Isolated && !inside {
+                self.dropped += 1;
+            }
+        }
     }
 
-    pub fn get_particle_positions(&self) -> Vec<[f32; 2]> {
-        self.particles
-            .iter()
-            .map(|p| [p.position[0] as f32, p.position[1] as f32])
-            .collect()
+    fn dropped_particles(&self) -> usize {
+        self.dropped
+    }
+
+    fn periodic_domain(&self) -> Option<[f64; 4]> {
+        self.config.periodic_domain()
+    }
+
+    fn softening(&self) -> Softening {
+        self.config.softening
+    }
+
+    fn gravitational_constant(&self) -> Option<f64> {
+        Some(GRAVIT_CONST).filter(|_| self.periodic_domain().is_none())
+    }
+}
+
+impl ForceSolver for TreePm {
+    fn compute_forces(&mut self, particles: &[Particle], total_forces: &mut [[f64; 2]]) {
+        let pm = self.config.mesh;
+        let domain = pm.domain.unwrap_or_else(|| {
+            Mesh::isolated_domain(pm.grid_size, QuadTree::bounding_square(particles))
+        });
+        let accelerations = self.mesh.accelerations(particles, domain);
+
+        let scale = self.config.split * self.mesh.cell_length(domain);
+        let split = ForceSplit {
+            scale,
+            cutoff: self.config.cutoff * scale,
+            softening: pm.softening,
+        };
+        let (tree, period) = match pm.boundary {
+            MeshBoundary::Periodic => {
+                let wrapped = wrap_particles(particles, domain);
+                (
+                    LinearQuadTree::build(&wrapped, domain, self.config.limits),
+                    Some(period(domain)),
+                )
+            }
+            MeshBoundary::Isolated => (
+                LinearQuadTree::build(particles, domain, self.config.limits),
+                None,
+            ),
+        };
+        self.dropped = tree.dropped;
+
+        let walk = TreeWalk {
+            theta: self.config.theta,
+            criterion: OpeningCriterion::Geometric,
+            open_containing: true,
+            multipole_order: MultipoleOrder::Monopole,
+            force: Gravity::with_softening(pm.softening),
+        };
+        let short_range: Vec<[f64; 2]> = tree
+            .particles
+            .par_iter()
+            .map(|particle| tree.compute_short_range_force(particle, &walk, &split, period))
+            .collect();
+        // Dropped particles feel no force
+        total_forces.fill([0.0, 0.0]);
+        for (&i, force) in tree.order.iter().zip(short_range) {
+            let (p, acc) = (&particles[i], accelerations[i]);
+            total_forces[i] = [force[0] + p.mass * acc[0], force[1] + p.mass * acc[1]];
+        }
+    }
+
+    fn dropped_particles(&self) -> usize {
+        self.dropped
+    }
+
+    fn periodic_domain(&self) -> Option<[f64; 4]> {
+        self.config.periodic_domain()
+    }
+
+    fn softening(&self) -> Softening {
+        self.config.mesh.softening
+    }
+
+    fn gravitational_constant(&self) -> Option<f64> {
+        Some(GRAVIT_CONST).filter(|_| self.periodic_domain().is_none())
+    }
+}
+
+impl<F: PairForce> ForceSolver for NeighbourList<F> {
+    fn compute_forces(&mut self, particles: &[Particle], total_forces: &mut [[f64; 2]]) {
+        let particles = &*self.prepare(particles);
+        let solver = &*self;
+        total_forces
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, force)| *force = solver.force_on(particles, i));
+    }
+
+    fn compute_active_forces(
+        &mut self,
+        particles: &[Particle],
+        active: &[usize],
+        total_forces: &mut [[f64; 2]],
+    ) {
+        let particles = &*self.prepare(particles);
+        let solver = &*self;
+        let active_forces: Vec<[f64; 2]> = active
+            .par_iter()
+            .map(|&i| solver.force_on(particles, i))
+            .collect();
+        for (&i, force) in active.iter().zip(active_forces) {
+            total_forces[i] = force;
+        }
+    }
+
+    fn supports_jerk(&self) -> bool {
+        true
+    }
+
+    fn compute_forces_and_jerks(
+        &mut self,
+        particles: &[Particle],
+        total_forces: &mut [[f64; 2]],
+        total_jerks: &mut [[f64; 2]],
+    ) {
+        let particles = &*self.prepare(particles);
+        let solver = &*self;
+        let list = solver.list.as_ref().unwrap();
+        total_forces
+            .par_iter_mut()
+            .zip(total_jerks.par_iter_mut())
+            .enumerate()
+            .for_each(|(i, (force, jerk))| {
+                *force = [0.0, 0.0];
+                *jerk = [0.0, 0.0];
+                for &j in list.neighbours_of(i) {
+                    let other = solver.image(particles, i, j);
+                    let (f, dj) = solver.config.force.force_and_jerk(&particles[i], &other);
+                    force[0] += f[0];
+                    force[1] += f[1];
+                    jerk[0] += dj[0];
+                    jerk[1] += dj[1];
+                }
+            });
+    }
+
+    fn periodic_domain(&self) -> Option<[f64; 4]> {
+        self.config.domain
+    }
+
+    fn softening(&self) -> Softening {
+        self.config.force.softening()
+    }
+
+    fn potential_energy(&self, particles: &[Particle]) -> f64 {
+        pair_potential_energy(
+            particles,
+            &self.config.force,
+            self.periodic_domain().map(period),
+        )
+    }
+}
+
+fn wrap_particles(particles: &[Particle], domain: [f64; 4]) -> Vec<Particle> {
+    particles
+        .iter()
+        .map(|p| {
+            let mut p = *p;
+            p.position = wrap_position(p.position, domain);
+            p
+        })
+        .collect()
+}
+
+fn previous_acceleration(previous_acc: &[f64], n: usize, i: usize) -> Option<f64> {
+    if previous_acc.len() == n {
+        Some(previous_acc[i])
+    } else {
+        None
+    }
+}
+
+// Keeps the accelerations of the particles whose force was just computed (all of
+// them if `active` is None), if the opening criterion needs them
+fn record_accelerations<F>(
+    previous_acc: &mut Vec<f64>,
+    config: &BarnesHutConfig<F>,
+    particles: &[Particle],
+    forces: &[[f64; 2]],
+    active: Option<&[usize]>,
+) {
+    if !matches!(config.criterion, OpeningCriterion::Relative { .. }) {
+        previous_acc.clear();
+        return;
+    }
+
+    let acceleration = |i: usize| {
+        let f = forces[i];
+        (f[0] * f[0] + f[1] * f[1]).sqrt() / particles[i].mass
+    };
+    match active {
+        Some(active) if previous_acc.len() == particles.len() => {
+            for &i in active {
+                previous_acc[i] = acceleration(i);
+            }
+        }
+        // Forces of the inactive particles may not be known yet
+        Some(_) => previous_acc.clear(),
+        None => *previous_acc = (0..particles.len()).map(acceleration).collect(),
     }
 }
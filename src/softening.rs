@@ -0,0 +1,182 @@
+use crate::error::ConfigError;
+use crate::ewald::period;
+use crate::linear_tree::LinearQuadTree;
+use crate::particle::Particle;
+use crate::quadtree::{QuadTree, TreeLimits};
+use rayon::prelude::*;
+
+// Support of the spline kernel in units of its Plummer-equivalent length
+const SPLINE_SUPPORT: f64 = 2.8;
+// Plummer forces are within 1.5e-4 of Newtonian ones beyond this many lengths
+const PLUMMER_REACH: f64 = 100.0;
+
+// Softening of the gravitational interaction at short distances. Kernels are
+// given through the force F = G m1 m2 f(r) d, with d = x2 - x1, and the
+// potential -G m1 m2 phi(r).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Softening {
+    // Newtonian forces down to zero distance
+    None,
+    // phi = 1 / sqrt(r^2 + epsilon^2)
+    Plummer { epsilon: f64 },
+    // Cubic spline kernel of GADGET (Monaghan & Lattanzio 1985), Newtonian
+    // beyond h = 2.8 epsilon, with phi(0) = 1 / epsilon as for Plummer
+    Spline { epsilon: f64 },
+    // Spline kernel with the length of each particle, `Particle::softening`,
+    // pairs taking the larger of the two. Simulation sets the lengths before
+    // each step to eta times the distance to the `neighbours`-th nearest
+    // neighbour.
+    Adaptive { neighbours: usize, eta: f64 },
+}
+
+impl Default for Softening {
+    fn default() -> Self {
+        Softening::Spline { epsilon: 1.0 }
+    }
+}
+
+impl Softening {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let valid = match *self {
+            Softening::None => true,
+            Softening::Plummer { epsilon } | Softening::Spline { epsilon } => {
+                epsilon.is_finite() && epsilon > 0.0
+            }
+            Softening::Adaptive { neighbours, eta } => {
+                neighbours > 0 && eta.is_finite() && eta > 0.0
+            }
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(ConfigError::InvalidSoftening(*self))
+        }
+    }
+
+    // Softening length of a pair, from the lengths of the particles
+    pub fn pair_length(&self, length_1: f64, length_2: f64) -> f64 {
+        match *self {
+            Softening::None => 0.0,
+            Softening::Plummer { epsilon } | Softening::Spline { epsilon } => epsilon,
+            Softening::Adaptive { .. } => length_1.max(length_2),
+        }
+    }
+
+    // Largest softening length of any pair
+    pub fn max_length(&self, particles: &[Particle]) -> f64 {
+        match self {
+            Softening::Adaptive { .. } => particles.iter().map(|p| p.softening).fold(0.0, f64::max),
+            _ => self.pair_length(0.0, 0.0),
+        }
+    }
+
+    // Distance beyond which forces with the given length are Newtonian, or as
+    // good as for Plummer softening
+    pub fn reach(&self, length: f64) -> f64 {
+        match self {
+            Softening::None => 0.0,
+            Softening::Plummer { .. } => PLUMMER_REACH * length,
+            Softening::Spline { .. } | Softening::Adaptive { .. } => SPLINE_SUPPORT * length,
+        }
+    }
+
+    // f(r), zero at r = 0 where the force vanishes by symmetry
+    pub fn force_factor(&self, dist_sq: f64, length: f64) -> f64 {
+        self.force_factor_and_derivative(dist_sq, length).0
+    }
+
+    // f(r) and f'(r) / r, the time derivative of the force being
+    // G m1 m2 (f dv + f'/r (d.dv) d)
+    pub fn force_factor_and_derivative(&self, dist_sq: f64, length: f64) -> (f64, f64) {
+        if dist_sq == 0.0 {
+            return (0.0, 0.0);
+        }
+        let newtonian = |dist_sq: f64| {
+            let inv_dist_sq = 1.0 / dist_sq;
+            let f = inv_dist_sq * inv_dist_sq.sqrt();
+            (f, -3.0 * f * inv_dist_sq)
+        };
+        match self {
+            Softening::None => newtonian(dist_sq),
+            Softening::Plummer { .. } => newtonian(dist_sq + length * length),
+            Softening::Spline { .. } | Softening::Adaptive { .. } => {
+                let h = SPLINE_SUPPORT * length;
+                if dist_sq >= h * h {
+                    return newtonian(dist_sq);
+                }
+                let u = dist_sq.sqrt() / h;
+                let h3 = h * h * h;
+                let h5 = h3 * h * h;
+                if u < 0.5 {
+                    (
+                        (32.0 / 3.0 + u * u * (32.0 * u - 38.4)) / h3,
+                        (96.0 * u - 76.8) / h5,
+                    )
+                } else {
+                    let u3 = u * u * u;
+                    (
+                        (64.0 / 3.0 - 48.0 * u + 38.4 * u * u
+                            - 32.0 / 3.0 * u3
+                            - 1.0 / (15.0 * u3))
+                            / h3,
+                        (-48.0 / u + 76.8 - 32.0 * u + 0.2 / (u3 * u * u)) / h5,
+                    )
+                }
+            }
+        }
+    }
+
+    // phi(r)
+    pub fn potential(&self, dist: f64, length: f64) -> f64 {
+        match self {
+            Softening::None => 1.0 / dist,
+            Softening::Plummer { .. } => 1.0 / (dist * dist + length * length).sqrt(),
+            Softening::Spline { .. } | Softening::Adaptive { .. } => {
+                let h = SPLINE_SUPPORT * length;
+                if dist >= h {
+                    return 1.0 / dist;
+                }
+                let u = dist / h;
+                let u2 = u * u;
+                if u < 0.5 {
+                    (2.8 - u2 * (16.0 / 3.0 + u2 * (6.4 * u - 9.6))) / h
+                } else {
+                    (3.2 - 1.0 / (15.0 * u)
+                        - u2 * (32.0 / 3.0 + u * (-16.0 + u * (9.6 - 32.0 / 15.0 * u))))
+                        / h
+                }
+            }
+        }
+    }
+}
+
+// Sets the softening length of each particle to eta times the distance to its
+// `neighbours`-th nearest neighbour, counting the periodic images of the others
+// in a periodic domain
+pub fn adapt_softening_lengths(
+    particles: &mut [Particle],
+    neighbours: usize,
+    eta: f64,
+    periodic_domain: Option<[f64; 4]>,
+) {
+    if particles.len() < 2 {
+        return;
+    }
+    let limits = TreeLimits {
+        leaf_capacity: 8,
+        max_depth: TreeLimits::MAX_DEPTH,
+    };
+    let boundary = periodic_domain.unwrap_or_else(|| QuadTree::bounding_square(particles));
+    let tree = LinearQuadTree::build(particles, boundary, limits);
+    let period = periodic_domain.map(period);
+    // Each particle is its own nearest neighbour in the tree
+    let k = neighbours.min(particles.len() - 1) + 1;
+    let lengths: Vec<f64> = tree
+        .particles
+        .par_iter()
+        .map(|p| eta * tree.nearest_distance(p.position, k, period))
+        .collect();
+    for (&i, length) in tree.order.iter().zip(lengths) {
+        particles[i].softening = length;
+    }
+}
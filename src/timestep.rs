@@ -0,0 +1,146 @@
+use crate::error::ConfigError;
+use crate::integrator::{ForceEvaluator, StepReport};
+use crate::particle::Particle;
+use rayon::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimestepCriterion {
+    // dt = eta * min over particles of sqrt(softening / |a|)
+    Acceleration { eta: f64, softening: f64 },
+    // dt = courant * min over particles of length / |v|
+    Velocity { courant: f64, length: f64 },
+    // Standard step size control on the integrator's scaled local error, where
+    // `order` is the order of the embedded error estimate (4 for Dormand-Prince)
+    LocalError { safety: f64, order: u32 },
+    // Time step suggested by the integrator itself, such as the Aarseth
+    // criterion of the Hermite scheme
+    IntegratorSuggestion,
+}
+
+#[derive(Debug, Clone)]
+pub struct TimestepController {
+    criteria: Vec<TimestepCriterion>,
+    dt_min: f64,
+    dt_max: f64,
+}
+
+impl TimestepCriterion {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let valid = match *self {
+            TimestepCriterion::Acceleration { eta, softening } => eta > 0.0 && softening > 0.0,
+            TimestepCriterion::Velocity { courant, length } => courant > 0.0 && length > 0.0,
+            TimestepCriterion::LocalError { safety, order } => safety > 0.0 && order > 0,
+            TimestepCriterion::IntegratorSuggestion => true,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(ConfigError::InvalidTimestepCriterion(*self))
+        }
+    }
+}
+
+impl TimestepController {
+    pub fn new(
+        criteria: Vec<TimestepCriterion>,
+        dt_min: f64,
+        dt_max: f64,
+    ) -> Result<Self, ConfigError> {
+        if !(dt_min > 0.0 && dt_min <= dt_max && dt_max.is_finite()) {
+            return Err(ConfigError::InvalidTimestepBounds(dt_min, dt_max));
+        }
+        for criterion in criteria.iter() {
+            criterion.validate()?;
+        }
+        Ok(TimestepController {
+            criteria,
+            dt_min,
+            dt_max,
+        })
+    }
+
+    pub fn criteria(&self) -> &[TimestepCriterion] {
+        &self.criteria
+    }
+
+    pub fn dt_min(&self) -> f64 {
+        self.dt_min
+    }
+
+    pub fn dt_max(&self) -> f64 {
+        self.dt_max
+    }
+
+    pub fn uses_error_estimate(&self) -> bool {
+        self.criteria
+            .iter()
+            .any(|c| matches!(c, TimestepCriterion::LocalError { .. }))
+    }
+
+    // Picks the time step for the next step from the current state, the previous
+    // time step and what the integrator reported for it
+    pub fn next_dt(
+        &self,
+        particles: &[Particle],
+        forces: &mut ForceEvaluator,
+        previous_dt: f64,
+        previous_report: &StepReport,
+    ) -> f64 {
+        let mut dt = self.dt_max;
+        for criterion in self.criteria.iter() {
+            let candidate = match *criterion {
+                TimestepCriterion::Acceleration { eta, softening } => {
+                    let f = forces.forces(particles);
+                    let max_acc = particles
+                        .par_iter()
+                        .zip(f.par_iter())
+                        .map(|(p, force)| {
+                            (force[0] * force[0] + force[1] * force[1]).sqrt() / p.mass
+                        })
+                        .reduce(|| 0.0, f64::max);
+                    eta * (softening / max_acc).sqrt()
+                }
+                TimestepCriterion::Velocity { courant, length } => {
+                    let max_vel = particles
+                        .par_iter()
+                        .map(|p| {
+                            (p.velocity[0] * p.velocity[0] + p.velocity[1] * p.velocity[1]).sqrt()
+                        })
+                        .reduce(|| 0.0, f64::max);
+                    courant * length / max_vel
+                }
+                TimestepCriterion::LocalError { safety, order } => match previous_report.error {
+                    Some(err) => previous_dt * error_factor(err, safety, order),
+                    None => previous_dt,
+                },
+                TimestepCriterion::IntegratorSuggestion => {
+                    previous_report.suggested_dt.unwrap_or(previous_dt)
+                }
+            };
+            dt = dt.min(candidate);
+        }
+        dt.clamp(self.dt_min, self.dt_max)
+    }
+
+    // Smaller time step to retry a step whose local error exceeded the tolerance
+    pub fn retry_dt(&self, dt: f64, error: f64) -> f64 {
+        let factor = self
+            .criteria
+            .iter()
+            .filter_map(|c| match *c {
+                TimestepCriterion::LocalError { safety, order } => {
+                    Some(error_factor(error, safety, order))
+                }
+                _ => None,
+            })
+            .fold(1.0, f64::min);
+        (dt * factor).clamp(self.dt_min, self.dt_max)
+    }
+}
+
+fn error_factor(error: f64, safety: f64, order: u32) -> f64 {
+    if error == 0.0 {
+        return 5.0;
+    }
+    (safety * error.powf(-1.0 / (order as f64 + 1.0))).clamp(0.2, 5.0)
+}
@@ -0,0 +1,415 @@
+use crate::error::ConfigError;
+use crate::integrator::{ForceEvaluator, Integrator};
+use crate::particle::Particle;
+use rayon::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhCoordinates {
+    // Bodies are nested in index order after the central one, which suits
+    // hierarchical systems sorted by distance
+    Jacobi,
+    // Heliocentric positions and barycentric velocities (Duncan et al. 1998)
+    DemocraticHeliocentric,
+}
+
+// Mixed-variable symplectic integrator (Wisdom & Holman 1991) in the WHFast
+// drift-kick-drift form: Keplerian motion around the central body is solved
+// exactly, and the interactions between the other bodies are computed by the
+// active solver, which must compute Newtonian gravity and gives G to both, along
+// with the bonds.
+// Second order, with an error scaling as the mass ratio of the light bodies to
+// the central one.
+#[derive(Debug, Clone, Copy)]
+pub struct WisdomHolman {
+    coordinates: WhCoordinates,
+    // Index of the central body, the most massive particle if not given
+    central: Option<usize>,
+}
+
+impl WisdomHolman {
+    pub fn new(coordinates: WhCoordinates, central: Option<usize>) -> Self {
+        WisdomHolman {
+            coordinates,
+            central,
+        }
+    }
+
+    pub fn coordinates(&self) -> WhCoordinates {
+        self.coordinates
+    }
+
+    pub fn central(&self) -> Option<usize> {
+        self.central
+    }
+
+    fn central_index(&self, particles: &[Particle]) -> usize {
+        self.central.unwrap_or_else(|| {
+            particles
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.mass.total_cmp(&b.mass))
+                .map(|(i, _)| i)
+                .unwrap_or(0)
+        })
+    }
+}
+
+impl Integrator for WisdomHolman {
+    fn step(&self, particles: &mut [Particle], forces: &mut ForceEvaluator, dt: f64) {
+        if particles.len() < 2 {
+            forces.drift(particles, dt);
+            return;
+        }
+
+        let constant = forces
+            .gravitational_constant()
+            .expect("Wisdom-Holman needs a solver computing Newtonian gravity");
+        let central = self.central_index(particles);
+        match self.coordinates {
+            WhCoordinates::DemocraticHeliocentric => {
+                democratic_heliocentric_step(particles, central, constant, forces, dt)
+            }
+            WhCoordinates::Jacobi => jacobi_step(particles, central, constant, forces, dt),
+        }
+        forces.invalidate();
+    }
+
+    fn requires_gravity(&self) -> bool {
+        true
+    }
+
+    fn validate(&self, n: usize) -> Result<(), ConfigError> {
+        match self.central {
+            Some(central) if central >= n => Err(ConfigError::InvalidCentralBody(central, n)),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Interaction forces between all bodies but the central one, from the solver,
+// plus the forces of the bonds, which may involve the central body
+fn light_body_forces(
+    particles: &[Particle],
+    central: usize,
+    forces: &mut ForceEvaluator,
+) -> Vec<[f64; 2]> {
+    let mut light: Vec<Particle> = particles.to_vec();
+    light.remove(central);
+    let mut light_forces = vec![[0.0, 0.0]; light.len()];
+    forces.compute_subsystem_forces(&light, &mut light_forces);
+    light_forces.insert(central, [0.0, 0.0]);
+    forces.add_bond_forces(particles, &mut light_forces);
+    light_forces
+}
+
+fn democratic_heliocentric_step(
+    particles: &mut [Particle],
+    central: usize,
+    constant: f64,
+    forces: &mut ForceEvaluator,
+    dt: f64,
+) {
+    let masses: Vec<f64> = particles.iter().map(|p| p.mass).collect();
+    let central_mass = masses[central];
+    let mu = constant * central_mass;
+    let total_mass: f64 = masses.iter().sum();
+
+    let mut com = [0.0, 0.0];
+    let mut com_velocity = [0.0, 0.0];
+    for p in particles.iter() {
+        for d in 0..2 {
+            com[d] += p.mass * p.position[d] / total_mass;
+            com_velocity[d] += p.mass * p.velocity[d] / total_mass;
+        }
+    }
+
+    // Heliocentric positions and barycentric velocities
+    let origin = particles[central].position;
+    let mut coords: Vec<([f64; 2], [f64; 2])> = particles
+        .iter()
+        .map(|p| {
+            (
+                [p.position[0] - origin[0], p.position[1] - origin[1]],
+                [
+                    p.velocity[0] - com_velocity[0],
+                    p.velocity[1] - com_velocity[1],
+                ],
+            )
+        })
+        .collect();
+
+    let kepler = |coords: &mut Vec<([f64; 2], [f64; 2])>, h: f64| {
+        coords.par_iter_mut().enumerate().for_each(|(i, c)| {
+            if i != central {
+                *c = kepler_drift(c.0, c.1, mu, h);
+            }
+        });
+    };
+    let jump = |coords: &mut Vec<([f64; 2], [f64; 2])>, h: f64| {
+        let mut momentum = [0.0, 0.0];
+        for (i, (m, c)) in masses.iter().zip(coords.iter()).enumerate() {
+            if i != central {
+                momentum[0] += m * c.1[0];
+                momentum[1] += m * c.1[1];
+            }
+        }
+        for (i, c) in coords.iter_mut().enumerate() {
+            if i != central {
+                c.0[0] += h * momentum[0] / central_mass;
+                c.0[1] += h * momentum[1] / central_mass;
+            }
+        }
+    };
+
+    kepler(&mut coords, 0.5 * dt);
+    jump(&mut coords, 0.5 * dt);
+
+    // The barycentre moves uniformly
+    com[0] += com_velocity[0] * dt;
+    com[1] += com_velocity[1] * dt;
+
+    // Interaction kick. Every interaction, bonds to the central body included,
+    // depends on heliocentric positions only, so the central body is not kicked.
+    from_democratic_heliocentric(particles, &coords, central, com, com_velocity, total_mass);
+    let light_forces = light_body_forces(particles, central, forces);
+    for (i, (c, force)) in coords.iter_mut().zip(light_forces.iter()).enumerate() {
+        if i != central {
+            c.1[0] += dt * force[0] / masses[i];
+            c.1[1] += dt * force[1] / masses[i];
+        }
+    }
+
+    jump(&mut coords, 0.5 * dt);
+    kepler(&mut coords, 0.5 * dt);
+
+    from_democratic_heliocentric(particles, &coords, central, com, com_velocity, total_mass);
+}
+
+fn from_democratic_heliocentric(
+    particles: &mut [Particle],
+    coords: &[([f64; 2], [f64; 2])],
+    central: usize,
+    com: [f64; 2],
+    com_velocity: [f64; 2],
+    total_mass: f64,
+) {
+    let central_mass = particles[central].mass;
+    let mut weighted_position = [0.0, 0.0];
+    let mut momentum = [0.0, 0.0];
+    for (i, (p, c)) in particles.iter().zip(coords.iter()).enumerate() {
+        if i != central {
+            for d in 0..2 {
+                weighted_position[d] += p.mass * c.0[d];
+                momentum[d] += p.mass * c.1[d];
+            }
+        }
+    }
+
+    let mut origin = [0.0, 0.0];
+    for d in 0..2 {
+        origin[d] = com[d] - weighted_position[d] / total_mass;
+        particles[central].position[d] = origin[d];
+        particles[central].velocity[d] = com_velocity[d] - momentum[d] / central_mass;
+    }
+    for (i, (p, c)) in particles.iter_mut().zip(coords.iter()).enumerate() {
+        if i != central {
+            for d in 0..2 {
+                p.position[d] = c.0[d] + origin[d];
+                p.velocity[d] = c.1[d] + com_velocity[d];
+            }
+        }
+    }
+}
+
+fn jacobi_step(
+    particles: &mut [Particle],
+    central: usize,
+    constant: f64,
+    forces: &mut ForceEvaluator,
+    dt: f64,
+) {
+    // Central body first, then the others in index order
+    let order: Vec<usize> = std::iter::once(central)
+        .chain((0..particles.len()).filter(|&i| i != central))
+        .collect();
+    let masses: Vec<f64> = order.iter().map(|&i| particles[i].mass).collect();
+    // Cumulative interior masses
+    let eta: Vec<f64> = masses
+        .iter()
+        .scan(0.0, |sum, m| {
+            *sum += m;
+            Some(*sum)
+        })
+        .collect();
+
+    let positions: Vec<[f64; 2]> = order.iter().map(|&i| particles[i].position).collect();
+    let velocities: Vec<[f64; 2]> = order.iter().map(|&i| particles[i].velocity).collect();
+    let mut jacobi_positions = to_jacobi(&positions, &masses, &eta);
+    let mut jacobi_velocities = to_jacobi(&velocities, &masses, &eta);
+
+    let kepler = |jp: &mut [[f64; 2]], jv: &mut [[f64; 2]], h: f64| {
+        jp.par_iter_mut()
+            .zip(jv.par_iter_mut())
+            .enumerate()
+            .for_each(|(i, (r, v))| {
+                if i == 0 {
+                    // Centre of mass
+                    r[0] += v[0] * h;
+                    r[1] += v[1] * h;
+                } else {
+                    (*r, *v) = kepler_drift(*r, *v, constant * eta[i], h);
+                }
+            });
+    };
+
+    kepler(&mut jacobi_positions, &mut jacobi_velocities, 0.5 * dt);
+
+    // Inertial accelerations: interactions from the solver, exact central term.
+    // Velocities are only needed by damped bonds.
+    let positions = from_jacobi(&jacobi_positions, &masses, &eta);
+    let velocities = from_jacobi(&jacobi_velocities, &masses, &eta);
+    for (k, &i) in order.iter().enumerate() {
+        particles[i].position = positions[k];
+        particles[i].velocity = velocities[k];
+    }
+    let light_forces = light_body_forces(particles, central, forces);
+    let central_position = positions[0];
+    let mut accelerations: Vec<[f64; 2]> = vec![[0.0, 0.0]; order.len()];
+    accelerations[0][0] = light_forces[central][0] / masses[0];
+    accelerations[0][1] = light_forces[central][1] / masses[0];
+    for (k, &i) in order.iter().enumerate().skip(1) {
+        let dx = positions[k][0] - central_position[0];
+        let dy = positions[k][1] - central_position[1];
+        let inv_r3 = 1.0 / (dx * dx + dy * dy).powf(1.5);
+        accelerations[k][0] = light_forces[i][0] / masses[k] - constant * masses[0] * dx * inv_r3;
+        accelerations[k][1] = light_forces[i][1] / masses[k] - constant * masses[0] * dy * inv_r3;
+        accelerations[0][0] += constant * masses[k] * dx * inv_r3;
+        accelerations[0][1] += constant * masses[k] * dy * inv_r3;
+    }
+
+    // Jacobi interaction accelerations, with the Keplerian part removed
+    let jacobi_accelerations = to_jacobi(&accelerations, &masses, &eta);
+    for k in 1..order.len() {
+        let r = jacobi_positions[k];
+        let inv_r3 = 1.0 / (r[0] * r[0] + r[1] * r[1]).powf(1.5);
+        for d in 0..2 {
+            let a = jacobi_accelerations[k][d] + constant * eta[k] * r[d] * inv_r3;
+            jacobi_velocities[k][d] += dt * a;
+        }
+    }
+
+    kepler(&mut jacobi_positions, &mut jacobi_velocities, 0.5 * dt);
+
+    let positions = from_jacobi(&jacobi_positions, &masses, &eta);
+    let velocities = from_jacobi(&jacobi_velocities, &masses, &eta);
+    for (k, &i) in order.iter().enumerate() {
+        particles[i].position = positions[k];
+        particles[i].velocity = velocities[k];
+    }
+}
+
+// Jacobi transform of positions, velocities or accelerations. Entry 0 holds the
+// centre of mass, entry i the vector relative to the centre of mass of bodies 0..i
+fn to_jacobi(vectors: &[[f64; 2]], masses: &[f64], eta: &[f64]) -> Vec<[f64; 2]> {
+    let mut jacobi = vec![[0.0, 0.0]; vectors.len()];
+    let mut com = vectors[0];
+    for i in 1..vectors.len() {
+        for d in 0..2 {
+            jacobi[i][d] = vectors[i][d] - com[d];
+            com[d] = (eta[i - 1] * com[d] + masses[i] * vectors[i][d]) / eta[i];
+        }
+    }
+    jacobi[0] = com;
+    jacobi
+}
+
+fn from_jacobi(jacobi: &[[f64; 2]], masses: &[f64], eta: &[f64]) -> Vec<[f64; 2]> {
+    let mut vectors = vec![[0.0, 0.0]; jacobi.len()];
+    let mut com = jacobi[0];
+    for i in (1..jacobi.len()).rev() {
+        for d in 0..2 {
+            com[d] -= masses[i] * jacobi[i][d] / eta[i];
+            vectors[i][d] = jacobi[i][d] + com[d];
+        }
+    }
+    vectors[0] = com;
+    vectors
+}
+
+// Stumpff functions c0..c3 of z
+fn stumpff(z: f64) -> [f64; 4] {
+    if z.abs() < 1e-2 {
+        // Series, to avoid cancellations for small arguments
+        let c2 = 1.0 / 2.0 - z / 24.0 + z * z / 720.0 - z * z * z / 40320.0;
+        let c3 = 1.0 / 6.0 - z / 120.0 + z * z / 5040.0 - z * z * z / 362880.0;
+        [1.0 - z * c2, 1.0 - z * c3, c2, c3]
+    } else if z > 0.0 {
+        let s = z.sqrt();
+        let c0 = s.cos();
+        let c1 = s.sin() / s;
+        [c0, c1, (1.0 - c0) / z, (1.0 - c1) / z]
+    } else {
+        let s = (-z).sqrt();
+        let c0 = s.cosh();
+        let c1 = s.sinh() / s;
+        [c0, c1, (1.0 - c0) / z, (1.0 - c1) / z]
+    }
+}
+
+// Advances a Kepler orbit of gravitational parameter `mu` by `dt` with the
+// universal variable formulation (Danby), valid for any eccentricity
+pub fn kepler_drift(
+    position: [f64; 2],
+    velocity: [f64; 2],
+    mu: f64,
+    dt: f64,
+) -> ([f64; 2], [f64; 2]) {
+    let r0 = (position[0] * position[0] + position[1] * position[1]).sqrt();
+    let v0_sq = velocity[0] * velocity[0] + velocity[1] * velocity[1];
+    let eta0 = position[0] * velocity[0] + position[1] * velocity[1];
+    let beta = 2.0 * mu / r0 - v0_sq;
+
+    // G-functions G_k = X^k c_k(beta X^2)
+    let g_functions = |x: f64| {
+        let c = stumpff(beta * x * x);
+        [c[0], x * c[1], x * x * c[2], x * x * x * c[3]]
+    };
+
+    // Solve r0 G1 + eta0 G2 + mu G3 = dt for the universal anomaly X, with
+    // Laguerre-Conway iterations which converge from any starting point
+    let mut x = dt / r0;
+    let n = 5.0;
+    for _ in 0..50 {
+        let g = g_functions(x);
+        let f = r0 * g[1] + eta0 * g[2] + mu * g[3] - dt;
+        let df = r0 * g[0] + eta0 * g[1] + mu * g[2];
+        let ddf = eta0 * g[0] + (mu - beta * r0) * g[1];
+        let root = ((n - 1.0) * (n - 1.0) * df * df - n * (n - 1.0) * f * ddf)
+            .abs()
+            .sqrt();
+        let denominator = if df >= 0.0 { df + root } else { df - root };
+        let dx = n * f / denominator;
+        x -= dx;
+        if dx.abs() <= 1e-15 * x.abs().max(1e-300) {
+            break;
+        }
+    }
+
+    let g = g_functions(x);
+    let r = r0 * g[0] + eta0 * g[1] + mu * g[2];
+    let f = 1.0 - mu * g[2] / r0;
+    let g_coeff = dt - mu * g[3];
+    let df = -mu * g[1] / (r * r0);
+    let dg = 1.0 - mu * g[2] / r;
+
+    (
+        [
+            f * position[0] + g_coeff * velocity[0],
+            f * position[1] + g_coeff * velocity[1],
+        ],
+        [
+            df * position[0] + dg * velocity[0],
+            df * position[1] + dg * velocity[1],
+        ],
+    )
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use common::CountingSolver;
+use particle_sim::block_timestep::BlockTimesteps;
+use particle_sim::integrator::LeapfrogKdk;
+use particle_sim::simulation::DirectSum;
+use particle_sim::{Particle, Simulation, Softening};
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// A circular binary of period 2 amid a sparse ring of light particles
+fn binary_and_ring() -> Vec<Particle> {
+    let mut particles = vec![
+        Particle::new([-1.0, 0.0], [0.0, -PI], 1.0),
+        Particle::new([1.0, 0.0], [0.0, PI], 1.0),
+    ];
+    for k in 0..8 {
+        let angle = k as f64 * PI / 4.0;
+        particles.push(Particle::new(
+            [400.0 * angle.cos(), 400.0 * angle.sin()],
+            [0.0, 0.0],
+            1e-3,
+        ));
+    }
+    particles
+}
+
+fn counting_simulation(
+    particles: Vec<Particle>,
+    integrator: BlockTimesteps,
+) -> (Simulation, Arc<AtomicUsize>) {
+    let solver = DirectSum::new(Softening::Plummer { epsilon: 0.01 }).unwrap();
+    let (solver, evaluations) = CountingSolver::new(solver);
+    let simulation = Simulation::new(particles, 0.1, solver, integrator).unwrap();
+    (simulation, evaluations)
+}
+
+#[test]
+fn max_rung_is_capped() {
+    assert!(BlockTimesteps::new(BlockTimesteps::MAX_RUNG, 0.1, 1.0).is_ok());
+    assert!(BlockTimesteps::new(BlockTimesteps::MAX_RUNG + 1, 0.1, 1.0).is_err());
+}
+
+#[test]
+fn forces_are_evaluated_once_per_step_of_the_finest_occupied_rung() {
+    let integrator = BlockTimesteps::new(BlockTimesteps::MAX_RUNG, 0.5, 0.01).unwrap();
+    let (mut simulation, evaluations) = counting_simulation(binary_and_ring(), integrator);
+    simulation.simulation_step();
+
+    for _ in 0..5 {
+        let rungs = integrator.assign_rungs(&simulation.particles, &simulation.total_forces, 0.1);
+        let finest = *rungs.iter().max().unwrap();
+        assert!(finest > 0 && finest < 10);
+        evaluations.store(0, Ordering::Relaxed);
+        simulation.simulation_step();
+        // Rungs may change during the step, so allow some slack either way
+        let count = evaluations.load(Ordering::Relaxed);
+        assert!(
+            count >= 1 << (finest - 1) && count <= 4 << finest,
+            "{} on rung {}",
+            count,
+            finest
+        );
+    }
+}
+
+#[test]
+fn single_rung_matches_leapfrog() {
+    // A huge accuracy parameter puts every particle on rung 0
+    let integrator = BlockTimesteps::new(BlockTimesteps::MAX_RUNG, 1e6, 1.0).unwrap();
+    let (mut block, evaluations) = counting_simulation(binary_and_ring(), integrator);
+    let solver = DirectSum::new(Softening::Plummer { epsilon: 0.01 }).unwrap();
+    let mut leapfrog = Simulation::new(binary_and_ring(), 0.1, solver, LeapfrogKdk).unwrap();
+    for _ in 0..20 {
+        block.simulation_step();
+        leapfrog.simulation_step();
+    }
+    // One evaluation per step, plus the initial one
+    assert_eq!(evaluations.load(Ordering::Relaxed), 21);
+    for (a, b) in block.particles.iter().zip(leapfrog.particles.iter()) {
+        for d in 0..2 {
+            assert!((a.position[d] - b.position[d]).abs() < 1e-12);
+            assert!((a.velocity[d] - b.velocity[d]).abs() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use particle_sim::bonds::{add_bond_forces, bond_energy, Bond, FENE_MAX_STRETCH};
+use particle_sim::error::ConfigError;
+use particle_sim::integrator::{Hermite, VelocityVerlet};
+use particle_sim::pair_force::{Gravity, LennardJones};
+use particle_sim::simulation::{DirectSum, NeighbourList, NeighbourListConfig};
+use particle_sim::{Diagnostics, Particle, Simulation, Softening};
+use std::f64::consts::PI;
+
+// No forces besides those of the bonds
+fn free() -> DirectSum {
+    DirectSum::with_force(Gravity {
+        constant: 0.0,
+        softening: Softening::None,
+    })
+    .unwrap()
+}
+
+fn bond_forces(bonds: &[Bond], particles: &[Particle]) -> Vec<[f64; 2]> {
+    let mut forces = vec![[0.0, 0.0]; particles.len()];
+    add_bond_forces(bonds, particles, None, &mut forces);
+    forces
+}
+
+fn triangle() -> Vec<Particle> {
+    vec![
+        Particle::new([0.3, -0.1], [0.2, 0.5], 1.0),
+        Particle::new([1.1, 0.2], [-0.4, 0.1], 2.0),
+        Particle::new([1.4, 1.3], [0.3, -0.2], 1.5),
+    ]
+}
+
+const BONDS: [Bond; 5] = [
+    Bond::Harmonic {
+        i: 0,
+        j: 2,
+        stiffness: 3.0,
+        rest_length: 1.0,
+        damping: 0.0,
+    },
+    Bond::Fene {
+        i: 1,
+        j: 0,
+        stiffness: 2.0,
+        max_length: 1.5,
+    },
+    Bond::Angle {
+        i: 0,
+        vertex: 1,
+        j: 2,
+        stiffness: 1.5,
+        rest_angle: 2.0,
+    },
+    Bond::Angle {
+        i: 2,
+        vertex: 1,
+        j: 0,
+        stiffness: 1.5,
+        rest_angle: 0.5,
+    },
+    // Nearly aligned, about a straight rest angle
+    Bond::Angle {
+        i: 0,
+        vertex: 1,
+        j: 2,
+        stiffness: 1.0,
+        rest_angle: PI,
+    },
+];
+
+#[test]
+fn bond_forces_are_minus_the_gradient_of_the_energy() {
+    let mut straight = triangle();
+    straight[2].position = [1.9, 0.51];
+    for particles in [triangle(), straight] {
+        for bond in BONDS {
+            let forces = bond_forces(&[bond], &particles);
+            for (k, force) in forces.iter().enumerate() {
+                for (d, f) in force.iter().enumerate() {
+                    let h = 1e-6;
+                    let (mut plus, mut minus) = (particles.clone(), particles.clone());
+                    plus[k].position[d] += h;
+                    minus[k].position[d] -= h;
+                    let gradient = (bond_energy(&[bond], &plus, None)
+                        - bond_energy(&[bond], &minus, None))
+                        / (2.0 * h);
+                    assert!(
+                        (f + gradient).abs() < 1e-6 * gradient.abs().max(1.0),
+                        "{:?} on {}: {} and {}",
+                        bond,
+                        k,
+                        f,
+                        -gradient
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn bonds_conserve_momentum_and_angular_momentum() {
+    let particles = triangle();
+    let damped = Bond::Harmonic {
+        i: 1,
+        j: 2,
+        stiffness: 1.0,
+        rest_length: 0.5,
+        damping: 0.7,
+    };
+    for bond in BONDS.iter().chain([damped].iter()) {
+        let forces = bond_forces(&[*bond], &particles);
+        let (mut total, mut torque) = ([0.0, 0.0], 0.0);
+        for (p, f) in particles.iter().zip(forces.iter()) {
+            total[0] += f[0];
+            total[1] += f[1];
+            torque += p.position[0] * f[1] - p.position[1] * f[0];
+        }
+        assert!(
+            total[0].abs() < 1e-12 && total[1].abs() < 1e-12,
+            "{:?}",
+            total
+        );
+        assert!(torque.abs() < 1e-12, "{:?}: {}", bond, torque);
+    }
+}
+
+#[test]
+fn polymer_under_gravity_conserves_energy() {
+    // Bent chain with springs between neighbours and angles at each joint
+    let n = 8;
+    let particles: Vec<Particle> = (0..n)
+        .map(|i| {
+            let angle = 0.3 * i as f64;
+            let position = [3.0 * angle.cos(), 3.0 * angle.sin()];
+            Particle::new(position, [0.0, 0.1 * (i % 3) as f64], 1e-3)
+        })
+        .collect();
+    let mut bonds = Vec::new();
+    for i in 0..n - 1 {
+        bonds.push(Bond::Harmonic {
+            i,
+            j: i + 1,
+            stiffness: 0.02,
+            rest_length: 0.8,
+            damping: 0.0,
+        });
+    }
+    for i in 1..n - 1 {
+        bonds.push(Bond::Angle {
+            i: i - 1,
+            vertex: i,
+            j: i + 1,
+            stiffness: 0.01,
+            rest_angle: PI,
+        });
+    }
+
+    let mut simulation =
+        Simulation::new(particles, 5e-3, DirectSum::default(), VelocityVerlet).unwrap();
+    simulation.set_bonds(bonds).unwrap();
+    let energy = |simulation: &Simulation| {
+        Diagnostics::compute(&simulation.particles).total_energy
+            + bond_energy(simulation.bonds(), &simulation.particles, None)
+    };
+    let initial = energy(&simulation);
+    for _ in 0..4000 {
+        simulation.simulation_step();
+    }
+    let error = ((energy(&simulation) - initial) / initial).abs();
+    assert!(error < 1e-4, "{}", error);
+}
+
+#[test]
+fn damped_springs_dissipate_energy() {
+    let particles = vec![
+        Particle::new([0.0, 0.0], [0.0, 0.0], 1.0),
+        Particle::new([2.0, 0.0], [0.3, 0.0], 3.0),
+    ];
+    let bonds = vec![Bond::Harmonic {
+        i: 0,
+        j: 1,
+        stiffness: 4.0,
+        rest_length: 1.0,
+        damping: 2.0,
+    }];
+    let mut simulation = Simulation::new(particles, 1e-3, free(), VelocityVerlet).unwrap();
+    simulation.set_bonds(bonds).unwrap();
+    let energy = |simulation: &Simulation| {
+        Diagnostics::compute(&simulation.particles).kinetic_energy
+            + bond_energy(simulation.bonds(), &simulation.particles, None)
+    };
+    let initial_momentum = Diagnostics::compute(&simulation.particles).momentum;
+
+    let mut previous = energy(&simulation);
+    for _ in 0..20 {
+        for _ in 0..500 {
+            simulation.simulation_step();
+        }
+        let current = energy(&simulation);
+        assert!(current < previous, "{} after {}", current, previous);
+        previous = current;
+    }
+    // Only the motion of the centre of mass is left
+    let momentum = Diagnostics::compute(&simulation.particles).momentum;
+    let drift = 0.5 * (momentum[0].powi(2) + momentum[1].powi(2)) / 4.0;
+    assert!(
+        (previous - drift).abs() < 1e-6,
+        "{} and {}",
+        previous,
+        drift
+    );
+    for d in 0..2 {
+        assert!((momentum[d] - initial_momentum[d]).abs() < 1e-12);
+    }
+}
+
+#[test]
+fn fene_bonds_stay_below_their_maximum_length() {
+    let particles = vec![
+        Particle::new([0.0, 0.0], [-4.0, 0.0], 1.0),
+        Particle::new([1.0, 0.0], [4.0, 0.0], 1.0),
+    ];
+    let mut simulation = Simulation::new(particles, 1e-4, free(), VelocityVerlet).unwrap();
+    simulation
+        .set_bonds(vec![Bond::Fene {
+            i: 0,
+            j: 1,
+            stiffness: 5.0,
+            max_length: 1.5,
+        }])
+        .unwrap();
+    let mut longest: f64 = 0.0;
+    for _ in 0..20000 {
+        simulation.simulation_step();
+        let [a, b] = [simulation.particles[0], simulation.particles[1]];
+        longest = longest.max(b.position[0] - a.position[0]);
+    }
+    assert!(longest > 1.4 && longest < 1.5, "{}", longest);
+}
+
+#[test]
+fn overstretched_fene_bonds_continue_harmonically_and_are_counted() {
+    let bond = Bond::Fene {
+        i: 0,
+        j: 1,
+        stiffness: 5.0,
+        max_length: 1.5,
+    };
+    let pair = |r: f64| {
+        vec![
+            Particle::new([0.0, 0.0], [-4.0, 0.0], 1.0),
+            Particle::new([r, 0.0], [4.0, 0.0], 1.0),
+        ]
+    };
+    let limit = 1.5 * FENE_MAX_STRETCH.sqrt();
+    for r in [1.0, limit, 1.5, 2.0, 4.0] {
+        let h = 1e-6;
+        let force = bond_forces(&[bond], &pair(r))[1][0];
+        let gradient = (bond_energy(&[bond], &pair(r + h), None)
+            - bond_energy(&[bond], &pair(r - h), None))
+            / (2.0 * h);
+        assert!(force.is_finite() && force < 0.0, "{} at {}", force, r);
+        assert!((force + gradient).abs() < 1e-6 * gradient.abs(), "at {}", r);
+        assert_eq!(
+            add_bond_forces(&[bond], &pair(r), None, &mut [[0.0; 2]; 2]),
+            (r > limit) as usize
+        );
+    }
+    let below = bond_energy(&[bond], &pair(limit - 1e-12), None);
+    let above = bond_energy(&[bond], &pair(limit + 1e-12), None);
+    assert!((above - below).abs() < 1e-8, "{} and {}", below, above);
+
+    // Far too long a time step for the bond
+    let mut simulation = Simulation::new(pair(1.0), 0.1, free(), VelocityVerlet).unwrap();
+    simulation.set_bonds(vec![bond]).unwrap();
+    assert_eq!(simulation.overstretched_bonds(), 0);
+    let mut counted = 0;
+    for _ in 0..20 {
+        simulation.simulation_step();
+        counted += simulation.overstretched_bonds();
+    }
+    assert!(counted > 0);
+    assert!(simulation.particles.iter().all(|p| p
+        .position
+        .iter()
+        .chain(p.velocity.iter())
+        .all(|x| x.is_finite())));
+}
+
+#[test]
+fn bonds_join_the_nearest_images_in_periodic_boxes() {
+    let domain = [0.0, 0.0, 10.0, 10.0];
+    let config = NeighbourListConfig::new(LennardJones::wca(1.0, 0.1))
+        .unwrap()
+        .with_periodic_domain(domain)
+        .unwrap();
+    let particles = vec![
+        Particle::new([0.5, 5.0], [0.0, 0.0], 1.0),
+        Particle::new([9.0, 5.0], [0.0, 0.0], 1.0),
+    ];
+    let mut simulation =
+        Simulation::new(particles, 1e-3, NeighbourList::new(config), VelocityVerlet).unwrap();
+    simulation
+        .set_bonds(vec![Bond::Harmonic {
+            i: 0,
+            j: 1,
+            stiffness: 1.0,
+            rest_length: 1.0,
+            damping: 0.0,
+        }])
+        .unwrap();
+    simulation.simulation_step();
+    // Stretched to 1.5 through the edge at x = 0, so pulled towards it
+    let forces = &simulation.total_forces;
+    assert!((forces[0][0] + 0.5).abs() < 1e-3, "{:?}", forces);
+    assert!((forces[1][0] - 0.5).abs() < 1e-3, "{:?}", forces);
+}
+
+#[test]
+fn invalid_bonds_are_rejected() {
+    let mut simulation = Simulation::new(triangle(), 1e-3, free(), VelocityVerlet).unwrap();
+    let invalid = [
+        Bond::Harmonic {
+            i: 0,
+            j: 3,
+            stiffness: 1.0,
+            rest_length: 1.0,
+            damping: 0.0,
+        },
+        Bond::Fene {
+            i: 1,
+            j: 1,
+            stiffness: 1.0,
+            max_length: 1.0,
+        },
+        Bond::Angle {
+            i: 0,
+            vertex: 1,
+            j: 2,
+            stiffness: -1.0,
+            rest_angle: 1.0,
+        },
+    ];
+    for bond in invalid {
+        assert_eq!(
+            simulation.set_bonds(vec![BONDS[0], bond]).unwrap_err(),
+            ConfigError::InvalidBond(bond)
+        );
+    }
+    assert!(simulation.bonds().is_empty());
+
+    let mut hermite =
+        Simulation::new(triangle(), 1e-3, free(), Hermite::new(0.02).unwrap()).unwrap();
+    assert_eq!(
+        hermite.set_bonds(BONDS.to_vec()).unwrap_err(),
+        ConfigError::JerkNotSupported
+    );
+}
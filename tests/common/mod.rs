@@ -0,0 +1,77 @@
+// Helpers shared by the integration tests, each of which uses only some of them
+#![allow(dead_code)]
+
+use particle_sim::{ForceSolver, Particle, Softening};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+pub fn forces(solver: &mut dyn ForceSolver, particles: &[Particle]) -> Vec<[f64; 2]> {
+    let mut forces = vec![[0.0, 0.0]; particles.len()];
+    solver.compute_forces(particles, &mut forces);
+    forces
+}
+
+// Error relative to the RMS of the reference forces, or absolute when they all
+// vanish
+pub fn relative_rms_error(forces: &[[f64; 2]], reference: &[[f64; 2]]) -> f64 {
+    let mut error = 0.0;
+    let mut norm = 0.0;
+    for (f, r) in forces.iter().zip(reference.iter()) {
+        error += (f[0] - r[0]).powi(2) + (f[1] - r[1]).powi(2);
+        norm += r[0] * r[0] + r[1] * r[1];
+    }
+    if norm == 0.0 {
+        return error.sqrt();
+    }
+    (error / norm).sqrt()
+}
+
+// Particles scattered over the square of side `extent` starting at `origin` in
+// both directions, evenly but without any lattice structure
+pub fn scattered_particles(n: usize, origin: f64, extent: f64) -> Vec<Particle> {
+    (0..n)
+        .map(|i| {
+            let x = origin + (i as f64 * 0.618_034).fract() * extent;
+            let y = origin + (i as f64 * 0.414_214).fract() * extent;
+            Particle::new([x, y], [0.0, 0.0], 1.0 + (i % 5) as f64)
+        })
+        .collect()
+}
+
+// Solver counting its evaluations, full or partial
+pub struct CountingSolver<S> {
+    pub inner: S,
+    pub evaluations: Arc<AtomicUsize>,
+}
+
+impl<S: ForceSolver> CountingSolver<S> {
+    pub fn new(inner: S) -> (Self, Arc<AtomicUsize>) {
+        let evaluations = Arc::new(AtomicUsize::new(0));
+        let solver = CountingSolver {
+            inner,
+            evaluations: evaluations.clone(),
+        };
+        (solver, evaluations)
+    }
+}
+
+impl<S: ForceSolver> ForceSolver for CountingSolver<S> {
+    fn compute_forces(&mut self, particles: &[Particle], forces: &mut [[f64; 2]]) {
+        self.evaluations.fetch_add(1, Ordering::Relaxed);
+        self.inner.compute_forces(particles, forces);
+    }
+
+    fn compute_active_forces(
+        &mut self,
+        particles: &[Particle],
+        active: &[usize],
+        forces: &mut [[f64; 2]],
+    ) {
+        self.evaluations.fetch_add(1, Ordering::Relaxed);
+        self.inner.compute_active_forces(particles, active, forces);
+    }
+
+    fn softening(&self) -> Softening {
+        self.inner.softening()
+    }
+}
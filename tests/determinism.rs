@@ -0,0 +1,62 @@
+mod common;
+
+use common::{forces, scattered_particles};
+use particle_sim::simulation::{
+    BarnesHutConfig, BarnesHutParallel, DirectSumParallel, FastMultipole, FmmConfig,
+    LinearBarnesHut, ParticleMesh, PmConfig, TreePm, TreePmConfig,
+};
+use particle_sim::ForceSolver;
+
+fn bits(forces: &[[f64; 2]]) -> Vec<[u64; 2]> {
+    forces
+        .iter()
+        .map(|f| [f[0].to_bits(), f[1].to_bits()])
+        .collect()
+}
+
+// Forces from fresh solvers on thread pools of different sizes, which split
+// the parallel work differently, must agree to the last bit
+fn check_reproducible(name: &str, n: usize, solver: impl Fn() -> Box<dyn ForceSolver>) {
+    let particles = scattered_particles(n, 0.0, 1000.0);
+    let results: Vec<Vec<[u64; 2]>> = [1, 3, 8]
+        .iter()
+        .map(|&threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let mut solver = solver();
+            pool.install(|| bits(&forces(&mut *solver, &particles)))
+        })
+        .collect();
+    assert!(results.iter().all(|r| *r == results[0]), "{}", name);
+}
+
+#[test]
+fn direct_and_tree_forces_are_bitwise_reproducible() {
+    let config = BarnesHutConfig::new(0.5).unwrap();
+    check_reproducible("direct sum", 2_000, || {
+        Box::new(DirectSumParallel::default())
+    });
+    check_reproducible("Barnes-Hut", 2_000, || {
+        Box::new(BarnesHutParallel::new(config))
+    });
+    check_reproducible("linear tree", 2_000, || {
+        Box::new(LinearBarnesHut::new(config))
+    });
+}
+
+#[test]
+fn fmm_forces_are_bitwise_reproducible() {
+    let config = FmmConfig::new(8, 0.5).unwrap();
+    check_reproducible("FMM", 2_000, || Box::new(FastMultipole::new(config)));
+}
+
+#[test]
+fn mesh_forces_are_bitwise_reproducible() {
+    let mesh = PmConfig::isolated(128).unwrap();
+    let tree_pm = TreePmConfig::new(mesh, 0.5).unwrap();
+    // Enough particles for the mass assignment to be split into many chunks
+    check_reproducible("PM", 40_000, || Box::new(ParticleMesh::new(mesh)));
+    check_reproducible("TreePM", 40_000, || Box::new(TreePm::new(tree_pm)));
+}
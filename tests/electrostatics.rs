@@ -0,0 +1,142 @@
+mod common;
+
+use common::{forces, relative_rms_error};
+use particle_sim::linear_tree::LinearQuadTree;
+use particle_sim::pair_force::{Coulomb, Coupling, Yukawa};
+use particle_sim::quadtree::{MultipoleOrder, QuadTree, TreeLimits};
+use particle_sim::simulation::{
+    BarnesHut, BarnesHutConfig, BarnesHutParallel, DirectSum, LinearBarnesHut,
+};
+use particle_sim::{ForceSolver, PairForce, Particle};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Neutral plasma as from generate_neutral_plasma, but with a seeded generator
+// so that the error bounds below hold every run
+fn neutral_plasma(n: usize) -> Vec<Particle> {
+    let mut rng = StdRng::seed_from_u64(0);
+    (0..n)
+        .map(|i| {
+            let position = [rng.gen_range(0.0..1500.0), rng.gen_range(0.0..900.0)];
+            let charge = if i % 2 == 0 { 1.0 } else { -1.0 };
+            Particle::new(position, [0.0, 0.0], 1.0).with_charge(charge)
+        })
+        .collect()
+}
+
+fn config<F: PairForce>(theta: f64, force: F, leaf_capacity: usize) -> BarnesHutConfig<F> {
+    let limits = TreeLimits {
+        leaf_capacity,
+        max_depth: TreeLimits::MAX_DEPTH,
+    };
+    BarnesHutConfig::new(theta)
+        .unwrap()
+        .with_limits(limits)
+        .unwrap()
+        .with_force(force)
+        .unwrap()
+}
+
+#[test]
+fn centres_of_signed_nodes_stay_inside_them() {
+    let particles = neutral_plasma(2000);
+    let boundary = QuadTree::bounding_square(&particles);
+    let tree = LinearQuadTree::build_with_coupling(
+        &particles,
+        boundary,
+        TreeLimits::default(),
+        Coupling::Charge,
+    );
+    for node in tree.nodes.iter() {
+        let [x_min, y_min, x_max, y_max] = node.boundary;
+        let [x, y] = node.center_of_mass;
+        assert!(x >= x_min && x <= x_max && y >= y_min && y <= y_max);
+
+        let charges = &tree.particles[node.start..node.end];
+        let net: f64 = charges.iter().map(|p| p.charge).sum();
+        assert!((node.mass - net).abs() < 1e-9);
+        assert_eq!(node.abs_mass, charges.len() as f64);
+    }
+}
+
+#[test]
+fn neutral_dipoles_are_seen_from_afar() {
+    // A bound pair far from a test charge: the node holding the pair has no
+    // net charge, only a dipole
+    let particles = [
+        Particle::new([0.0, 0.0], [0.0, 0.0], 1.0).with_charge(1.0),
+        Particle::new([1.0, 0.5], [0.0, 0.0], 1.0).with_charge(-1.0),
+        Particle::new([300.0, 200.0], [0.0, 0.0], 1.0).with_charge(1.0),
+    ];
+    let direct = forces(
+        &mut DirectSum::with_force(Coulomb::new(1.0)).unwrap(),
+        &particles,
+    );
+    let config = config(0.5, Coulomb::new(1.0), 1);
+    let tree = forces(&mut LinearBarnesHut::new(config), &particles);
+    let (f, r) = (tree[2], direct[2]);
+    let error = ((f[0] - r[0]).powi(2) + (f[1] - r[1]).powi(2)).sqrt();
+    let norm = (r[0] * r[0] + r[1] * r[1]).sqrt();
+    assert!(norm > 0.0);
+    assert!(error < 1e-2 * norm, "{:?} and {:?}", f, r);
+}
+
+#[test]
+fn tree_solvers_match_the_direct_sum_on_a_plasma() {
+    let particles = neutral_plasma(500);
+    let coulomb = Coulomb::new(1.0);
+    let direct = forces(&mut DirectSum::with_force(coulomb).unwrap(), &particles);
+    let config = config(0.0, coulomb, 4);
+    for solver in [
+        &mut BarnesHut::new(config) as &mut dyn ForceSolver,
+        &mut BarnesHutParallel::new(config),
+        &mut LinearBarnesHut::new(config),
+    ] {
+        let error = relative_rms_error(&forces(solver, &particles), &direct);
+        assert!(error < 1e-12, "{}", error);
+    }
+}
+
+#[test]
+fn higher_orders_improve_signed_charges() {
+    let particles = neutral_plasma(2000);
+    let coulomb = Coulomb::new(1.0);
+    let direct = forces(&mut DirectSum::with_force(coulomb).unwrap(), &particles);
+    let mut previous = f64::INFINITY;
+    for order in [
+        MultipoleOrder::Monopole,
+        MultipoleOrder::Quadrupole,
+        MultipoleOrder::Octupole,
+    ] {
+        let config = config(0.5, coulomb, 4).with_multipole_order(order);
+        let error = relative_rms_error(
+            &forces(&mut LinearBarnesHut::new(config), &particles),
+            &direct,
+        );
+        assert!(
+            error < previous,
+            "{:?}: {} after {}",
+            order,
+            error,
+            previous
+        );
+        // Without the dipoles of the nodes, the error stays around 1e-3
+        assert!(error < 5e-4, "{:?}: {}", order, error);
+        previous = error;
+    }
+}
+
+#[test]
+fn screened_plasmas_keep_the_dipoles() {
+    let particles = neutral_plasma(2000);
+    let yukawa = Yukawa::new(Coupling::Charge, 1.0, 100.0);
+    let direct = forces(&mut DirectSum::with_force(yukawa).unwrap(), &particles);
+    let config = config(0.5, yukawa, 4);
+    for solver in [
+        &mut BarnesHut::new(config) as &mut dyn ForceSolver,
+        &mut LinearBarnesHut::new(config),
+    ] {
+        let error = relative_rms_error(&forces(solver, &particles), &direct);
+        assert!(error < 3e-4, "{}", error);
+    }
+}
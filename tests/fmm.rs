@@ -0,0 +1,58 @@
+mod common;
+
+use common::{forces, relative_rms_error, scattered_particles};
+use particle_sim::simulation::{DirectSum, FastMultipole, FmmConfig};
+use particle_sim::Particle;
+
+fn fmm_error(order: usize, theta: f64, particles: &[Particle], direct: &[[f64; 2]]) -> f64 {
+    let config = FmmConfig::new(order, theta).unwrap();
+    relative_rms_error(&forces(&mut FastMultipole::new(config), particles), direct)
+}
+
+#[test]
+fn error_falls_with_the_expansion_order() {
+    let particles = scattered_particles(500, 0.0, 100.0);
+    let direct = forces(&mut DirectSum::default(), &particles);
+    for theta in [0.3, 0.5, 0.7] {
+        let mut previous = f64::INFINITY;
+        for order in [1, 2, 4, 8, 16] {
+            let error = fmm_error(order, theta, &particles, &direct);
+            assert!(error < 0.5 * previous, "order {} at theta {}", order, theta);
+            // Truncation error of the expansions
+            assert!(
+                error < theta.powi(order as i32 + 1),
+                "order {} at theta {}",
+                order,
+                theta
+            );
+            previous = error;
+        }
+    }
+}
+
+#[test]
+fn error_falls_with_theta_at_fixed_order() {
+    let particles = scattered_particles(500, 0.0, 100.0);
+    let direct = forces(&mut DirectSum::default(), &particles);
+    for order in [2, 6] {
+        let coarse = fmm_error(order, 0.7, &particles, &direct);
+        let fine = fmm_error(order, 0.3, &particles, &direct);
+        assert!(fine < 0.1 * coarse, "order {}", order);
+    }
+}
+
+#[test]
+fn high_orders_reach_round_off() {
+    let particles = scattered_particles(500, 0.0, 100.0);
+    let direct = forces(&mut DirectSum::default(), &particles);
+    assert!(fmm_error(24, 0.3, &particles, &direct) < 1e-11);
+}
+
+#[test]
+fn invalid_configs_are_rejected() {
+    assert!(FmmConfig::new(0, 0.5).is_err());
+    assert!(FmmConfig::new(31, 0.5).is_err());
+    assert!(FmmConfig::new(4, 0.0).is_err());
+    assert!(FmmConfig::new(4, 1.0).is_err());
+    assert!(FmmConfig::new(30, 0.99).is_ok());
+}
@@ -0,0 +1,200 @@
+use particle_sim::forces::GRAVIT_CONST;
+use particle_sim::integrator::{
+    Composition, DormandPrince, Hermite, LeapfrogDkd, LeapfrogKdk, RungeKutta4, VelocityVerlet,
+};
+use particle_sim::simulation::{BarnesHut, BarnesHutConfig, DirectSum};
+use particle_sim::timestep::{TimestepController, TimestepCriterion};
+use particle_sim::{ConfigError, Integrator, Particle, Simulation, Softening};
+use std::fmt::Debug;
+
+const PLANET_MASS: f64 = 1e-3;
+const MU: f64 = GRAVIT_CONST * (1.0 + PLANET_MASS);
+// Orbit of semi-major axis 4 and eccentricity 0.5, whose period is close to 8
+const SEMI_MAJOR_AXIS: f64 = 4.0;
+const ECCENTRICITY: f64 = 0.5;
+
+// Relative position and velocity on the orbit, `time` after the perihelion
+// on the x axis
+fn kepler_orbit(time: f64) -> ([f64; 2], [f64; 2]) {
+    let (a, e) = (SEMI_MAJOR_AXIS, ECCENTRICITY);
+    let mean_motion = (MU / (a * a * a)).sqrt();
+    let mean_anomaly = mean_motion * time;
+    let mut anomaly = mean_anomaly;
+    for _ in 0..50 {
+        anomaly -= (anomaly - e * anomaly.sin() - mean_anomaly) / (1.0 - e * anomaly.cos());
+    }
+    let (sin, cos) = anomaly.sin_cos();
+    let b = a * (1.0 - e * e).sqrt();
+    let rate = mean_motion / (1.0 - e * cos);
+    ([a * (cos - e), b * sin], [-a * sin * rate, b * cos * rate])
+}
+
+// Sun and planet with the given relative position and velocity, about their
+// centre of mass at rest at the origin
+fn pair(r: [f64; 2], v: [f64; 2]) -> Vec<Particle> {
+    let sun = -PLANET_MASS / (1.0 + PLANET_MASS);
+    let planet = 1.0 / (1.0 + PLANET_MASS);
+    vec![
+        Particle::new([sun * r[0], sun * r[1]], [sun * v[0], sun * v[1]], 1.0),
+        Particle::new(
+            [planet * r[0], planet * r[1]],
+            [planet * v[0], planet * v[1]],
+            PLANET_MASS,
+        ),
+    ]
+}
+
+fn perihelion() -> Vec<Particle> {
+    let (r, v) = kepler_orbit(0.0);
+    pair(r, v)
+}
+
+// Error on the relative position after most of an orbit from the perihelion
+fn orbit_error(integrator: impl Integrator + 'static, steps: usize) -> f64 {
+    let time = 6.4;
+    let solver = DirectSum::new(Softening::None).unwrap();
+    let mut simulation =
+        Simulation::new(perihelion(), time / steps as f64, solver, integrator).unwrap();
+    for _ in 0..steps {
+        simulation.simulation_step();
+    }
+    let [sun, planet] = [simulation.particles[0], simulation.particles[1]];
+    let (exact, _) = kepler_orbit(time);
+    let dx = planet.position[0] - sun.position[0] - exact[0];
+    let dy = planet.position[1] - sun.position[1] - exact[1];
+    (dx * dx + dy * dy).sqrt()
+}
+
+fn check_order(integrator: impl Integrator + Clone + Debug + 'static, steps: usize, order: f64) {
+    let coarse = orbit_error(integrator.clone(), steps);
+    let fine = orbit_error(integrator.clone(), 2 * steps);
+    let measured = (coarse / fine).log2();
+    assert!(
+        (measured - order).abs() < 0.3,
+        "{:?}: errors {} and {}, order {}",
+        integrator,
+        coarse,
+        fine,
+        measured
+    );
+}
+
+#[test]
+fn the_reference_orbit_is_exact() {
+    // Back at the perihelion after one period
+    let period = 2.0 * std::f64::consts::PI * (SEMI_MAJOR_AXIS.powi(3) / MU).sqrt();
+    let (r, v) = kepler_orbit(period);
+    let (r0, v0) = kepler_orbit(0.0);
+    for d in 0..2 {
+        assert!((r[d] - r0[d]).abs() < 1e-12 && (v[d] - v0[d]).abs() < 1e-12);
+    }
+    // Vis-viva at the aphelion
+    let (r, v) = kepler_orbit(0.5 * period);
+    let speed_sq = v[0] * v[0] + v[1] * v[1];
+    assert!((r[0] + 6.0).abs() < 1e-12);
+    assert!((speed_sq - MU * (2.0 / 6.0 - 1.0 / SEMI_MAJOR_AXIS)).abs() < 1e-12);
+}
+
+#[test]
+fn leapfrog_and_verlet_converge_at_second_order() {
+    check_order(LeapfrogKdk, 100, 2.0);
+    check_order(LeapfrogDkd, 100, 2.0);
+    check_order(VelocityVerlet, 100, 2.0);
+}
+
+#[test]
+fn compositions_raise_the_order_of_leapfrog() {
+    check_order(Composition::forest_ruth(), 100, 4.0);
+    check_order(Composition::yoshida4(), 100, 4.0);
+    check_order(Composition::yoshida6(), 100, 6.0);
+}
+
+#[test]
+fn composition_weights_must_be_finite_and_sum_to_one() {
+    assert!(Composition::new(LeapfrogKdk, vec![0.5, 0.5]).is_ok());
+    for weights in [
+        vec![],
+        vec![0.5, 0.4],
+        vec![f64::NAN],
+        vec![f64::INFINITY, 1.0],
+        vec![f64::INFINITY, f64::NEG_INFINITY],
+    ] {
+        assert!(matches!(
+            Composition::new(LeapfrogKdk, weights),
+            Err(ConfigError::InvalidCompositionWeights(_))
+        ));
+    }
+}
+
+#[test]
+fn runge_kutta_converges_at_fourth_order() {
+    check_order(RungeKutta4, 200, 4.0);
+}
+
+// Dormand-Prince step from the perihelion with a unit absolute tolerance, so
+// that the reported error is the RMS estimate itself, along with the RMS
+// error of the state against the exact orbit
+fn dopri_step(dt: f64) -> (f64, f64) {
+    let integrator = DormandPrince::new(1.0, 0.0).unwrap();
+    let solver = DirectSum::new(Softening::None).unwrap();
+    let mut simulation = Simulation::new(perihelion(), dt, solver, integrator).unwrap();
+    simulation.simulation_step();
+    let (r, v) = kepler_orbit(dt);
+    let mut sum = 0.0;
+    for (p, exact) in simulation.particles.iter().zip(pair(r, v)) {
+        for d in 0..2 {
+            sum += (p.position[d] - exact.position[d]).powi(2);
+            sum += (p.velocity[d] - exact.velocity[d]).powi(2);
+        }
+    }
+    (simulation.local_error().unwrap(), (sum / 8.0).sqrt())
+}
+
+#[test]
+fn dormand_prince_error_estimate_bounds_and_tracks_the_true_error() {
+    let (coarse_estimate, coarse_error) = dopri_step(0.08);
+    let (fine_estimate, fine_error) = dopri_step(0.04);
+    assert!(coarse_error < coarse_estimate && fine_error < fine_estimate);
+    // The embedded fourth-order solution has a local error of order dt^5
+    let order = (coarse_estimate / fine_estimate).log2();
+    assert!((order - 5.0).abs() < 0.5, "estimate order {}", order);
+}
+
+#[test]
+fn rejected_dormand_prince_steps_shrink_the_time_step() {
+    let integrator = DormandPrince::new(1e-10, 1e-10).unwrap();
+    let solver = DirectSum::new(Softening::None).unwrap();
+    let mut simulation = Simulation::new(perihelion(), 0.8, solver, integrator).unwrap();
+    let criterion = TimestepCriterion::LocalError {
+        safety: 0.9,
+        order: 4,
+    };
+    simulation.set_timestep_controller(Some(
+        TimestepController::new(vec![criterion], 1e-6, 0.8).unwrap(),
+    ));
+    simulation.simulation_step();
+    assert!(simulation.dt < 0.8);
+    assert!(simulation.local_error().unwrap() <= 1.0);
+    assert_eq!(simulation.time, simulation.dt);
+}
+
+#[test]
+fn hermite_requires_a_solver_computing_jerks() {
+    let solver = BarnesHut::new(BarnesHutConfig::new(0.5).unwrap());
+    let simulation = Simulation::new(perihelion(), 0.08, solver, Hermite::default());
+    assert!(matches!(simulation, Err(ConfigError::JerkNotSupported)));
+
+    // Also when composed
+    let composed = Composition::new(Hermite::default(), vec![1.0]).unwrap();
+    let solver = BarnesHut::new(BarnesHutConfig::new(0.5).unwrap());
+    let simulation = Simulation::new(perihelion(), 0.08, solver, composed.clone());
+    assert!(matches!(simulation, Err(ConfigError::JerkNotSupported)));
+    let solver = DirectSum::new(Softening::None).unwrap();
+    let mut simulation = Simulation::new(perihelion(), 0.08, solver, composed).unwrap();
+    simulation.simulation_step();
+}
+
+#[test]
+fn hermite_converges_at_fourth_order_with_direct_summation() {
+    check_order(Hermite::default(), 400, 4.0);
+}
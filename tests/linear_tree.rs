@@ -0,0 +1,104 @@
+mod common;
+
+use common::{forces, relative_rms_error, scattered_particles};
+use particle_sim::linear_tree::{par_radix_sort, LinearQuadTree};
+use particle_sim::quadtree::{MultipoleOrder, TreeLimits};
+use particle_sim::simulation::{BarnesHut, BarnesHutConfig, DirectSum, LinearBarnesHut};
+use particle_sim::{Particle, QuadTree};
+use proptest::prelude::*;
+
+// Arbitrary keys, and keys sharing their leading bytes so that the sort
+// recurses through every pass
+fn keys_strategy() -> impl Strategy<Value = Vec<u64>> {
+    prop_oneof![
+        prop::collection::vec(any::<u64>(), 0..10_000),
+        prop::collection::vec(
+            (0u64..1 << 12).prop_map(|k| (k << 4) | 0xAB << 56),
+            0..10_000
+        ),
+    ]
+}
+
+fn particles_strategy(max_len: usize) -> impl Strategy<Value = Vec<Particle>> {
+    prop::collection::vec((0.0f64..1000.0, 0.0f64..600.0, 1.0f64..100.0), 1..max_len).prop_map(
+        |points| {
+            points
+                .into_iter()
+                .map(|(x, y, mass)| Particle::new([x, y], [0.0, 0.0], mass))
+                .collect()
+        },
+    )
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn radix_sort_orders_any_keys(keys in keys_strategy()) {
+        let mut items: Vec<(u64, usize)> = keys.iter().copied().zip(0..).collect();
+        let mut expected = items.clone();
+        expected.sort_unstable();
+        par_radix_sort(&mut items);
+        prop_assert!(items.windows(2).all(|w| w[0].0 <= w[1].0));
+        // Items with equal keys may come in any order
+        items.sort_unstable();
+        prop_assert_eq!(items, expected);
+    }
+
+    #[test]
+    fn linear_tree_keeps_every_particle_and_its_mass(
+        particles in particles_strategy(2000),
+        leaf_capacity in 1usize..8,
+    ) {
+        let limits = TreeLimits {
+            leaf_capacity,
+            max_depth: TreeLimits::MAX_DEPTH,
+        };
+        let tree = LinearQuadTree::build(&particles, QuadTree::bounding_square(&particles), limits);
+        prop_assert_eq!(tree.dropped, 0);
+        let mut order = tree.order.clone();
+        order.sort_unstable();
+        prop_assert!(order.into_iter().eq(0..particles.len()));
+        for (p, &i) in tree.particles.iter().zip(tree.order.iter()) {
+            prop_assert_eq!(p.position, particles[i].position);
+        }
+
+        let root = tree.nodes[0];
+        prop_assert_eq!((root.start, root.end, root.skip), (0, particles.len(), tree.nodes.len()));
+        for node in tree.nodes.iter() {
+            let inside = &tree.particles[node.start..node.end];
+            let mass: f64 = inside.iter().map(|p| p.mass).sum();
+            prop_assert!((node.mass - mass).abs() <= 1e-9 * mass);
+            for d in 0..2 {
+                let centre = inside.iter().map(|p| p.mass * p.position[d]).sum::<f64>() / mass;
+                prop_assert!((node.center_of_mass[d] - centre).abs() <= 1e-9 * 1000.0);
+            }
+            if node.is_leaf() {
+                prop_assert!(node.end - node.start <= leaf_capacity);
+            }
+        }
+    }
+}
+
+#[test]
+fn linear_barnes_hut_matches_the_pointer_tree_for_every_multipole_order() {
+    let particles = scattered_particles(1000, 0.0, 100.0);
+    let direct = forces(&mut DirectSum::default(), &particles);
+    for order in [
+        MultipoleOrder::Monopole,
+        MultipoleOrder::Quadrupole,
+        MultipoleOrder::Octupole,
+    ] {
+        for theta in [0.0, 0.3] {
+            let config = BarnesHutConfig::new(theta)
+                .unwrap()
+                .with_multipole_order(order);
+            let linear = forces(&mut LinearBarnesHut::new(config), &particles);
+            let pointer = forces(&mut BarnesHut::new(config), &particles);
+            assert!(relative_rms_error(&linear, &pointer) < 1e-12, "{:?}", order);
+            let tolerance = if theta == 0.0 { 1e-12 } else { 1e-2 };
+            let error = relative_rms_error(&linear, &direct);
+            assert!(error < tolerance, "{:?} at {}: {}", order, theta, error);
+        }
+    }
+}
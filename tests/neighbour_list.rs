@@ -0,0 +1,199 @@
+mod common;
+
+use common::{forces, relative_rms_error};
+use particle_sim::diagnostics::Diagnostics;
+use particle_sim::error::ConfigError;
+use particle_sim::initial_conditions::generate_square_lattice;
+use particle_sim::integrator::VelocityVerlet;
+use particle_sim::pair_force::{Gravity, LennardJones, Morse};
+use particle_sim::simulation::{DirectSum, NeighbourList, NeighbourListConfig};
+use particle_sim::{PairForce, Particle, Simulation};
+
+const DOMAIN: [f64; 4] = [0.0, 0.0, 20.0, 20.0];
+
+// Jittered lattice filling DOMAIN, with no two particles too close
+fn gas(side: usize) -> Vec<Particle> {
+    let spacing = (DOMAIN[2] - DOMAIN[0]) / side as f64;
+    let mut particles = generate_square_lattice(side, spacing, 1.0);
+    for (i, p) in particles.iter_mut().enumerate() {
+        p.position[0] += 0.3 * spacing * ((i as f64 * 0.618_034).fract() - 0.5);
+        p.position[1] += 0.3 * spacing * ((i as f64 * 0.414_214).fract() - 0.5);
+    }
+    particles
+}
+
+#[test]
+fn short_range_forces_derive_from_their_potentials() {
+    fn check(force: &impl PairForce) {
+        let cutoff = force.cutoff().unwrap();
+        for i in 1..100 {
+            let r = 0.8 + 0.02 * i as f64;
+            if (r - cutoff).abs() < 1e-3 {
+                continue;
+            }
+            let h = 1e-6;
+            let derivative =
+                (force.potential(r + h, 0.0) - force.potential(r - h, 0.0)) / (2.0 * h);
+            let (f, f_derivative) = force.force_factor_and_derivative(r * r, 0.0);
+            assert!(
+                (f * r - derivative).abs() < 1e-5 * derivative.abs().max(1.0),
+                "{:?} at r = {}: {} and {}",
+                force,
+                r,
+                f * r,
+                derivative
+            );
+            let numerical = (force.force_factor((r + h) * (r + h), 0.0)
+                - force.force_factor((r - h) * (r - h), 0.0))
+                / (2.0 * h)
+                / r;
+            assert!(
+                (f_derivative - numerical).abs() < 1e-4 * numerical.abs().max(1.0),
+                "{:?} at r = {}: {} and {}",
+                force,
+                r,
+                f_derivative,
+                numerical
+            );
+        }
+        // Truncated and shifted: nothing at or beyond the cutoff
+        assert_eq!(force.force_factor(cutoff * cutoff, 0.0), 0.0);
+        assert!(force.potential(cutoff - 1e-9, 0.0).abs() < 1e-6);
+        assert_eq!(force.potential(cutoff + 1.0, 0.0), 0.0);
+    }
+
+    check(&LennardJones::new(1.0, 1.0, 2.5));
+    check(&LennardJones::wca(2.0, 1.1));
+    check(&Morse::new(1.5, 2.0, 1.2, 3.0));
+
+    // WCA is purely repulsive, and smooth at its cutoff
+    let wca = LennardJones::wca(1.0, 1.0);
+    let cutoff = wca.cutoff().unwrap();
+    assert!(wca.force_factor((0.9 * cutoff).powi(2), 0.0) < 0.0);
+    assert!(wca.force_factor((cutoff - 1e-9).powi(2), 0.0).abs() < 1e-6);
+}
+
+#[test]
+fn neighbour_lists_match_the_direct_sum() {
+    let particles = gas(30);
+    for force in [
+        LennardJones::new(1.0, 0.6, 1.5),
+        LennardJones::wca(1.0, 0.6),
+    ] {
+        let direct = forces(&mut DirectSum::with_force(force).unwrap(), &particles);
+        let config = NeighbourListConfig::new(force).unwrap();
+        let error = relative_rms_error(
+            &forces(&mut NeighbourList::new(config), &particles),
+            &direct,
+        );
+        assert!(error < 1e-12, "{:?}: {}", force, error);
+    }
+
+    let morse = Morse::new(1.0, 3.0, 0.7, 1.8);
+    let direct = forces(&mut DirectSum::with_force(morse).unwrap(), &particles);
+    let config = NeighbourListConfig::new(morse).unwrap();
+    let error = relative_rms_error(
+        &forces(&mut NeighbourList::new(config), &particles),
+        &direct,
+    );
+    assert!(error < 1e-12, "{}", error);
+}
+
+#[test]
+fn periodic_boxes_wrap_around() {
+    // Two particles on opposite edges of the box are 1 apart through it
+    let particles = [
+        Particle::new([0.2, 10.0], [0.0, 0.0], 1.0),
+        Particle::new([19.2, 10.0], [0.0, 0.0], 1.0),
+    ];
+    let force = LennardJones::new(1.0, 1.0, 2.5);
+    let config = NeighbourListConfig::new(force)
+        .unwrap()
+        .with_periodic_domain(DOMAIN)
+        .unwrap();
+    let result = forces(&mut NeighbourList::new(config), &particles);
+    // Repelled away from each other, so through the edge at x = 0
+    let expected = -force.force_factor(1.0, 0.0);
+    assert!((result[0][0] - expected).abs() < 1e-12, "{:?}", result);
+    assert!((result[1][0] + expected).abs() < 1e-12, "{:?}", result);
+
+    // Positions outside of the box are wrapped first
+    let mut shifted = particles;
+    shifted[1].position[0] -= 20.0;
+    assert_eq!(forces(&mut NeighbourList::new(config), &shifted), result);
+}
+
+#[test]
+fn lists_are_rebuilt_once_particles_move_half_the_skin() {
+    let mut particles = gas(20);
+    let config = NeighbourListConfig::new(LennardJones::new(1.0, 0.8, 2.0))
+        .unwrap()
+        .with_skin(0.4)
+        .unwrap();
+    let mut solver = NeighbourList::new(config);
+    forces(&mut solver, &particles);
+    assert_eq!(solver.rebuilds(), 1);
+
+    particles[7].position[0] += 0.19;
+    forces(&mut solver, &particles);
+    assert_eq!(solver.rebuilds(), 1);
+
+    particles[7].position[0] += 0.02;
+    let reused = forces(&mut solver, &particles);
+    assert_eq!(solver.rebuilds(), 2);
+    let direct = forces(
+        &mut DirectSum::with_force(config.force()).unwrap(),
+        &particles,
+    );
+    assert!(relative_rms_error(&reused, &direct) < 1e-12);
+}
+
+#[test]
+fn periodic_lennard_jones_fluid_conserves_energy() {
+    let force = LennardJones::new(1.0, 1.0, 2.5);
+    let domain = [0.0, 0.0, 16.0, 16.0];
+    let particles = generate_square_lattice(14, 16.0 / 14.0, 1.0);
+    let config = NeighbourListConfig::new(force)
+        .unwrap()
+        .with_periodic_domain(domain)
+        .unwrap();
+    let mut simulation =
+        Simulation::new(particles, 2e-3, NeighbourList::new(config), VelocityVerlet).unwrap();
+
+    let initial = Diagnostics::compute_in_periodic_domain(&simulation.particles, &force, domain);
+    for _ in 0..500 {
+        simulation.simulation_step();
+    }
+    let last = Diagnostics::compute_in_periodic_domain(&simulation.particles, &force, domain);
+    let error = (last.total_energy - initial.total_energy).abs() / initial.kinetic_energy;
+    assert!(error < 1e-3, "{}", error);
+    for d in 0..2 {
+        assert!(last.momentum[d].abs() < 1e-10, "{:?}", last.momentum);
+    }
+    for p in simulation.particles.iter() {
+        assert!(p.position.iter().all(|&x| (0.0..16.0).contains(&x)));
+    }
+}
+
+#[test]
+fn invalid_neighbour_lists_are_rejected() {
+    assert_eq!(
+        NeighbourListConfig::new(Gravity::default()).unwrap_err(),
+        ConfigError::InvalidNeighbourList(f64::INFINITY, f64::INFINITY)
+    );
+    let config = NeighbourListConfig::new(LennardJones::new(1.0, 1.0, 2.5)).unwrap();
+    assert_eq!(
+        config.with_skin(-0.1).unwrap_err(),
+        ConfigError::InvalidNeighbourList(2.5, -0.1)
+    );
+    assert_eq!(
+        config
+            .with_periodic_domain([0.0, 0.0, 5.0, 50.0])
+            .unwrap_err(),
+        ConfigError::InvalidNeighbourList(2.5, 0.25)
+    );
+    assert!(matches!(
+        NeighbourListConfig::new(LennardJones::new(1.0, -1.0, 2.5)),
+        Err(ConfigError::InvalidPairForce(_))
+    ));
+}
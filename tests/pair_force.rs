@@ -0,0 +1,180 @@
+mod common;
+
+use common::{forces, relative_rms_error, scattered_particles};
+use particle_sim::error::ConfigError;
+use particle_sim::forces::compute_gravity;
+use particle_sim::pair_force::{Coulomb, Coupling, Gravity, PowerLaw, Yukawa};
+use particle_sim::quadtree::{MultipoleOrder, TreeLimits};
+use particle_sim::simulation::{
+    BarnesHut, BarnesHutConfig, BarnesHutParallel, DirectSum, DirectSumParallel, LinearBarnesHut,
+};
+use particle_sim::{ForceSolver, PairForce, Particle, Softening};
+
+// Scattered particles with positive charges
+fn charged_particles(n: usize) -> Vec<Particle> {
+    scattered_particles(n, 0.0, 100.0)
+        .into_iter()
+        .enumerate()
+        .map(|(i, p)| p.with_charge(0.5 + (i % 3) as f64))
+        .collect()
+}
+
+// Checks f = u'/r and the jerk against finite differences
+fn check_consistency(force: &impl PairForce) {
+    let length = 0.6;
+    for i in 1..40 {
+        let r = 0.1 * i as f64;
+        let h = 1e-5;
+        let derivative =
+            (force.potential(r + h, length) - force.potential(r - h, length)) / (2.0 * h);
+        let f = force.force_factor(r * r, length) * r;
+        assert!(
+            (f - derivative).abs() < 1e-6 * f.abs().max(1e-3),
+            "{:?} at r = {}: {} and {}",
+            force,
+            r,
+            f,
+            derivative
+        );
+
+        let (f, f_derivative) = force.force_factor_and_derivative(r * r, length);
+        let numerical = (force.force_factor((r + h) * (r + h), length)
+            - force.force_factor((r - h) * (r - h), length))
+            / (2.0 * h)
+            / r;
+        assert!(
+            (f_derivative - numerical).abs() < 1e-6 * f_derivative.abs().max(1e-3),
+            "{:?} at r = {}: {} and {} (f = {})",
+            force,
+            r,
+            f_derivative,
+            numerical,
+            f
+        );
+    }
+}
+
+#[test]
+fn forces_derive_from_their_potentials() {
+    check_consistency(&Gravity::default());
+    check_consistency(&Coulomb::new(2.0).with_softening(Softening::Plummer { epsilon: 0.3 }));
+    check_consistency(&Yukawa::new(Coupling::Charge, 1.5, 2.0));
+    check_consistency(
+        &Yukawa::new(Coupling::Mass, -1.0, 0.8).with_softening(Softening::Spline { epsilon: 0.4 }),
+    );
+    for exponent in [1.0, 2.0, 3.5] {
+        check_consistency(
+            &PowerLaw::new(Coupling::Mass, 1.2, exponent)
+                .with_softening(Softening::Plummer { epsilon: 0.2 }),
+        );
+    }
+}
+
+#[test]
+fn default_gravity_matches_compute_gravity() {
+    let particles = charged_particles(2);
+    let gravity = Gravity::default();
+    let expected = compute_gravity(&particles[0], &particles[1], &Softening::default());
+    assert_eq!(gravity.force(&particles[0], &particles[1]), expected);
+}
+
+#[test]
+fn like_charges_repel_and_opposite_charges_attract() {
+    let coulomb = Coulomb::new(1.0);
+    let p1 = Particle::new([0.0, 0.0], [0.0, 0.0], 1.0).with_charge(1.0);
+    for (charge, sign) in [(2.0, -1.0), (-2.0, 1.0)] {
+        let p2 = Particle::new([3.0, 0.0], [0.0, 0.0], 1.0).with_charge(charge);
+        let force = coulomb.force(&p1, &p2);
+        assert!((force[0] - sign * 2.0 / 9.0).abs() < 1e-15, "{:?}", force);
+        assert_eq!(force[1], 0.0);
+        assert!(coulomb.potential_energy(&p1, &p2) * sign < 0.0);
+    }
+}
+
+#[test]
+fn screened_forces_fall_off_exponentially() {
+    let yukawa = Yukawa::new(Coupling::Charge, 1.0, 2.0);
+    let coulomb = Coulomb::new(1.0);
+    for r in [1.0f64, 5.0, 20.0] {
+        let ratio = yukawa.force_factor(r * r, 0.0) / coulomb.force_factor(r * r, 0.0);
+        let expected = (-r / 2.0).exp() * (1.0 + r / 2.0);
+        assert!(
+            (ratio - expected).abs() < 1e-12 * expected,
+            "{} and {}",
+            ratio,
+            expected
+        );
+    }
+}
+
+#[test]
+fn tree_solvers_match_the_direct_sum_for_any_force() {
+    let particles = charged_particles(400);
+    let limits = TreeLimits {
+        leaf_capacity: 4,
+        max_depth: TreeLimits::MAX_DEPTH,
+    };
+    let config = BarnesHutConfig::new(0.0)
+        .unwrap()
+        .with_limits(limits)
+        .unwrap();
+
+    fn check<F: PairForce + 'static>(config: BarnesHutConfig, force: F, particles: &[Particle]) {
+        let direct = forces(&mut DirectSum::with_force(force).unwrap(), particles);
+        let parallel = forces(
+            &mut DirectSumParallel::with_force(force).unwrap(),
+            particles,
+        );
+        assert!(relative_rms_error(&parallel, &direct) < 1e-12);
+
+        let config = config.with_force(force).unwrap();
+        for solver in [
+            &mut BarnesHut::new(config) as &mut dyn ForceSolver,
+            &mut BarnesHutParallel::new(config),
+            &mut LinearBarnesHut::new(config),
+        ] {
+            let error = relative_rms_error(&forces(solver, particles), &direct);
+            assert!(error < 1e-12, "{:?}: {}", force, error);
+        }
+
+        // Inverse-square forces also get the higher multipoles
+        if force.inverse_square_strength().is_some() {
+            let config = config
+                .with_multipole_order(MultipoleOrder::Octupole)
+                .with_theta(0.4)
+                .unwrap();
+            let error = relative_rms_error(
+                &forces(&mut LinearBarnesHut::new(config), particles),
+                &direct,
+            );
+            assert!(error < 2e-3, "{:?}: {}", force, error);
+        }
+    }
+
+    check(config, Coulomb::new(3.0), &particles);
+    check(config, Yukawa::new(Coupling::Charge, 1.0, 10.0), &particles);
+    check(config, PowerLaw::new(Coupling::Mass, 2.0, 1.0), &particles);
+    check(config, PowerLaw::new(Coupling::Mass, 2.0, 2.0), &particles);
+}
+
+#[test]
+fn invalid_forces_are_rejected() {
+    assert!(matches!(
+        DirectSum::with_force(Yukawa::new(Coupling::Charge, 1.0, 0.0)),
+        Err(ConfigError::InvalidPairForce(_))
+    ));
+    assert!(matches!(
+        DirectSum::with_force(
+            PowerLaw::new(Coupling::Mass, 1.0, 2.0)
+                .with_softening(Softening::Spline { epsilon: 1.0 })
+        ),
+        Err(ConfigError::InvalidPairForce(_))
+    ));
+    assert_eq!(
+        DirectSum::with_force(
+            Coulomb::new(1.0).with_softening(Softening::Plummer { epsilon: -1.0 })
+        )
+        .unwrap_err(),
+        ConfigError::InvalidSoftening(Softening::Plummer { epsilon: -1.0 })
+    );
+}
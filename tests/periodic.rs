@@ -0,0 +1,101 @@
+mod common;
+
+use common::{forces, relative_rms_error, scattered_particles};
+use particle_sim::integrator::LeapfrogKdk;
+use particle_sim::pm::MassAssignment;
+use particle_sim::simulation::{
+    BarnesHutConfig, BarnesHutParallel, LinearBarnesHut, PeriodicDirectSum, PmConfig, TreePm,
+    TreePmConfig,
+};
+use particle_sim::{Particle, Simulation};
+
+const DOMAIN: [f64; 4] = [0.0, 0.0, 400.0, 400.0];
+
+// Scattered particles, some of them outside of the box
+fn scattered_around_the_box(n: usize) -> Vec<Particle> {
+    scattered_particles(n, -50.0, 500.0)
+}
+
+#[test]
+fn particles_half_a_box_apart_feel_no_force() {
+    let particles = [
+        Particle::new([100.0, 100.0], [0.0, 0.0], 1.0),
+        Particle::new([300.0, 300.0], [0.0, 0.0], 2.0),
+    ];
+    let forces = forces(&mut PeriodicDirectSum::new(DOMAIN).unwrap(), &particles);
+    for f in forces {
+        assert!(f[0].abs() < 1e-12 && f[1].abs() < 1e-12, "{:?}", f);
+    }
+}
+
+#[test]
+fn forces_do_not_depend_on_the_origin() {
+    let particles = scattered_around_the_box(300);
+    let shifted: Vec<Particle> = particles
+        .iter()
+        .map(|p| {
+            let mut p = *p;
+            p.position = [p.position[0] + 123.4, p.position[1] - 56.7];
+            p
+        })
+        .collect();
+    let mut solver = PeriodicDirectSum::new(DOMAIN).unwrap();
+    let error = relative_rms_error(
+        &forces(&mut solver, &shifted),
+        &forces(&mut solver, &particles),
+    );
+    assert!(error < 1e-6, "{}", error);
+}
+
+#[test]
+fn ewald_sum_matches_periodic_tree_pm() {
+    let particles = scattered_around_the_box(500);
+    let direct = forces(&mut PeriodicDirectSum::new(DOMAIN).unwrap(), &particles);
+    let mesh = PmConfig::periodic(256, DOMAIN)
+        .unwrap()
+        .with_assignment(MassAssignment::Tsc);
+    let tree_pm = forces(
+        &mut TreePm::new(TreePmConfig::new(mesh, 0.0).unwrap()),
+        &particles,
+    );
+    let error = relative_rms_error(&tree_pm, &direct);
+    assert!(error < 3e-3, "{}", error);
+}
+
+#[test]
+fn periodic_trees_match_periodic_direct_sum() {
+    let particles = scattered_around_the_box(500);
+    let direct = forces(&mut PeriodicDirectSum::new(DOMAIN).unwrap(), &particles);
+    for theta in [0.0, 0.3] {
+        let config = BarnesHutConfig::new(theta)
+            .unwrap()
+            .with_periodic_domain(DOMAIN)
+            .unwrap();
+        let tolerance = if theta == 0.0 { 1e-12 } else { 1e-2 };
+        let parallel = forces(&mut BarnesHutParallel::new(config), &particles);
+        let linear = forces(&mut LinearBarnesHut::new(config), &particles);
+        assert!(relative_rms_error(&parallel, &direct) < tolerance);
+        assert!(relative_rms_error(&linear, &direct) < tolerance);
+    }
+}
+
+#[test]
+fn simulation_wraps_positions_into_the_box() {
+    let particles = vec![
+        Particle::new([395.0, 5.0], [2000.0, -2000.0], 1.0),
+        Particle::new([200.0, 200.0], [0.0, 0.0], 1.0),
+    ];
+    let config = BarnesHutConfig::new(0.5)
+        .unwrap()
+        .with_periodic_domain(DOMAIN)
+        .unwrap();
+    let mut simulation =
+        Simulation::new(particles, 0.01, LinearBarnesHut::new(config), LeapfrogKdk).unwrap();
+    for _ in 0..10 {
+        simulation.simulation_step();
+    }
+    for p in simulation.particles.iter() {
+        assert!((0.0..400.0).contains(&p.position[0]), "{:?}", p.position);
+        assert!((0.0..400.0).contains(&p.position[1]), "{:?}", p.position);
+    }
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use common::{forces, relative_rms_error};
+use particle_sim::error::ConfigError;
+use particle_sim::initial_conditions::generate_random_particles;
+use particle_sim::pm::{MassAssignment, Mesh, ISOLATED_MARGIN};
+use particle_sim::simulation::{DirectSum, ParticleMesh, PmConfig, TreePm, TreePmConfig};
+use particle_sim::{ForceSolver, Particle};
+
+// Particles on a perturbed lattice, many cells apart at the mesh sizes below
+fn lattice_particles() -> Vec<Particle> {
+    (0..400)
+        .map(|i| {
+            let (x, y) = ((i % 20) as f64, (i / 20) as f64);
+            Particle::new(
+                [
+                    x * 50.0 + (i * 7 % 13) as f64,
+                    y * 50.0 + (i * 5 % 11) as f64,
+                ],
+                [0.0, 0.0],
+                1.0 + (i % 7) as f64,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn isolated_mesh_converges_to_direct_sum() {
+    let particles = lattice_particles();
+    let direct = forces(&mut DirectSum::default(), &particles);
+    for assignment in [MassAssignment::Cic, MassAssignment::Tsc] {
+        let errors = [128, 256].map(|size| {
+            let config = PmConfig::isolated(size)
+                .unwrap()
+                .with_assignment(assignment);
+            relative_rms_error(&forces(&mut ParticleMesh::new(config), &particles), &direct)
+        });
+        assert!(errors[1] < errors[0], "{:?} {:?}", assignment, errors);
+        assert!(errors[1] < 5e-3, "{:?} {:?}", assignment, errors);
+    }
+}
+
+#[test]
+fn periodic_mesh_conserves_momentum() {
+    let particles = lattice_particles();
+    for assignment in [
+        MassAssignment::Ngp,
+        MassAssignment::Cic,
+        MassAssignment::Tsc,
+    ] {
+        let config = PmConfig::periodic(64, [0.0, 0.0, 1000.0, 1000.0])
+            .unwrap()
+            .with_assignment(assignment);
+        let forces = forces(&mut ParticleMesh::new(config), &particles);
+        let net = forces
+            .iter()
+            .fold([0.0, 0.0], |acc, f| [acc[0] + f[0], acc[1] + f[1]]);
+        let scale: f64 = forces.iter().map(|f| f[0].abs() + f[1].abs()).sum();
+        assert!(net[0].abs() + net[1].abs() < 1e-9 * scale, "{:?}", net);
+    }
+}
+
+#[test]
+fn particles_outside_a_fixed_isolated_domain_are_dropped() {
+    let particles = lattice_particles();
+    let config = PmConfig::isolated(64)
+        .unwrap()
+        .with_domain([0.0, 0.0, 500.0, 500.0])
+        .unwrap();
+    let mut solver = ParticleMesh::new(config);
+    let forces = forces(&mut solver, &particles);
+    let outside = particles
+        .iter()
+        .filter(|p| p.position[0] > 500.0 || p.position[1] > 500.0)
+        .count();
+    assert_eq!(solver.dropped_particles(), outside);
+    assert!(forces
+        .iter()
+        .zip(particles.iter())
+        .all(|(f, p)| (p.position[0] <= 500.0 && p.position[1] <= 500.0) || *f == [0.0, 0.0]));
+}
+
+#[test]
+fn tree_pm_matches_direct_sum_at_all_scales() {
+    let particles = generate_random_particles(2000);
+    let direct = forces(&mut DirectSum::default(), &particles);
+    for size in [64, 128] {
+        let config = TreePmConfig::new(PmConfig::isolated(size).unwrap(), 0.0).unwrap();
+        let error = relative_rms_error(&forces(&mut TreePm::new(config), &particles), &direct);
+        assert!(error < 2e-3, "{} {}", size, error);
+    }
+}
+
+#[test]
+fn periodic_tree_pm_does_not_depend_on_the_split() {
+    let domain = [0.0, 0.0, 1000.0, 1000.0];
+    let particles = generate_random_particles(2000);
+    let reference = TreePmConfig::new(PmConfig::periodic(256, domain).unwrap(), 0.0).unwrap();
+    let reference = forces(&mut TreePm::new(reference), &particles);
+    for (size, split) in [(32, 1.25), (64, 2.0)] {
+        let config = TreePmConfig::new(PmConfig::periodic(size, domain).unwrap(), 0.0)
+            .unwrap()
+            .with_split(split, 4.5)
+            .unwrap();
+        let error = relative_rms_error(&forces(&mut TreePm::new(config), &particles), &reference);
+        assert!(error < 5e-3, "{} {} {}", size, split, error);
+    }
+}
+
+#[test]
+fn periodic_cutoff_must_stay_within_half_the_box() {
+    let mesh = PmConfig::periodic(16, [0.0, 0.0, 100.0, 100.0]).unwrap();
+    let config = TreePmConfig::new(mesh, 0.5).unwrap();
+    assert_eq!(
+        config.with_split(2.0, 4.5).unwrap_err(),
+        ConfigError::InvalidForceSplit(2.0, 4.5)
+    );
+}
+
+#[test]
+fn isolated_domain_keeps_its_cells_while_the_particles_spread_slightly() {
+    let cell = |domain: [f64; 4]| (domain[2] - domain[0]) / 64.0;
+    let domain = Mesh::isolated_domain(64, [0.0, 0.0, 100.0, 100.0]);
+    let moved = Mesh::isolated_domain(64, [3.0, -2.0, 104.5, 99.5]);
+    assert!((cell(moved) - cell(domain)).abs() < 1e-12 * cell(domain));
+    assert!(domain[0] <= -(ISOLATED_MARGIN as f64) * cell(domain));
+    assert!(moved[2] >= 104.5 + ISOLATED_MARGIN as f64 * cell(moved));
+    let spread = Mesh::isolated_domain(64, [0.0, 0.0, 110.0, 110.0]);
+    assert!(cell(spread) > cell(domain));
+
+    // The Green's function kept from the first evaluation still applies
+    let particles = lattice_particles();
+    let shifted: Vec<Particle> = particles
+        .iter()
+        .map(|p| {
+            Particle::new(
+                [p.position[0] + 7.3, p.position[1] - 4.1],
+                p.velocity,
+                p.mass,
+            )
+        })
+        .collect();
+    let config = PmConfig::isolated(64).unwrap();
+    let mut solver = ParticleMesh::new(config);
+    forces(&mut solver, &particles);
+    let reused = forces(&mut solver, &shifted);
+    let fresh = forces(&mut ParticleMesh::new(config), &shifted);
+    assert!(relative_rms_error(&reused, &fresh) < 1e-10);
+}
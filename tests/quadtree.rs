@@ -0,0 +1,348 @@
+mod common;
+
+use common::{forces, relative_rms_error, scattered_particles};
+use particle_sim::quadtree::{MultipoleOrder, OpeningCriterion, TreeLimits};
+use particle_sim::simulation::{
+    BarnesHut, BarnesHutConfig, BarnesHutParallel, DirectSum, LinearBarnesHut,
+};
+use particle_sim::{ForceSolver, Particle, QuadTree, Softening};
+use proptest::prelude::*;
+
+// Particles on distinct points of a grid, so that no two of them coincide
+fn particles_strategy(max_len: usize) -> impl Strategy<Value = Vec<Particle>> {
+    prop::collection::hash_set((0u32..1000, 0u32..1000), 1..max_len).prop_flat_map(|points| {
+        let n = points.len();
+        (
+            Just(points.into_iter().collect::<Vec<_>>()),
+            prop::collection::vec(1.0f64..100.0, n),
+        )
+            .prop_map(|(points, masses)| {
+                points
+                    .into_iter()
+                    .zip(masses)
+                    .map(|((x, y), mass)| {
+                        Particle::new([x as f64 * 1.5, y as f64 * 0.9], [0.0, 0.0], mass)
+                    })
+                    .collect()
+            })
+    })
+}
+
+// Particles on a coarse grid, many of them sharing the same position
+fn coincident_particles_strategy(max_len: usize) -> impl Strategy<Value = Vec<Particle>> {
+    prop::collection::vec((0u32..8, 0u32..8, 1.0f64..100.0), 1..max_len).prop_map(|points| {
+        points
+            .into_iter()
+            .map(|(x, y, mass)| Particle::new([x as f64, y as f64], [0.0, 0.0], mass))
+            .collect()
+    })
+}
+
+fn limits_strategy() -> impl Strategy<Value = TreeLimits> {
+    (1usize..8, 0u32..=TreeLimits::MAX_DEPTH).prop_map(|(leaf_capacity, max_depth)| TreeLimits {
+        leaf_capacity,
+        max_depth,
+    })
+}
+
+fn serial_tree(particles: &[Particle], boundary: [f64; 4], limits: TreeLimits) -> QuadTree {
+    let mut root = QuadTree::with_limits(boundary, limits);
+    for particle in particles {
+        root.insert(*particle);
+    }
+    root.finalize();
+    root
+}
+
+fn merged_tree(
+    particles: &[Particle],
+    boundary: [f64; 4],
+    limits: TreeLimits,
+    chunk_size: usize,
+) -> QuadTree {
+    let mut root = QuadTree::with_limits(boundary, limits);
+    for chunk in particles.chunks(chunk_size) {
+        let mut tree = QuadTree::with_limits(boundary, limits);
+        for particle in chunk {
+            tree.insert(*particle);
+        }
+        root.merge(&mut tree);
+    }
+    root.finalize();
+    root
+}
+
+fn close(a: f64, b: f64, scale: f64) -> bool {
+    (a - b).abs() <= 1e-9 * scale
+}
+
+// Same shape, same leaves and same mass and centre of mass in every node
+fn assert_same_tree(a: &QuadTree, b: &QuadTree, total_mass: f64, extent: f64) {
+    assert_eq!(a.boundary, b.boundary);
+    assert!(
+        close(a.mass, b.mass, total_mass),
+        "{} != {}",
+        a.mass,
+        b.mass
+    );
+    if a.mass > 0.0 {
+        for d in 0..2 {
+            assert!(close(a.center_of_mass[d], b.center_of_mass[d], extent));
+        }
+    }
+    // Leaves hold the same particles, possibly in a different order
+    let leaf_positions = |tree: &QuadTree| {
+        let mut positions: Vec<[f64; 2]> = tree.particles.iter().map(|p| p.position).collect();
+        positions.sort_by(|p, q| p.partial_cmp(q).unwrap());
+        positions
+    };
+    assert_eq!(leaf_positions(a), leaf_positions(b));
+    match (&a.children, &b.children) {
+        (Some(a_children), Some(b_children)) => {
+            for (a_child, b_child) in a_children.iter().zip(b_children.iter()) {
+                assert_same_tree(a_child, b_child, total_mass, extent);
+            }
+        }
+        (None, None) => {}
+        _ => panic!("trees differ in shape at {:?}", a.boundary),
+    }
+}
+
+proptest! {
+    #[test]
+    fn merged_tree_matches_serial_build(
+        particles in particles_strategy(300),
+        limits in limits_strategy(),
+        chunk_size in 1usize..50,
+    ) {
+        let boundary = QuadTree::bounding_square(&particles);
+        let serial = serial_tree(&particles, boundary, limits);
+        let merged = merged_tree(&particles, boundary, limits, chunk_size);
+
+        let total_mass: f64 = particles.iter().map(|p| p.mass).sum();
+        let extent = boundary[2] - boundary[0];
+        prop_assert!(close(serial.mass, total_mass, total_mass));
+        assert_same_tree(&serial, &merged, total_mass, extent);
+    }
+
+    #[test]
+    fn parallel_forces_match_serial(
+        particles in particles_strategy(400),
+        theta in 0.0f64..1.0,
+    ) {
+        let config = BarnesHutConfig::new(theta).unwrap();
+        let serial = forces(&mut BarnesHut::new(config), &particles);
+        let parallel = forces(&mut BarnesHutParallel::new(config), &particles);
+        let linear = forces(&mut LinearBarnesHut::new(config), &particles);
+        prop_assert!(relative_rms_error(&parallel, &serial) < 1e-9);
+        prop_assert!(relative_rms_error(&linear, &serial) < 1e-9);
+    }
+
+    #[test]
+    fn forces_match_direct_sum_without_approximation(particles in particles_strategy(200)) {
+        let config = BarnesHutConfig::new(0.0).unwrap();
+        let direct = forces(&mut DirectSum::default(), &particles);
+        let serial = forces(&mut BarnesHut::new(config), &particles);
+        let parallel = forces(&mut BarnesHutParallel::new(config), &particles);
+        prop_assert!(relative_rms_error(&serial, &direct) < 1e-12);
+        prop_assert!(relative_rms_error(&parallel, &direct) < 1e-12);
+    }
+
+    #[test]
+    fn forces_are_close_to_direct_sum(particles in particles_strategy(400)) {
+        let config = BarnesHutConfig::new(0.3).unwrap();
+        let direct = forces(&mut DirectSum::default(), &particles);
+        let parallel = forces(&mut BarnesHutParallel::new(config), &particles);
+        prop_assert!(relative_rms_error(&parallel, &direct) < 1e-2);
+    }
+
+    #[test]
+    fn coincident_particles_stay_in_bounded_leaves(
+        particles in coincident_particles_strategy(300),
+        limits in limits_strategy(),
+    ) {
+        let boundary = QuadTree::bounding_square(&particles);
+        let tree = serial_tree(&particles, boundary, limits);
+        prop_assert!(max_depth(&tree) <= limits.max_depth);
+
+        let config = BarnesHutConfig::new(0.0).unwrap().with_limits(limits).unwrap();
+        let direct = forces(&mut DirectSum::default(), &particles);
+        let serial = forces(&mut BarnesHut::new(config), &particles);
+        let parallel = forces(&mut BarnesHutParallel::new(config), &particles);
+        let linear = forces(&mut LinearBarnesHut::new(config), &particles);
+        prop_assert!(relative_rms_error(&serial, &direct) < 1e-12);
+        prop_assert!(relative_rms_error(&parallel, &direct) < 1e-12);
+        prop_assert!(relative_rms_error(&linear, &direct) < 1e-12);
+    }
+}
+
+fn max_depth(tree: &QuadTree) -> u32 {
+    match &tree.children {
+        Some(children) => children.iter().map(max_depth).max().unwrap(),
+        None => tree.depth,
+    }
+}
+
+// Particles over [0, 100]^2, followed by a few escaping from it
+fn with_escapers() -> Vec<Particle> {
+    let mut particles = scattered_particles(100, 0.0, 100.0);
+    particles.push(Particle::new([150.0, 50.0], [0.0, 0.0], 1.0));
+    particles.push(Particle::new([-10.0, -10.0], [0.0, 0.0], 2.0));
+    particles.push(Particle::new([50.0, 1e4], [0.0, 0.0], 3.0));
+    particles
+}
+
+#[test]
+fn automatic_domain_keeps_escaping_particles() {
+    let particles = with_escapers();
+    let config = BarnesHutConfig::new(0.0).unwrap();
+    let direct = forces(&mut DirectSum::default(), &particles);
+    let mut serial = BarnesHut::new(config);
+    let mut parallel = BarnesHutParallel::new(config);
+    let mut linear = LinearBarnesHut::new(config);
+    for solver in [
+        &mut serial as &mut dyn ForceSolver,
+        &mut parallel,
+        &mut linear,
+    ] {
+        assert!(relative_rms_error(&forces(solver, &particles), &direct) < 1e-12);
+        assert_eq!(solver.dropped_particles(), 0);
+    }
+}
+
+#[test]
+fn fixed_domain_drops_and_counts_particles_outside() {
+    let particles = with_escapers();
+    let config = BarnesHutConfig::new(0.0)
+        .unwrap()
+        .with_domain([0.0, 0.0, 100.0, 100.0])
+        .unwrap();
+    let inside = &particles[..100];
+    let direct = forces(&mut DirectSum::default(), inside);
+    let mut serial = BarnesHut::new(config);
+    let mut parallel = BarnesHutParallel::new(config);
+    let mut linear = LinearBarnesHut::new(config);
+    for solver in [
+        &mut serial as &mut dyn ForceSolver,
+        &mut parallel,
+        &mut linear,
+    ] {
+        let f = forces(solver, &particles);
+        assert!(relative_rms_error(&f[..100], &direct) < 1e-12);
+        assert_eq!(solver.dropped_particles(), 3);
+    }
+    assert!(BarnesHutConfig::new(0.5)
+        .unwrap()
+        .with_domain([0.0, 0.0, -1.0, 100.0])
+        .is_err());
+}
+
+fn tree_error(config: BarnesHutConfig, particles: &[Particle]) -> f64 {
+    let direct = forces(&mut DirectSum::default(), particles);
+    relative_rms_error(&forces(&mut BarnesHut::new(config), particles), &direct)
+}
+
+#[test]
+fn higher_multipoles_reduce_the_error_at_fixed_theta() {
+    let particles = scattered_particles(400, 0.0, 100.0);
+    for theta in [0.4, 0.7, 1.0] {
+        let config = BarnesHutConfig::new(theta).unwrap();
+        let monopole = tree_error(config, &particles);
+        let quadrupole = tree_error(
+            config.with_multipole_order(MultipoleOrder::Quadrupole),
+            &particles,
+        );
+        let octupole = tree_error(
+            config.with_multipole_order(MultipoleOrder::Octupole),
+            &particles,
+        );
+        assert!(quadrupole < 0.5 * monopole, "theta {}", theta);
+        assert!(octupole < 0.8 * quadrupole, "theta {}", theta);
+    }
+}
+
+// Largest error on a single particle relative to its own force
+fn max_relative_error(forces: &[[f64; 2]], reference: &[[f64; 2]]) -> f64 {
+    forces
+        .iter()
+        .zip(reference.iter())
+        .map(|(f, r)| ((f[0] - r[0]).hypot(f[1] - r[1])) / r[0].hypot(r[1]))
+        .fold(0.0, f64::max)
+}
+
+#[test]
+fn criteria_meet_their_error_bounds() {
+    let particles = scattered_particles(400, 0.0, 100.0);
+    let direct = forces(&mut DirectSum::default(), &particles);
+    for theta in [0.2, 0.4, 0.8] {
+        for criterion in [OpeningCriterion::Geometric, OpeningCriterion::SalmonWarren] {
+            let config = BarnesHutConfig::new(theta)
+                .unwrap()
+                .with_opening_criterion(criterion)
+                .unwrap();
+            let error = tree_error(config, &particles);
+            assert!(error < 0.25 * theta * theta, "{:?} {}", criterion, theta);
+        }
+    }
+    // The error of each accepted node is below alpha times the previous
+    // acceleration of the particle, known after a first evaluation
+    for alpha in [1e-4, 1e-3, 1e-2] {
+        let criterion = OpeningCriterion::Relative { alpha };
+        let config = BarnesHutConfig::new(0.5)
+            .unwrap()
+            .with_opening_criterion(criterion)
+            .unwrap();
+        let mut solver = BarnesHut::new(config);
+        forces(&mut solver, &particles);
+        let f = forces(&mut solver, &particles);
+        assert!(
+            relative_rms_error(&f, &direct) < 3.0 * alpha,
+            "alpha {}",
+            alpha
+        );
+        assert!(
+            max_relative_error(&f, &direct) < 10.0 * alpha,
+            "alpha {}",
+            alpha
+        );
+    }
+}
+
+// Light particle next to a neighbour, in a cell whose centre of mass is pulled
+// into the far corner by a heavy cluster
+fn far_corner_cluster() -> Vec<Particle> {
+    let mut particles = vec![
+        Particle::new([1.0, 1.0], [0.0, 0.0], 1.0),
+        Particle::new([2.0, 1.0], [0.0, 0.0], 1.0),
+    ];
+    for k in 0..10 {
+        let offset = 0.1 * k as f64;
+        particles.push(Particle::new([99.0 - offset, 99.0], [0.0, 0.0], 100.0));
+    }
+    particles
+}
+
+#[test]
+fn salmon_warren_and_containing_guard_catch_off_centre_mass() {
+    let particles = far_corner_cluster();
+    let direct = forces(&mut DirectSum::new(Softening::None).unwrap(), &particles);
+    let config = BarnesHutConfig::new(1.0)
+        .unwrap()
+        .with_softening(Softening::None)
+        .unwrap()
+        .with_domain([0.0, 0.0, 100.0, 100.0])
+        .unwrap();
+    let error = |config: BarnesHutConfig| {
+        let f = forces(&mut BarnesHut::new(config), &particles);
+        max_relative_error(&f[..1], &direct[..1])
+    };
+
+    // The whole domain is taken for a point mass by the geometric criterion
+    assert!(error(config.with_open_containing(false)) > 0.5);
+    assert!(error(config.with_open_containing(true)) < 1e-2);
+    let salmon_warren = config
+        .with_open_containing(false)
+        .with_opening_criterion(OpeningCriterion::SalmonWarren)
+        .unwrap();
+    assert!(error(salmon_warren) < 1e-2);
+}
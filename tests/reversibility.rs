@@ -0,0 +1,76 @@
+mod common;
+
+use common::scattered_particles;
+use particle_sim::integrator::{LeapfrogKdk, RungeKutta4};
+use particle_sim::pair_force::Gravity;
+use particle_sim::reversibility::{time_reversal_test, ReversalMethod};
+use particle_sim::simulation::DirectSum;
+use particle_sim::{Diagnostics, Integrator, Particle, Simulation, Softening};
+
+fn cluster(integrator: impl Integrator + 'static) -> Simulation {
+    let particles = scattered_particles(40, 0.0, 20.0);
+    Simulation::new(particles, 1e-2, DirectSum::default(), integrator).unwrap()
+}
+
+#[test]
+fn leapfrog_returns_to_the_start_up_to_round_off() {
+    for method in [
+        ReversalMethod::FlipVelocities,
+        ReversalMethod::NegateTimestep,
+    ] {
+        let mut simulation = cluster(LeapfrogKdk);
+        let report = time_reversal_test(&mut simulation, 200, method);
+        assert_eq!(report.steps, 200);
+        assert!(report.max_position_error < 1e-10, "{:?}", report);
+        assert!(report.max_velocity_error < 1e-10, "{:?}", report);
+        assert!(report.energy_error < 1e-12, "{:?}", report);
+    }
+}
+
+#[test]
+fn runge_kutta_does_not_return_to_the_start() {
+    for method in [
+        ReversalMethod::FlipVelocities,
+        ReversalMethod::NegateTimestep,
+    ] {
+        let mut simulation = cluster(RungeKutta4);
+        let report = time_reversal_test(&mut simulation, 200, method);
+        assert!(report.max_position_error > 1e-3, "{:?}", report);
+        assert!(report.energy_error > 1e-6, "{:?}", report);
+    }
+}
+
+#[test]
+fn reversal_restores_the_time_and_time_step() {
+    let mut simulation = cluster(LeapfrogKdk);
+    simulation.simulation_step();
+    let (time, dt) = (simulation.time, simulation.dt);
+    time_reversal_test(&mut simulation, 10, ReversalMethod::NegateTimestep);
+    assert_eq!(simulation.time, time);
+    assert_eq!(simulation.dt, dt);
+}
+
+#[test]
+fn energies_use_the_forces_of_the_solver() {
+    let gravity = Gravity {
+        constant: 1.0,
+        softening: Softening::Plummer { epsilon: 0.5 },
+    };
+    let solver = DirectSum::with_force(gravity).unwrap();
+    let particles = scattered_particles(40, 0.0, 20.0);
+    let mut simulation = Simulation::new(particles, 1e-3, solver, LeapfrogKdk).unwrap();
+    let expected = Diagnostics::compute_with_force(&simulation.particles, &gravity);
+    assert_eq!(simulation.diagnostics().total_energy, expected.total_energy);
+    let report = time_reversal_test(&mut simulation, 100, ReversalMethod::FlipVelocities);
+    assert!(report.max_position_error < 1e-10, "{:?}", report);
+    assert!(report.energy_error < 1e-12, "{:?}", report);
+}
+
+#[test]
+fn energy_error_is_absolute_for_a_vanishing_reference_energy() {
+    let at_rest = Diagnostics::compute(&[Particle::new([0.0, 0.0], [0.0, 0.0], 2.0)]);
+    let moving = Diagnostics::compute(&[Particle::new([0.0, 0.0], [3.0, 0.0], 2.0)]);
+    assert_eq!(at_rest.total_energy, 0.0);
+    assert_eq!(moving.energy_error(&at_rest), 9.0);
+    assert_eq!(at_rest.energy_error(&moving), 1.0);
+}
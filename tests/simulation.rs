@@ -0,0 +1,50 @@
+use particle_sim::integrator::Euler;
+use particle_sim::simulation::{BarnesHut, BarnesHutConfig};
+use particle_sim::{ConfigError, ForceSolver, Particle, Simulation};
+
+// Uniform field pulling every particle along x
+struct UniformField(f64);
+
+impl ForceSolver for UniformField {
+    fn compute_forces(&mut self, particles: &[Particle], forces: &mut [[f64; 2]]) {
+        for (force, p) in forces.iter_mut().zip(particles.iter()) {
+            *force = [self.0 * p.mass, 0.0];
+        }
+    }
+}
+
+fn particles() -> Vec<Particle> {
+    vec![
+        Particle::new([0.0, 0.0], [0.0, 1.0], 1.0),
+        Particle::new([10.0, 0.0], [0.0, -1.0], 3.0),
+    ]
+}
+
+#[test]
+fn invalid_settings_are_rejected_at_construction() {
+    for dt in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+        let result = Simulation::new(particles(), dt, UniformField(1.0), Euler);
+        assert!(matches!(result, Err(ConfigError::InvalidTimestep(_))));
+    }
+    for theta in [-0.5, f64::INFINITY] {
+        assert_eq!(
+            BarnesHutConfig::new(theta).err(),
+            Some(ConfigError::InvalidTheta(theta))
+        );
+    }
+    assert!(BarnesHutConfig::new(f64::NAN).is_err());
+    let config = BarnesHutConfig::new(0.7).unwrap();
+    assert_eq!(config.theta(), 0.7);
+    assert!(Simulation::new(particles(), 0.1, BarnesHut::new(config), Euler).is_ok());
+}
+
+#[test]
+fn simulation_steps_with_a_third_party_solver() {
+    let mut simulation = Simulation::new(particles(), 0.5, UniformField(2.0), Euler).unwrap();
+    simulation.simulation_step();
+    simulation.simulation_step();
+    for (p, v_y) in simulation.particles.iter().zip([1.0, -1.0]) {
+        assert!((p.velocity[0] - 2.0).abs() < 1e-12);
+        assert_eq!(p.velocity[1], v_y);
+    }
+}
@@ -0,0 +1,257 @@
+mod common;
+
+use common::{forces, relative_rms_error, CountingSolver};
+use particle_sim::error::ConfigError;
+use particle_sim::forces::{compute_gravity, minimum_image};
+use particle_sim::integrator::RungeKutta4;
+use particle_sim::pair_force::Gravity;
+use particle_sim::quadtree::TreeLimits;
+use particle_sim::simulation::{
+    BarnesHut, BarnesHutConfig, DirectSum, FastMultipole, FmmConfig, LinearBarnesHut,
+};
+use particle_sim::softening::adapt_softening_lengths;
+use particle_sim::timestep::{TimestepController, TimestepCriterion};
+use particle_sim::{ForceSolver, PairForce, Particle, Simulation, Softening};
+use std::sync::atomic::Ordering;
+
+const KERNELS: [Softening; 4] = [
+    Softening::None,
+    Softening::Plummer { epsilon: 0.7 },
+    Softening::Spline { epsilon: 0.7 },
+    Softening::Adaptive {
+        neighbours: 4,
+        eta: 0.5,
+    },
+];
+
+// Clustered particles, closer to each other than the softening lengths
+fn clustered_particles(n: usize) -> Vec<Particle> {
+    (0..n)
+        .map(|i| {
+            let r = 20.0 * (i as f64 / n as f64).powi(2);
+            let angle = i as f64 * 2.399_963;
+            Particle::new(
+                [r * angle.cos(), r * angle.sin()],
+                [0.0, 0.0],
+                1.0 + (i % 3) as f64,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn forces_are_minus_the_gradient_of_the_potential() {
+    for softening in KERNELS {
+        let length = 0.7;
+        for i in 1..60 {
+            let r = 0.05 * i as f64;
+            let h = 1e-5;
+            let derivative = (softening.potential(r + h, length)
+                - softening.potential(r - h, length))
+                / (2.0 * h);
+            let force = softening.force_factor(r * r, length) * r;
+            assert!(
+                (force + derivative).abs() < 1e-6 * force,
+                "{:?} at r = {}: {} and {}",
+                softening,
+                r,
+                force,
+                -derivative
+            );
+        }
+    }
+}
+
+#[test]
+fn spline_is_continuous_and_newtonian_beyond_its_support() {
+    let softening = Softening::Spline { epsilon: 1.0 };
+    let h = 2.8;
+    for u in [0.5, 1.0] {
+        let (below, above) = ((u * h - 1e-9_f64).powi(2), (u * h + 1e-9_f64).powi(2));
+        let (f_below, f_above) = (
+            softening.force_factor(below, 1.0),
+            softening.force_factor(above, 1.0),
+        );
+        assert!(
+            (f_below - f_above).abs() < 1e-7,
+            "{} and {}",
+            f_below,
+            f_above
+        );
+    }
+    for r in [h, 3.0, 10.0] {
+        assert!((softening.force_factor(r * r, 1.0) - 1.0 / (r * r * r)).abs() < 1e-12);
+        assert!((softening.potential(r, 1.0) - 1.0 / r).abs() < 1e-12);
+    }
+    // Same central potential as a Plummer sphere of the same length
+    assert!((softening.potential(0.0, 1.0) - 1.0).abs() < 1e-12);
+}
+
+#[test]
+fn jerk_is_the_time_derivative_of_the_force() {
+    for softening in KERNELS {
+        let mut p1 = Particle::new([0.1, -0.2], [0.3, 0.1], 2.0);
+        let mut p2 = Particle::new([0.9, 0.4], [-0.2, 0.5], 3.0);
+        p1.softening = 0.6;
+        p2.softening = 0.8;
+        let (_, jerk) = Gravity::with_softening(softening).force_and_jerk(&p1, &p2);
+
+        let dt = 1e-6;
+        let force_at = |t: f64| {
+            let (mut a, mut b) = (p1, p2);
+            for d in 0..2 {
+                a.position[d] += a.velocity[d] * t;
+                b.position[d] += b.velocity[d] * t;
+            }
+            compute_gravity(&a, &b, &softening)
+        };
+        let (after, before) = (force_at(dt), force_at(-dt));
+        for d in 0..2 {
+            let numerical = (after[d] - before[d]) / (2.0 * dt);
+            assert!(
+                (jerk[d] - numerical).abs() < 1e-5 * jerk[d].abs().max(1.0),
+                "{:?}: {:?} and {}",
+                softening,
+                jerk,
+                numerical
+            );
+        }
+    }
+}
+
+#[test]
+fn adaptive_lengths_follow_the_nearest_neighbours() {
+    let mut particles = clustered_particles(400);
+    let (neighbours, eta) = (6, 0.4);
+    adapt_softening_lengths(&mut particles, neighbours, eta, None);
+    for (i, p) in particles.iter().enumerate() {
+        let mut distances: Vec<f64> = particles
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .map(|(_, other)| {
+                let d = [
+                    other.position[0] - p.position[0],
+                    other.position[1] - p.position[1],
+                ];
+                (d[0] * d[0] + d[1] * d[1]).sqrt()
+            })
+            .collect();
+        distances.sort_by(f64::total_cmp);
+        assert!(
+            (p.softening - eta * distances[neighbours - 1]).abs() < 1e-12,
+            "{}: {} and {}",
+            i,
+            p.softening,
+            eta * distances[neighbours - 1]
+        );
+    }
+}
+
+#[test]
+fn adaptive_lengths_count_periodic_images() {
+    // Pairs straddling the edges of the box, each far from the other pairs
+    let domain = [0.0, 0.0, 10.0, 10.0];
+    let mut particles = vec![
+        Particle::new([0.2, 5.0], [0.0, 0.0], 1.0),
+        Particle::new([9.9, 5.0], [0.0, 0.0], 1.0),
+        Particle::new([5.0, 9.7], [0.0, 0.0], 1.0),
+        Particle::new([5.0, 0.1], [0.0, 0.0], 1.0),
+        Particle::new([9.8, 9.9], [0.0, 0.0], 1.0),
+        Particle::new([0.3, 0.1], [0.0, 0.0], 1.0),
+    ];
+    adapt_softening_lengths(&mut particles, 1, 0.5, Some(domain));
+    for (i, p) in particles.iter().enumerate() {
+        let nearest = particles
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .map(|(_, other)| {
+                let d = [
+                    other.position[0] - p.position[0],
+                    other.position[1] - p.position[1],
+                ];
+                let d = minimum_image(d, Some([10.0, 10.0]));
+                (d[0] * d[0] + d[1] * d[1]).sqrt()
+            })
+            .fold(f64::INFINITY, f64::min);
+        assert!(nearest < 0.6);
+        assert!((p.softening - 0.5 * nearest).abs() < 1e-12, "{}", i);
+    }
+}
+
+#[test]
+fn adaptive_lengths_do_not_cost_extra_evaluations_with_a_controller() {
+    let softening = Softening::Adaptive {
+        neighbours: 4,
+        eta: 0.5,
+    };
+    let (solver, evaluations) = CountingSolver::new(DirectSum::new(softening).unwrap());
+    let particles = clustered_particles(50);
+    let mut simulation = Simulation::new(particles, 1e-3, solver, RungeKutta4).unwrap();
+    let criterion = TimestepCriterion::Acceleration {
+        eta: 0.1,
+        softening: 0.1,
+    };
+    simulation.set_timestep_controller(Some(
+        TimestepController::new(vec![criterion], 1e-6, 1e-2).unwrap(),
+    ));
+    for _ in 0..5 {
+        evaluations.store(0, Ordering::Relaxed);
+        simulation.simulation_step();
+        // One for the controller, which the first stage reuses, and three more
+        assert_eq!(evaluations.load(Ordering::Relaxed), 4);
+    }
+}
+
+#[test]
+fn tree_solvers_soften_like_the_direct_sum() {
+    let mut particles = clustered_particles(500);
+    adapt_softening_lengths(&mut particles, 4, 0.5, None);
+    let limits = TreeLimits {
+        leaf_capacity: 4,
+        max_depth: TreeLimits::MAX_DEPTH,
+    };
+    for softening in KERNELS {
+        let direct = forces(&mut DirectSum::new(softening).unwrap(), &particles);
+        let config = BarnesHutConfig::new(0.0)
+            .unwrap()
+            .with_limits(limits)
+            .unwrap()
+            .with_softening(softening)
+            .unwrap();
+        for solver in [
+            &mut BarnesHut::new(config) as &mut dyn ForceSolver,
+            &mut LinearBarnesHut::new(config),
+        ] {
+            let error = relative_rms_error(&forces(solver, &particles), &direct);
+            assert!(error < 1e-12, "{:?}: {}", softening, error);
+        }
+
+        let fmm = FmmConfig::new(12, 0.5)
+            .unwrap()
+            .with_softening(softening)
+            .unwrap();
+        let error = relative_rms_error(&forces(&mut FastMultipole::new(fmm), &particles), &direct);
+        assert!(error < 1e-5, "{:?}: {}", softening, error);
+    }
+}
+
+#[test]
+fn invalid_softening_is_rejected() {
+    for softening in [
+        Softening::Plummer { epsilon: 0.0 },
+        Softening::Spline {
+            epsilon: f64::INFINITY,
+        },
+        Softening::Adaptive {
+            neighbours: 0,
+            eta: 1.0,
+        },
+    ] {
+        assert_eq!(
+            DirectSum::new(softening).unwrap_err(),
+            ConfigError::InvalidSoftening(softening)
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use particle_sim::integrator::{ForceEvaluator, LeapfrogKdk, StepReport};
+use particle_sim::simulation::DirectSum;
+use particle_sim::timestep::{TimestepController, TimestepCriterion};
+use particle_sim::{ForceSolver, Particle, Simulation};
+
+// Two bodies ten units apart, at most 5 in speed
+fn pair() -> Vec<Particle> {
+    vec![
+        Particle::new([0.0, 0.0], [3.0, 4.0], 1.0),
+        Particle::new([6.0, 8.0], [-1.0, 0.5], 2.0),
+    ]
+}
+
+fn next_dt(criteria: Vec<TimestepCriterion>, dt_min: f64, dt_max: f64) -> f64 {
+    let controller = TimestepController::new(criteria, dt_min, dt_max).unwrap();
+    let particles = pair();
+    let mut solver = DirectSum::default();
+    let mut total_forces = vec![[0.0, 0.0]; particles.len()];
+    let mut up_to_date = false;
+    let mut evaluator = ForceEvaluator::new(&mut solver, &mut total_forces, &mut up_to_date);
+    controller.next_dt(&particles, &mut evaluator, 0.1, &StepReport::default())
+}
+
+fn assert_close(a: f64, b: f64) {
+    assert!((a - b).abs() < 1e-12 * b, "{} and {}", a, b);
+}
+
+#[test]
+fn acceleration_criterion_follows_the_largest_acceleration() {
+    let particles = pair();
+    let mut forces = vec![[0.0, 0.0]; particles.len()];
+    DirectSum::default().compute_forces(&particles, &mut forces);
+    let max_acc = forces
+        .iter()
+        .zip(particles.iter())
+        .map(|(f, p)| (f[0] * f[0] + f[1] * f[1]).sqrt() / p.mass)
+        .fold(0.0, f64::max);
+    let criterion = TimestepCriterion::Acceleration {
+        eta: 0.2,
+        softening: 0.5,
+    };
+    assert_close(
+        next_dt(vec![criterion], 1e-9, 1e3),
+        0.2 * (0.5 / max_acc).sqrt(),
+    );
+}
+
+#[test]
+fn velocity_criterion_follows_the_fastest_particle() {
+    let criterion = TimestepCriterion::Velocity {
+        courant: 0.3,
+        length: 2.0,
+    };
+    assert_close(next_dt(vec![criterion], 1e-9, 1e3), 0.3 * 2.0 / 5.0);
+}
+
+#[test]
+fn the_smallest_candidate_is_clamped_to_the_bounds() {
+    let velocity = |courant| TimestepCriterion::Velocity {
+        courant,
+        length: 1.0,
+    };
+    // Candidates of 0.02 and 0.2
+    assert_close(next_dt(vec![velocity(0.1), velocity(1.0)], 1e-9, 1e3), 0.02);
+    assert_eq!(next_dt(vec![velocity(1.0)], 1e-9, 0.05), 0.05);
+    assert_eq!(next_dt(vec![velocity(0.1)], 0.1, 0.5), 0.1);
+    // Without any criterion, the largest step is taken
+    assert_eq!(next_dt(vec![], 1e-9, 0.5), 0.5);
+}
+
+#[test]
+fn simulation_reports_the_time_step_it_took() {
+    let mut simulation = Simulation::new(pair(), 1.0, DirectSum::default(), LeapfrogKdk).unwrap();
+    let criterion = TimestepCriterion::Velocity {
+        courant: 0.01,
+        length: 1.0,
+    };
+    simulation.set_timestep_controller(Some(
+        TimestepController::new(vec![criterion], 1e-9, 1.0).unwrap(),
+    ));
+    for _ in 0..10 {
+        let (time, start) = (simulation.time, simulation.particles.clone());
+        let max_vel = start
+            .iter()
+            .map(|p| (p.velocity[0].powi(2) + p.velocity[1].powi(2)).sqrt())
+            .fold(0.0, f64::max);
+        simulation.simulation_step();
+        // The viewer shows `dt` as the step just taken
+        assert_close(simulation.dt, 0.01 / max_vel);
+        assert_close(simulation.time - time, simulation.dt);
+        let moved = simulation.particles[0].position[0] - start[0].position[0];
+        assert!((moved / simulation.dt - start[0].velocity[0]).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,186 @@
+use particle_sim::bonds::Bond;
+use particle_sim::forces::GRAVIT_CONST;
+use particle_sim::integrator::{Composition, RungeKutta4};
+use particle_sim::pair_force::{Coulomb, Gravity};
+use particle_sim::simulation::{DirectSum, PeriodicDirectSum};
+use particle_sim::wisdom_holman::{kepler_drift, WhCoordinates, WisdomHolman};
+use particle_sim::{ConfigError, Integrator, Particle, Simulation, Softening};
+use std::f64::consts::PI;
+
+const PLANET_MASS: f64 = 1e-3;
+const MU: f64 = GRAVIT_CONST * (1.0 + PLANET_MASS);
+
+fn distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt()
+}
+
+fn assert_close(a: ([f64; 2], [f64; 2]), b: ([f64; 2], [f64; 2]), tolerance: f64) {
+    assert!(
+        distance(a.0, b.0) < tolerance && distance(a.1, b.1) < tolerance,
+        "{:?} != {:?}",
+        a,
+        b
+    );
+}
+
+#[test]
+fn elliptic_drift_returns_to_the_start_after_a_period() {
+    // Perihelion of an orbit of semi-major axis 1 and eccentricity 0.5
+    let start = ([0.5, 0.0], [0.0, (3.0 * MU).sqrt()]);
+    let period = 2.0 * PI / MU.sqrt();
+    assert_close(kepler_drift(start.0, start.1, MU, period), start, 1e-10);
+    assert_close(kepler_drift(start.0, start.1, MU, -period), start, 1e-10);
+}
+
+#[test]
+fn consecutive_drifts_add_up() {
+    let escape = (2.0 * MU).sqrt();
+    for speed in [0.7 * escape, escape, 1.5 * escape] {
+        let start = ([0.3, 0.8], [-speed * 0.6, speed * 0.8 * 0.5]);
+        for (a, b) in [(0.13, 0.41), (0.9, -0.25), (2.0, 1.5)] {
+            let (r, v) = kepler_drift(start.0, start.1, MU, a);
+            let split = kepler_drift(r, v, MU, b);
+            assert_close(split, kepler_drift(start.0, start.1, MU, a + b), 1e-9);
+        }
+    }
+}
+
+#[test]
+fn drift_conserves_energy_and_angular_momentum() {
+    let escape = (2.0 * MU).sqrt();
+    for speed in [0.5 * escape, 2.0 * escape] {
+        let (r0, v0) = ([1.0, 0.2], [0.3 * speed, speed]);
+        let energy = |r: [f64; 2], v: [f64; 2]| {
+            0.5 * (v[0] * v[0] + v[1] * v[1]) - MU / (r[0] * r[0] + r[1] * r[1]).sqrt()
+        };
+        let momentum = |r: [f64; 2], v: [f64; 2]| r[0] * v[1] - r[1] * v[0];
+        let (r, v) = kepler_drift(r0, v0, MU, 3.7);
+        assert!((energy(r, v) - energy(r0, v0)).abs() < 1e-10 * energy(r0, v0).abs());
+        assert!((momentum(r, v) - momentum(r0, v0)).abs() < 1e-10 * momentum(r0, v0).abs());
+    }
+}
+
+// Sun and planet at the perihelion of an orbit of semi-major axis 1 and
+// eccentricity 0.5, about their centre of mass at rest at the origin
+fn sun_and_planet(mu: f64) -> Vec<Particle> {
+    let v = (3.0 * mu).sqrt();
+    let planet = 1.0 / (1.0 + PLANET_MASS);
+    let sun = planet - 1.0;
+    vec![
+        Particle::new([0.5 * sun, 0.0], [0.0, sun * v], 1.0),
+        Particle::new([0.5 * planet, 0.0], [0.0, planet * v], PLANET_MASS),
+    ]
+}
+
+#[test]
+fn jacobi_two_body_orbit_is_exact_at_any_time_step() {
+    // The gravitational constant is that of the solver
+    for constant in [GRAVIT_CONST, 1.0] {
+        let mu = constant * (1.0 + PLANET_MASS);
+        let particles = sun_and_planet(mu);
+        let solver = DirectSum::with_force(Gravity {
+            constant,
+            softening: Softening::None,
+        })
+        .unwrap();
+        let integrator = WisdomHolman::new(WhCoordinates::Jacobi, None);
+        // Steps of up to nearly half the orbital period
+        let mut simulation = Simulation::new(particles, 0.45, solver, integrator).unwrap();
+        for _ in 0..10 {
+            simulation.simulation_step();
+        }
+
+        let [s, p] = [simulation.particles[0], simulation.particles[1]];
+        let relative = (
+            [p.position[0] - s.position[0], p.position[1] - s.position[1]],
+            [p.velocity[0] - s.velocity[0], p.velocity[1] - s.velocity[1]],
+        );
+        let start = ([0.5, 0.0], [0.0, (3.0 * mu).sqrt()]);
+        assert_close(relative, kepler_drift(start.0, start.1, mu, 4.5), 1e-9);
+        let centre = [
+            s.mass * s.position[0] + p.mass * p.position[0],
+            s.mass * s.position[1] + p.mass * p.position[1],
+        ];
+        assert!(distance(centre, [0.0, 0.0]) < 1e-12);
+    }
+}
+
+#[test]
+fn solvers_without_newtonian_gravity_are_rejected() {
+    let integrator = WisdomHolman::new(WhCoordinates::DemocraticHeliocentric, None);
+    let coulomb = DirectSum::with_force(Coulomb::new(1.0)).unwrap();
+    let simulation = Simulation::new(sun_and_planet(MU), 0.01, coulomb, integrator);
+    assert!(matches!(simulation, Err(ConfigError::GravityRequired)));
+    let periodic = PeriodicDirectSum::new([-10.0, -10.0, 10.0, 10.0]).unwrap();
+    let simulation = Simulation::new(sun_and_planet(MU), 0.01, periodic, integrator);
+    assert!(matches!(simulation, Err(ConfigError::GravityRequired)));
+    let composed = Composition::new(integrator, vec![0.5, 0.5]).unwrap();
+    let coulomb = DirectSum::with_force(Coulomb::new(1.0)).unwrap();
+    let simulation = Simulation::new(sun_and_planet(MU), 0.01, coulomb, composed);
+    assert!(matches!(simulation, Err(ConfigError::GravityRequired)));
+}
+
+#[test]
+fn central_body_must_be_one_of_the_particles() {
+    for central in [Some(1), None] {
+        let integrator = WisdomHolman::new(WhCoordinates::Jacobi, central);
+        let solver = DirectSum::new(Softening::None).unwrap();
+        assert!(Simulation::new(sun_and_planet(MU), 0.01, solver, integrator).is_ok());
+    }
+    let integrator = WisdomHolman::new(WhCoordinates::Jacobi, Some(7));
+    let solver = DirectSum::new(Softening::None).unwrap();
+    let simulation = Simulation::new(sun_and_planet(MU), 0.01, solver, integrator);
+    assert!(matches!(
+        simulation,
+        Err(ConfigError::InvalidCentralBody(7, 2))
+    ));
+    let composed = Composition::new(integrator, vec![1.0]).unwrap();
+    let solver = DirectSum::new(Softening::None).unwrap();
+    let simulation = Simulation::new(sun_and_planet(MU), 0.01, solver, composed);
+    assert!(matches!(
+        simulation,
+        Err(ConfigError::InvalidCentralBody(7, 2))
+    ));
+}
+
+// Sun and two planets on circular orbits, the outer one held by springs to the
+// inner one and to the sun
+fn bonded_planets(integrator: impl Integrator + 'static, dt: f64) -> Simulation {
+    let circular = |r: f64| (GRAVIT_CONST / r).sqrt();
+    let particles = vec![
+        Particle::new([0.0, 0.0], [0.0, 0.0], 1.0),
+        Particle::new([1.0, 0.0], [0.0, circular(1.0)], PLANET_MASS),
+        Particle::new([0.0, 1.5], [-circular(1.5), 0.0], PLANET_MASS),
+    ];
+    let solver = DirectSum::new(Softening::None).unwrap();
+    let mut simulation = Simulation::new(particles, dt, solver, integrator).unwrap();
+    let spring = |i, j, rest_length| Bond::Harmonic {
+        i,
+        j,
+        stiffness: 0.01,
+        rest_length,
+        damping: 0.0,
+    };
+    simulation
+        .set_bonds(vec![spring(1, 2, 1.0), spring(0, 2, 1.2)])
+        .unwrap();
+    simulation
+}
+
+#[test]
+fn bonds_are_part_of_the_interactions() {
+    let mut reference = bonded_planets(RungeKutta4, 1e-4);
+    for _ in 0..10000 {
+        reference.simulation_step();
+    }
+    for coordinates in [WhCoordinates::Jacobi, WhCoordinates::DemocraticHeliocentric] {
+        let mut simulation = bonded_planets(WisdomHolman::new(coordinates, None), 1e-3);
+        for _ in 0..1000 {
+            simulation.simulation_step();
+        }
+        for (p, r) in simulation.particles.iter().zip(reference.particles.iter()) {
+            assert!(distance(p.position, r.position) < 1e-3, "{:?}", coordinates);
+            assert!(distance(p.velocity, r.velocity) < 1e-3, "{:?}", coordinates);
+        }
+    }
+}